@@ -0,0 +1,351 @@
+//! Call graph between the subs of a Megamix BTKS
+//!
+//! Every call target is a node, and every operation that runs or registers another piece of
+//! tickflow is an edge from the node it's part of. An operation is part of the closest node
+//! before it in the FLOW section.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use tickflow_binaries::{
+    data::{btks::BTKS, Pointer, RawTickflowOp},
+    extract::symbols::{json_string, SymbolMap},
+};
+
+use crate::data::megamix::MegamixOp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// Runs the target in a new thread
+    Async,
+    /// Runs the target and waits for it to return
+    Sync,
+    /// Registers the target as a function, to be called later by number
+    SetFunc,
+}
+
+impl CallKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Async => "async",
+            Self::Sync => "sync",
+            Self::SetFunc => "set_func",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// Offset of the node in the FLOW section
+    pub offset: u32,
+    pub scene: i32,
+    pub name: Option<String>,
+}
+
+impl Node {
+    /// Name of the node, or a generic one based on its offset if it doesn't have one
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(c) => c.clone(),
+            None => format!("loc_{:x}", self.offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: u32,
+    pub to: u32,
+    /// Offset of the call operation
+    pub at: u32,
+    pub kind: CallKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl CallGraph {
+    /// Builds the call graph of a BTKS. `symbols` names the nodes and sets their scenes, and `subs`
+    /// has the positions of the subs called by number, if known.
+    pub fn from_btks(
+        btks: &BTKS,
+        symbols: Option<&SymbolMap>,
+        subs: &HashMap<u32, u32>,
+    ) -> io::Result<Self> {
+        let mut graph = Self::from_ops(&btks.read_raw_ops::<MegamixOp>()?, subs);
+        graph.add_node(btks.flow.start_offset, -1);
+        if let Some(symbols) = symbols {
+            for symbol in &symbols.symbols {
+                graph.add_node(symbol.offset, symbol.scene);
+            }
+            graph.name_with(|c| symbols.by_offset(c.offset).and_then(|c| c.name.clone()));
+        }
+        graph.relink();
+        Ok(graph)
+    }
+
+    /// Builds the call graph of an operation stream, as returned by [`BTKS::read_raw_ops`]
+    pub fn from_ops(ops: &[(u32, RawTickflowOp)], subs: &HashMap<u32, u32>) -> Self {
+        let mut graph = Self::default();
+        if let Some((offset, op)) = ops.first() {
+            graph.add_node(*offset, op.scene);
+        }
+
+        let mut funcs: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut calls = vec![];
+        for (offset, op) in ops {
+            let target = |c: &Pointer| match c {
+                Pointer::Raw(c) => Some(*c),
+                Pointer::Label(_) => None,
+            };
+            match MegamixOp::try_from_raw(op) {
+                Some(MegamixOp::Call { loc, .. }) => {
+                    calls.push((*offset, target(&loc), CallKind::Async));
+                }
+                Some(MegamixOp::CallSync(loc)) => {
+                    calls.push((*offset, target(&loc), CallKind::Sync));
+                }
+                Some(MegamixOp::SetFunc { func, pos }) => {
+                    if let Some(pos) = target(&pos) {
+                        funcs.entry(func).or_default().push(pos);
+                    }
+                    calls.push((*offset, target(&pos), CallKind::SetFunc));
+                }
+                Some(MegamixOp::CallSub { sub, .. }) => {
+                    calls.push((*offset, subs.get(&sub).copied(), CallKind::Async));
+                }
+                Some(MegamixOp::CallSubSync(sub)) => {
+                    calls.push((*offset, subs.get(&sub).copied(), CallKind::Sync));
+                }
+                _ => {}
+            }
+        }
+        // functions are resolved statically, to every position they're ever set to
+        for (offset, op) in ops {
+            let (func, kind) = match MegamixOp::try_from_raw(op) {
+                Some(MegamixOp::CallFunc { func, .. }) => (func, CallKind::Async),
+                Some(MegamixOp::CallFuncSync(func)) => (func, CallKind::Sync),
+                _ => continue,
+            };
+            for pos in funcs.get(&func).into_iter().flatten() {
+                calls.push((*offset, Some(*pos), kind));
+            }
+        }
+        calls.sort_by_key(|c| c.0);
+
+        let scenes: HashMap<u32, i32> = ops.iter().map(|(c, op)| (*c, op.scene)).collect();
+        for (at, to, kind) in calls {
+            let Some(to) = to else {
+                continue;
+            };
+            graph.add_node(to, scenes.get(&to).copied().unwrap_or(-1));
+            graph.edges.push(Edge {
+                from: 0,
+                to,
+                at,
+                kind,
+            });
+        }
+        graph.relink();
+        graph
+    }
+
+    fn add_node(&mut self, offset: u32, scene: i32) {
+        match self.nodes.iter_mut().find(|c| c.offset == offset) {
+            Some(c) if scene != -1 => c.scene = scene,
+            Some(_) => {}
+            None => self.nodes.push(Node {
+                offset,
+                scene,
+                name: None,
+            }),
+        }
+    }
+
+    /// Sorts the nodes and sets the source of every edge to the node its call is part of
+    fn relink(&mut self) {
+        self.nodes.sort_by_key(|c| c.offset);
+        for edge in &mut self.edges {
+            let pos = self.nodes.partition_point(|c| c.offset <= edge.at);
+            edge.from = pos
+                .checked_sub(1)
+                .map(|c| self.nodes[c].offset)
+                .unwrap_or(0);
+        }
+    }
+
+    pub fn node(&self, offset: u32) -> Option<&Node> {
+        self.nodes.iter().find(|c| c.offset == offset)
+    }
+
+    /// Fills in the name of every node `namer` knows about
+    pub fn name_with(&mut self, namer: impl Fn(&Node) -> Option<String>) {
+        for node in &mut self.nodes {
+            if let Some(name) = namer(node) {
+                node.name = Some(name);
+            }
+        }
+    }
+
+    /// Writes the graph in Graphviz's DOT format
+    pub fn to_dot<F: Write>(&self, f: &mut F) -> io::Result<()> {
+        writeln!(f, "digraph tickflow {{")?;
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "  n{:x} [label={}];",
+                node.offset,
+                dot_string(&format!("{}\nscene {}", node.display_name(), node.scene))
+            )?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                f,
+                "  n{:x} -> n{:x} [label=\"{}\"{}];",
+                edge.from,
+                edge.to,
+                edge.kind.name(),
+                match edge.kind {
+                    CallKind::Async => "",
+                    CallKind::Sync => ", style=bold",
+                    CallKind::SetFunc => ", style=dashed",
+                }
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+
+    /// Writes the graph as a JSON object with a list of nodes and a list of edges
+    pub fn to_json_file<F: Write>(&self, f: &mut F) -> io::Result<()> {
+        writeln!(f, "{{")?;
+        writeln!(f, "  \"nodes\": [")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            write!(
+                f,
+                "    {{\"offset\": {}, \"scene\": {}, \"name\": {}}}",
+                node.offset,
+                node.scene,
+                match &node.name {
+                    Some(c) => json_string(c),
+                    None => "null".to_string(),
+                }
+            )?;
+            writeln!(f, "{}", if i + 1 == self.nodes.len() { "" } else { "," })?;
+        }
+        writeln!(f, "  ],")?;
+        writeln!(f, "  \"edges\": [")?;
+        for (i, edge) in self.edges.iter().enumerate() {
+            write!(
+                f,
+                "    {{\"from\": {}, \"to\": {}, \"at\": {}, \"kind\": \"{}\"}}",
+                edge.from,
+                edge.to,
+                edge.at,
+                edge.kind.name()
+            )?;
+            writeln!(f, "{}", if i + 1 == self.edges.len() { "" } else { "," })?;
+        }
+        writeln!(f, "  ]")?;
+        writeln!(f, "}}")
+    }
+}
+
+/// Quotes a string as a DOT ID. Line breaks are kept as centered line breaks in labels.
+fn dot_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> CallGraph {
+        CallGraph {
+            nodes: vec![
+                Node {
+                    offset: 0,
+                    scene: -1,
+                    name: Some("start".to_string()),
+                },
+                Node {
+                    offset: 0x20,
+                    scene: 3,
+                    name: Some("say \"hi\" \\ bye".to_string()),
+                },
+                Node {
+                    offset: 0x40,
+                    scene: 3,
+                    name: None,
+                },
+            ],
+            edges: vec![
+                Edge {
+                    from: 0,
+                    to: 0x20,
+                    at: 4,
+                    kind: CallKind::Sync,
+                },
+                Edge {
+                    from: 0x20,
+                    to: 0x40,
+                    at: 0x24,
+                    kind: CallKind::SetFunc,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn writes_dot() {
+        let mut out = vec![];
+        graph().to_dot(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"digraph tickflow {
+  n0 [label="start\nscene -1"];
+  n20 [label="say \"hi\" \\ bye\nscene 3"];
+  n40 [label="loc_40\nscene 3"];
+  n0 -> n20 [label="sync", style=bold];
+  n20 -> n40 [label="set_func", style=dashed];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut out = vec![];
+        graph().to_json_file(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{
+  "nodes": [
+    {"offset": 0, "scene": -1, "name": "start"},
+    {"offset": 32, "scene": 3, "name": "say \"hi\" \\ bye"},
+    {"offset": 64, "scene": 3, "name": null}
+  ],
+  "edges": [
+    {"from": 0, "to": 32, "at": 4, "kind": "sync"},
+    {"from": 32, "to": 64, "at": 36, "kind": "set_func"}
+  ]
+}
+"#
+        );
+    }
+}
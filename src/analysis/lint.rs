@@ -0,0 +1,332 @@
+//! Lint pass for Megamix tickflow
+//!
+//! Finds common mistakes in the operations of a BTKS, whether it was extracted from the game or
+//! compiled. Subs are delimited by the FLOW section's start and every call target, and each sub
+//! is checked on its own. Subs that are only called by number can't be told apart from the end
+//! of the previous sub, so they're reported as unreachable.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io,
+};
+
+use thiserror::Error;
+use tickflow_binaries::{
+    data::{btks::BTKS, OperationSet, RawTickflowOp},
+    extract::PointerType,
+};
+
+use crate::data::megamix::MegamixOp;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LintKind {
+    #[error("depth operation is never closed")]
+    UnclosedDepth,
+    #[error("undepth operation without a matching depth operation")]
+    UnmatchedUndepth,
+    #[error("unreachable code")]
+    Unreachable,
+    #[error("goto to undefined label {0}")]
+    UndefinedLabel(u32),
+    #[error("case outside of a switch")]
+    CaseOutsideSwitch,
+    #[error("argument {0} is a null string pointer")]
+    NullString(i8),
+    #[error("missing required arguments ({0} given)")]
+    MissingArgs(usize),
+    #[error("too many arguments ({0} given, at most {1} used)")]
+    ExtraArgs(usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Offset of the operation in the FLOW section
+    pub offset: u32,
+    pub kind: LintKind,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}: {}", self.offset, self.kind)
+    }
+}
+
+/// Lints every operation in a BTKS, returning the findings in FLOW order
+pub fn lint(btks: &BTKS) -> io::Result<Vec<Finding>> {
+    let ops = btks.read_raw_ops::<MegamixOp>()?;
+    let index = MegamixOp::op_index();
+
+    let pointers: HashMap<usize, PointerType> = btks
+        .ptro
+        .iter()
+        .flatten()
+        .map(|c| (c.at, c.ptype))
+        .collect();
+    let mut entries: HashSet<u32> = btks
+        .ptro
+        .iter()
+        .flatten()
+        .filter(|c| c.ptype == PointerType::Tickflow)
+        .map(|c| c.points_to)
+        .collect();
+    entries.insert(btks.flow.start_offset);
+    if let Some((offset, _)) = ops.first() {
+        entries.insert(*offset);
+    }
+
+    let mut findings = vec![];
+    let mut sub = SubState::default();
+    for (offset, op) in &ops {
+        let offset = *offset;
+        if entries.contains(&offset) {
+            sub.finish(&mut findings);
+        }
+
+        // argument checks
+        let missing_def_arg = [
+            index.calls.get(op, op.scene),
+            index.strings.get(op, op.scene),
+            index.signed.get(op, op.scene),
+            index.tempos.get(op, op.scene),
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|c| c.args.iter())
+        .any(|(arg, _)| *arg as usize >= op.args.len());
+        let decoded = MegamixOp::try_from_raw(op);
+        let max_args = decoded.as_ref().and_then(MegamixOp::max_args);
+        if missing_def_arg || decoded.is_none() {
+            findings.push(Finding {
+                offset,
+                kind: LintKind::MissingArgs(op.args.len()),
+            });
+        } else if let Some(max) = max_args.filter(|c| op.args.len() > *c) {
+            findings.push(Finding {
+                offset,
+                kind: LintKind::ExtraArgs(op.args.len(), max),
+            });
+        }
+        // extraction leaves null string pointers as they are, without relocating them
+        if let Some(c) = index.strings.get(op, op.scene) {
+            for (arg, _) in &c.args {
+                let at = offset as usize + 4 * (*arg as usize + 1);
+                if op.args.get(*arg as usize) == Some(&0)
+                    && pointers.get(&at) != Some(&PointerType::Data)
+                {
+                    findings.push(Finding {
+                        offset,
+                        kind: LintKind::NullString(*arg),
+                    });
+                }
+            }
+        }
+
+        let decoded = decoded.unwrap_or(MegamixOp::Other(op.clone()));
+        sub.check(offset, op, &decoded, &mut findings);
+    }
+    sub.finish(&mut findings);
+
+    findings.sort_by_key(|c| c.offset);
+    Ok(findings)
+}
+
+#[derive(Debug, Default)]
+struct SubState {
+    /// Open depth operations, and whether they're a switch
+    depth: Vec<(u32, bool)>,
+    dead: bool,
+    reported_dead: bool,
+    labels: HashSet<u32>,
+    gotos: Vec<(u32, u32)>,
+}
+
+impl SubState {
+    fn check(
+        &mut self,
+        offset: u32,
+        op: &RawTickflowOp,
+        decoded: &MegamixOp,
+        findings: &mut Vec<Finding>,
+    ) {
+        let index = MegamixOp::op_index();
+        let mut push = |kind| findings.push(Finding { offset, kind });
+
+        if let MegamixOp::Label(c) = decoded {
+            self.labels.insert(*c);
+            self.dead = false;
+            self.reported_dead = false;
+        }
+        if self.dead {
+            // only report the first operation of every unreachable block
+            if !self.reported_dead {
+                push(LintKind::Unreachable);
+                self.reported_dead = true;
+            }
+            return;
+        }
+
+        match decoded {
+            MegamixOp::Goto(c) => self.gotos.push((offset, *c)),
+            MegamixOp::Case(_) | MegamixOp::BreakCase | MegamixOp::DefaultCase
+                if !matches!(self.depth.last(), Some((_, true))) =>
+            {
+                push(LintKind::CaseOutsideSwitch)
+            }
+            _ => {}
+        }
+
+        if index.depth.get(op, op.scene).is_some() {
+            self.depth
+                .push((offset, matches!(decoded, MegamixOp::Switch)));
+        }
+        if index.undepth.get(op, op.scene).is_some() && self.depth.pop().is_none() {
+            push(LintKind::UnmatchedUndepth);
+        }
+        if index.returns.get(op, op.scene).is_some() && self.depth.is_empty() {
+            self.dead = true;
+        }
+    }
+
+    /// Reports everything that can only be checked at the end of a sub, then resets the state
+    fn finish(&mut self, findings: &mut Vec<Finding>) {
+        for (offset, _) in &self.depth {
+            findings.push(Finding {
+                offset: *offset,
+                kind: LintKind::UnclosedDepth,
+            });
+        }
+        for (offset, label) in &self.gotos {
+            if !self.labels.contains(label) {
+                findings.push(Finding {
+                    offset: *offset,
+                    kind: LintKind::UndefinedLabel(*label),
+                });
+            }
+        }
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::btks::{BtksType, FlowSection};
+
+    use super::*;
+
+    /// BTKS with the given operations as `(op, arg0, args)`, and no pointers
+    fn btks(ops: &[(u16, u32, &[u32])]) -> BTKS {
+        let mut data = vec![];
+        for (op, arg0, args) in ops {
+            let op_int = *op as u32 | (args.len() as u32) << 10 | arg0 << 14;
+            data.extend(op_int.to_le_bytes());
+            for arg in *args {
+                data.extend(arg.to_le_bytes());
+            }
+        }
+        BTKS {
+            btks_type: BtksType::MegamixIntl,
+            flow: FlowSection {
+                start_offset: 0,
+                data,
+            },
+            ptro: None,
+            tmpo: None,
+            strd: vec![],
+        }
+    }
+
+    fn kinds(btks: &BTKS) -> Vec<LintKind> {
+        lint(btks).unwrap().into_iter().map(|c| c.kind).collect()
+    }
+
+    #[test]
+    fn argument_counts() {
+        // rest with an argument, goto without a label
+        let btks = btks(&[(0xE, 1, &[5]), (0x15, 0, &[]), (7, 0, &[])]);
+        assert_eq!(
+            kinds(&btks),
+            [LintKind::ExtraArgs(1, 0), LintKind::MissingArgs(0)]
+        );
+    }
+
+    #[test]
+    fn null_strings() {
+        // a null string, and a string that isn't null but wasn't relocated
+        let btks = btks(&[(0x31, 0, &[0, 0]), (0x31, 0, &[0, 8]), (7, 0, &[])]);
+        let findings = lint(&btks).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].offset, 0);
+        assert_eq!(findings[0].kind, LintKind::NullString(1));
+    }
+
+    fn offsets(btks: &BTKS) -> Vec<(u32, LintKind)> {
+        lint(btks)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.offset, c.kind))
+            .collect()
+    }
+
+    #[test]
+    fn unclosed_depth() {
+        // the return inside the if doesn't end the sub
+        let btks = btks(&[(0x16, 0, &[1]), (7, 0, &[]), (0x19, 0, &[]), (7, 0, &[])]);
+        assert_eq!(
+            offsets(&btks),
+            [(0, LintKind::UnclosedDepth), (12, LintKind::UnclosedDepth)]
+        );
+    }
+
+    #[test]
+    fn unmatched_undepth() {
+        let btks = btks(&[(0x16, 0, &[1]), (0x18, 0, &[]), (0x1D, 0, &[]), (7, 0, &[])]);
+        assert_eq!(offsets(&btks), [(12, LintKind::UnmatchedUndepth)]);
+    }
+
+    #[test]
+    fn unreachable() {
+        // only the first operation after the return is reported, and a label ends the dead code
+        let btks = btks(&[
+            (7, 0, &[]),
+            (0xE, 0x30, &[]),
+            (0xE, 0x30, &[]),
+            (0x14, 0, &[1]),
+            (7, 0, &[]),
+        ]);
+        assert_eq!(offsets(&btks), [(4, LintKind::Unreachable)]);
+    }
+
+    #[test]
+    fn undefined_label() {
+        let btks = btks(&[
+            (0x15, 0, &[2]),
+            (0x15, 0, &[1]),
+            (0x14, 0, &[1]),
+            (7, 0, &[]),
+        ]);
+        assert_eq!(offsets(&btks), [(0, LintKind::UndefinedLabel(2))]);
+    }
+
+    #[test]
+    fn case_outside_switch() {
+        // a case directly inside a switch is fine, but not one inside an if inside it
+        let btks = btks(&[
+            (0x1A, 0, &[1]),
+            (0x19, 0, &[]),
+            (0x1A, 0, &[2]),
+            (0x16, 0, &[1]),
+            (0x1B, 0, &[]),
+            (0x18, 0, &[]),
+            (0x1D, 0, &[]),
+            (7, 0, &[]),
+        ]);
+        assert_eq!(
+            offsets(&btks),
+            [
+                (0, LintKind::CaseOutsideSwitch),
+                (28, LintKind::CaseOutsideSwitch)
+            ]
+        );
+    }
+}
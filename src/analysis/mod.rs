@@ -0,0 +1,5 @@
+//! Static analysis of extracted or compiled tickflow
+
+pub mod callgraph;
+pub mod lint;
+pub mod timeline;
@@ -0,0 +1,398 @@
+//! Cue timelines, listing when every call, scene change and input happens in a game
+//!
+//! Built from the events of an [`Interpreter`] run. Input operations haven't been documented
+//! yet, so the ones to look for are given by the caller.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Write},
+};
+
+use tickflow_binaries::{
+    data::{OperationSet, Pointer, RawTickflowOp, TickflowOpDef},
+    extract::symbols::{json_string, SymbolMap},
+};
+
+use crate::{
+    data::megamix::MegamixOp,
+    interpreter::{self, Event, Interpreter, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CueKind {
+    Call,
+    Scene,
+    Input,
+}
+
+impl CueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Call => "call",
+            Self::Scene => "scene",
+            Self::Input => "input",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cue {
+    pub tick: u32,
+    pub thread: usize,
+    /// Offset of the operation in the FLOW section
+    pub offset: u32,
+    pub kind: CueKind,
+    /// Call, scene or input operation
+    pub op: MegamixOp,
+    /// arg0 the operation was stored with, if known
+    pub arg0: Option<u32>,
+}
+
+/// Argument of a cue's operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CueArg {
+    Int(u32),
+    /// Pointer to a label that wasn't resolved
+    Label(String),
+}
+
+impl Display for CueArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(c) => write!(f, "{c}"),
+            Self::Label(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+impl Cue {
+    pub fn beat(&self) -> f64 {
+        self.tick as f64 / interpreter::TICKS_PER_BEAT as f64
+    }
+
+    /// Operation number, arg0 and arguments of the cue's operation, as they're stored. `None` if
+    /// it isn't a call, scene or undecoded operation.
+    pub fn raw_op(&self) -> Option<(u16, Option<u32>, Vec<CueArg>)> {
+        let int = |c: &u32| CueArg::Int(*c);
+        let pointer = |c: &Pointer| match c {
+            Pointer::Raw(c) => CueArg::Int(*c),
+            Pointer::Label(c) => CueArg::Label(c.clone()),
+        };
+        let optional = |c: &Option<u32>| c.as_ref().map(int);
+        let (op, args) = match &self.op {
+            MegamixOp::CallSub { sub, time, cat } => (
+                0,
+                [Some(int(sub)), optional(time), optional(cat)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            ),
+            MegamixOp::CallFunc { func, time } => (
+                1,
+                [Some(int(func)), optional(time)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            ),
+            MegamixOp::Call { loc, time } => (
+                2,
+                [Some(pointer(loc)), optional(time)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            ),
+            MegamixOp::CallSubSync(c) => (4, vec![int(c)]),
+            MegamixOp::CallFuncSync(c) => (5, vec![int(c)]),
+            MegamixOp::CallSync(c) => (6, vec![pointer(c)]),
+            MegamixOp::Scene(c) => (0x28, vec![int(c)]),
+            MegamixOp::Other(c) => {
+                return Some((c.op, Some(c.arg0), c.args.iter().map(int).collect()))
+            }
+            _ => return None,
+        };
+        Some((op, self.arg0, args))
+    }
+
+    /// Name of the cue's operation, or its number in hex if it doesn't have one. If arg0 isn't
+    /// known, it's looked up as 0. `None` if it isn't a call, scene or undecoded operation.
+    pub fn op_name(&self) -> Option<String> {
+        let (op, arg0, _) = self.raw_op()?;
+        let raw = RawTickflowOp {
+            op,
+            arg0: arg0.unwrap_or(0),
+            args: vec![],
+            scene: -1,
+        };
+        Some(match MegamixOp::alias_for_op(&raw, -1) {
+            Some((name, _)) => name.to_string(),
+            None => format!("{op:#x}"),
+        })
+    }
+
+    /// Name, arg0 and arguments of the cue's operation, for the writers
+    fn written_op(&self) -> io::Result<(String, Option<u32>, Vec<CueArg>)> {
+        match (self.op_name(), self.raw_op()) {
+            (Some(name), Some((_, arg0, args))) => Ok((name, arg0, args)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} isn't a cue operation", self.op),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub name: String,
+    pub cues: Vec<Cue>,
+}
+
+impl Timeline {
+    /// Picks the cues out of the events of an interpreter run. Undecoded operations that match
+    /// one of `input_ops` are input cues.
+    pub fn from_events(
+        name: impl Into<String>,
+        events: &[Event],
+        input_ops: &[TickflowOpDef],
+    ) -> Self {
+        let cues = events
+            .iter()
+            .filter_map(|c| {
+                let kind = match &c.op {
+                    MegamixOp::CallSub { .. }
+                    | MegamixOp::CallFunc { .. }
+                    | MegamixOp::Call { .. }
+                    | MegamixOp::CallSubSync(_)
+                    | MegamixOp::CallFuncSync(_)
+                    | MegamixOp::CallSync(_) => CueKind::Call,
+                    MegamixOp::Scene(_) => CueKind::Scene,
+                    MegamixOp::Other(op) if input_ops.contains(&op.as_definition()) => {
+                        CueKind::Input
+                    }
+                    _ => return None,
+                };
+                Some(Cue {
+                    tick: c.tick,
+                    thread: c.thread,
+                    offset: c.offset,
+                    kind,
+                    op: c.op.clone(),
+                    arg0: c.arg0,
+                })
+            })
+            .collect();
+        Self {
+            name: name.into(),
+            cues,
+        }
+    }
+
+    /// Runs `interpreter` from `start` and builds the timeline of the run
+    pub fn from_interpreter(
+        name: impl Into<String>,
+        interpreter: &mut Interpreter,
+        start: u32,
+        input_ops: &[TickflowOpDef],
+    ) -> interpreter::Result<Self> {
+        Ok(Self::from_events(name, &interpreter.run(start)?, input_ops))
+    }
+
+    /// Builds the timeline of every game in `games` (as in [`MegamixLocations::games`]) that was
+    /// extracted into `program`, each run with the given [`Interpreter::subs`] and
+    /// [`Interpreter::max_ticks`]. Games that weren't extracted are left out, and a game that
+    /// fails to run doesn't stop the others.
+    ///
+    /// [`MegamixLocations::games`]: crate::extract::megamix::MegamixLocations::games
+    pub fn from_games<'a>(
+        program: &Program,
+        symbols: &SymbolMap,
+        games: &[(&'a str, u32)],
+        subs: &HashMap<u32, u32>,
+        max_ticks: u32,
+        input_ops: &[TickflowOpDef],
+    ) -> Vec<(&'a str, interpreter::Result<Self>)> {
+        let mut interpreter = Interpreter::new(program);
+        interpreter.subs = subs.clone();
+        interpreter.max_ticks = max_ticks;
+        games
+            .iter()
+            .filter_map(|(name, address)| Some((*name, symbols.by_address(*address)?.offset)))
+            .map(|(name, start)| {
+                let timeline = Self::from_interpreter(name, &mut interpreter, start, input_ops);
+                (name, timeline)
+            })
+            .collect()
+    }
+}
+
+/// Writes every timeline as a JSON array of objects, each with the name and the list of cues.
+/// arg0 is `null` if it isn't known.
+pub fn to_json_file<F: Write>(f: &mut F, timelines: &[Timeline]) -> io::Result<()> {
+    writeln!(f, "[")?;
+    for (i, timeline) in timelines.iter().enumerate() {
+        writeln!(f, "  {{")?;
+        writeln!(f, "    \"name\": {},", json_string(&timeline.name))?;
+        writeln!(f, "    \"cues\": [")?;
+        for (j, cue) in timeline.cues.iter().enumerate() {
+            let (name, arg0, args) = cue.written_op()?;
+            let args: Vec<_> = args
+                .iter()
+                .map(|c| match c {
+                    CueArg::Int(c) => c.to_string(),
+                    CueArg::Label(c) => json_string(c),
+                })
+                .collect();
+            write!(
+                f,
+                "      {{\"beat\": {}, \"tick\": {}, \"thread\": {}, \"offset\": {}, \"kind\": \"{}\", \"op\": {}, \"arg0\": {}, \"args\": [{}]}}",
+                cue.beat(),
+                cue.tick,
+                cue.thread,
+                cue.offset,
+                cue.kind.name(),
+                json_string(&name),
+                arg0.map_or("null".to_string(), |c| c.to_string()),
+                args.join(", ")
+            )?;
+            writeln!(
+                f,
+                "{}",
+                if j + 1 == timeline.cues.len() {
+                    ""
+                } else {
+                    ","
+                }
+            )?;
+        }
+        writeln!(f, "    ]")?;
+        write!(f, "  }}")?;
+        writeln!(f, "{}", if i + 1 == timelines.len() { "" } else { "," })?;
+    }
+    writeln!(f, "]")
+}
+
+/// Writes every cue of every timeline as a row of a CSV file, with a header row. The arguments
+/// of every operation are in a single field, separated by spaces, and arg0 is left empty if it
+/// isn't known.
+pub fn to_csv_file<F: Write>(f: &mut F, timelines: &[Timeline]) -> io::Result<()> {
+    writeln!(f, "name,beat,tick,thread,offset,kind,op,arg0,args")?;
+    for timeline in timelines {
+        for cue in &timeline.cues {
+            let (name, arg0, args) = cue.written_op()?;
+            let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+            writeln!(
+                f,
+                "{},{},{},{},{:#x},{},{},{},{}",
+                csv_field(&timeline.name),
+                cue.beat(),
+                cue.tick,
+                cue.thread,
+                cue.offset,
+                cue.kind.name(),
+                csv_field(&name),
+                arg0.map(|c| c.to_string()).unwrap_or_default(),
+                csv_field(&args.join(" "))
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::btks::{BtksType, FlowSection, BTKS};
+
+    use super::*;
+
+    fn cue(kind: CueKind, op: MegamixOp, arg0: Option<u32>) -> Cue {
+        Cue {
+            tick: 0x30,
+            thread: 0,
+            offset: 0x10,
+            kind,
+            op,
+            arg0,
+        }
+    }
+
+    #[test]
+    fn writes_op_names_and_args() {
+        let timelines = [Timeline {
+            name: "game".to_string(),
+            cues: vec![
+                cue(
+                    CueKind::Call,
+                    MegamixOp::Call {
+                        loc: Pointer::Label("intro".to_string()),
+                        time: Some(2),
+                    },
+                    Some(0),
+                ),
+                cue(CueKind::Call, MegamixOp::CallSubSync(0x56), Some(3)),
+                cue(CueKind::Scene, MegamixOp::Scene(1), None),
+            ],
+        }];
+        let mut csv = vec![];
+        to_csv_file(&mut csv, &timelines).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "name,beat,tick,thread,offset,kind,op,arg0,args\n\
+             game,1,48,0,0x10,call,async_call,0,intro 2\n\
+             game,1,48,0,0x10,call,0x4,3,86\n\
+             game,1,48,0,0x10,scene,engine,,1\n"
+        );
+        let mut json = vec![];
+        to_json_file(&mut json, &timelines).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"op\": \"async_call\", \"arg0\": 0, \"args\": [\"intro\", 2]"));
+        assert!(json.contains("\"op\": \"engine\", \"arg0\": null, \"args\": [1]"));
+    }
+
+    #[test]
+    fn only_writes_cue_operations() {
+        let cue = cue(CueKind::Call, MegamixOp::Return, None);
+        assert!(cue.raw_op().is_none());
+        let timelines = [Timeline {
+            name: "game".to_string(),
+            cues: vec![cue],
+        }];
+        assert!(to_csv_file(&mut vec![], &timelines).is_err());
+    }
+
+    #[test]
+    fn keeps_arg0_from_the_btks() {
+        // async_sub 0x56 with an arg0 of 2, then return
+        let mut data = vec![];
+        for c in [1 << 10 | 2 << 14, 0x56, 7] {
+            data.extend(u32::to_le_bytes(c));
+        }
+        let btks = BTKS {
+            btks_type: BtksType::MegamixIntl,
+            flow: FlowSection {
+                start_offset: 0,
+                data,
+            },
+            ptro: None,
+            tmpo: None,
+            strd: vec![],
+        };
+        let program = Program::from_btks(&btks).unwrap();
+        let timeline =
+            Timeline::from_interpreter("game", &mut Interpreter::new(&program), 0, &[]).unwrap();
+        assert_eq!(timeline.cues.len(), 1);
+        assert_eq!(
+            timeline.cues[0].raw_op(),
+            Some((0, Some(2), vec![CueArg::Int(0x56)]))
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use bytestream::ByteOrder;
-use tickflow_binaries::data::{btks::BtksType, ArgsTickflowOpDef, OperationSet, RawTickflowOp, TickflowOpDef};
+use tickflow_binaries::data::{
+    btks::BtksType, ArgsTickflowOpDef, ArrayTickflowOpDef, OperationSet, RawTickflowOp,
+    TickflowOpDef,
+};
 
-use crate::{args_tf_op, args_tf_op_vec, tf_op_vec};
+use crate::{args_tf_op, args_tf_op_vec, array_tf_op_vec, tf_op_vec};
 
 pub enum FeverUsOp {
     Other(RawTickflowOp),
@@ -12,6 +15,8 @@ impl OperationSet for FeverUsOp {
 
     const ENDIAN: ByteOrder = ByteOrder::BigEndian;
 
+    tickflow_binaries::cached_op_index!();
+
     fn get_operation(op: RawTickflowOp) -> Self
     where
         Self: Sized,
@@ -39,8 +44,10 @@ impl OperationSet for FeverUsOp {
         ]
     }
 
-    fn get_array_operations() -> Vec<ArgsTickflowOpDef> {
-        todo!()
+    fn get_array_operations() -> Vec<ArrayTickflowOpDef> {
+        //TODO: none of Fever's operations are known to take an array yet. Until they're documented,
+        // array pointers are extracted as plain integers and the data they point to isn't copied.
+        array_tf_op_vec![]
     }
 
     fn get_depth_operations() -> Vec<TickflowOpDef> {
@@ -3,7 +3,8 @@ use bytestream::ByteOrder;
 use crate::args_tf_op;
 
 use tickflow_binaries::data::{
-    btks::BtksType, ArgsTickflowOpDef, OperationSet, RawTickflowOp, TickflowOpDef,
+    btks::BtksType, ArgsTickflowOpDef, ArrayTickflowOpDef, OperationSet, RawTickflowOp,
+    TickflowOpDef,
 };
 
 pub enum GoldOp {
@@ -14,6 +15,8 @@ impl OperationSet for GoldOp {
     const BTKS_TICKFLOW_TYPE: BtksType = BtksType::Gold;
     const ENDIAN: ByteOrder = ByteOrder::LittleEndian;
 
+    tickflow_binaries::cached_op_index!();
+
     fn get_operation(op: RawTickflowOp) -> Self
     where
         Self: Sized,
@@ -34,7 +37,7 @@ impl OperationSet for GoldOp {
         todo!();
     }
 
-    fn get_array_operations() -> Vec<ArgsTickflowOpDef> {
+    fn get_array_operations() -> Vec<ArrayTickflowOpDef> {
         todo!();
     }
 
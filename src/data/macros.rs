@@ -47,27 +47,30 @@ macro_rules! tf_op {
     };
     // for pattern matching
     // probably useless once the proc macros are done
+    // ops decoded from a file always have an arg0 and a scene, so anything unspecified is a wildcard
     (~$cmdname:literal <=$arg0:pat=> $(, $scene:literal)? $(,)?) => {
         $crate::data::TickflowOpDef {
             op: $cmdname,
             arg0: Some($arg0),
-            scene: $($scene + 1)? - 1,
+            scene: $crate::tf_op!(@scene $($scene)?),
         }
     };
     (~$cmdname:literal $(, $scene:literal)? $(,)?) => {
         $crate::data::TickflowOpDef {
             op: $cmdname,
-            arg0: None,
-            scene: $($scene + 1)? - 1,
+            arg0: _,
+            scene: $crate::tf_op!(@scene $($scene)?),
         }
     };
     (~$cmdname:literal <$arg0:literal> $(, $scene:literal)? $(,)?) => {
         $crate::data::TickflowOpDef {
             op: $cmdname,
             arg0: Some($arg0),
-            scene: $($scene + 1)? - 1,
+            scene: $crate::tf_op!(@scene $($scene)?),
         }
     };
+    (@scene) => { _ };
+    (@scene $scene:literal) => { $scene };
 }
 
 #[macro_export]
@@ -109,4 +112,54 @@ macro_rules! args_tf_op_vec {
         ),*]
     };
 
-}
\ No newline at end of file
+}
+#[macro_export]
+macro_rules! array_tf_op {
+    ($cmdname:literal $(<$arg0:literal>)?, [$(($argnum:literal, $atype:ident, $len:expr)),* $(,)?] $(, $scene:literal)? $(,)?) => {
+        {
+        #[allow(unused_mut, unused_assignments)]
+        let mut arg0 = None;
+        $(arg0 = Some($arg0);)?
+
+        #[allow(unused_mut, unused_assignments)]
+        let mut scene = -1;
+        $(scene = $scene;)?
+
+        #[allow(unused_imports)]
+        use $crate::data::ArrayLength::*;
+
+        $crate::data::ArrayTickflowOpDef {
+            op: $cmdname,
+            arg0,
+            args: vec![$(($argnum, $crate::data::ArrayType::$atype, $len)),*],
+            scene,
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! array_tf_op_vec {
+    [
+        $(
+            $cmdname:literal
+            $(<$arg0:literal>)?,
+            [
+                $(($argnum:literal, $atype:ident, $len:expr)),*
+                $(,)?
+            ]
+            $(, $scene:literal)?
+        );*
+
+        $(;)?
+    ] => {
+        vec![$(
+            $crate::array_tf_op!(
+                $cmdname
+                $(<$arg0>)?,
+                [ $(($argnum, $atype, $len)),* ]
+                $(, $scene)?
+            )
+        ),*]
+    };
+}
@@ -1,12 +1,13 @@
 use bytestream::ByteOrder;
 
-use crate::{args_tf_op, args_tf_op_vec, tf_op, tf_op_vec};
+use crate::{args_tf_op, args_tf_op_vec, array_tf_op_vec, tf_op, tf_op_vec};
 
 use tickflow_binaries::data::{
-    btks::BtksType, ArgsTickflowOpDef, OperationSet, Pointer, RawTickflowOp, TickflowOpDef,
+    btks::BtksType, ArgsTickflowOpDef, ArrayTickflowOpDef, OperationSet, Pointer, RawTickflowOp, TickflowOpDef,
 };
 
 //TODO: derive macro that creates specifications automatically
+#[derive(Debug, Clone)]
 pub enum MegamixOp {
     //#[tickflow_op(0)]
     CallSub {
@@ -124,73 +125,174 @@ pub enum MegamixOp {
     Other(RawTickflowOp),
 }
 
-impl OperationSet for MegamixOp {
-    const BTKS_TICKFLOW_TYPE: BtksType = BtksType::MegamixIntl;
-    const ENDIAN: ByteOrder = ByteOrder::LittleEndian;
+impl MegamixOp {
+    /// Most arguments this operation takes, or `None` if it isn't decoded yet
+    pub fn max_args(&self) -> Option<usize> {
+        Some(match self {
+            Self::KillAll
+            | Self::Return
+            | Self::Stop
+            | Self::PushCondvar
+            | Self::PopCondvar
+            | Self::Rest(_)
+            | Self::Sleep(_)
+            | Self::RestReset
+            | Self::Unrest(_)
+            | Self::Else
+            | Self::EndIf
+            | Self::Switch
+            | Self::BreakCase
+            | Self::DefaultCase
+            | Self::EndSwitch
+            | Self::SetCountdownCondvar
+            | Self::GetCountdownInit
+            | Self::GetCountdownProgress
+            | Self::GetCountdown
+            | Self::DecCountdown => 0,
+            Self::KillCat(_)
+            | Self::KillLoc(_)
+            | Self::KillSub(_)
+            | Self::CallSubSync(_)
+            | Self::CallFuncSync(_)
+            | Self::CallSync(_)
+            | Self::Cat(_)
+            | Self::SetCondvar(_)
+            | Self::AddCondvar(_)
+            | Self::GetRest(_)
+            | Self::Label(_)
+            | Self::Goto(_)
+            | Self::IfEq(_)
+            | Self::IfNe(_)
+            | Self::IfLt(_)
+            | Self::IfLe(_)
+            | Self::IfGt(_)
+            | Self::IfGe(_)
+            | Self::Case(_)
+            | Self::SetCountdown(_)
+            | Self::Tempo(_)
+            | Self::TempoID(_)
+            | Self::Speed(_)
+            | Self::Scene(_) => 1,
+            Self::CallFunc { .. }
+            | Self::SetFunc { .. }
+            | Self::Call { .. }
+            | Self::SetRest { .. } => 2,
+            Self::CallSub { .. } | Self::TempoRel { .. } | Self::SpeedRel { .. } => 3,
+            _ => return None,
+        })
+    }
 
-    //TODO: MissingRequiredArgument errors
+    /// Decodes a raw operation, or returns `None` if it's missing a required argument
     //TODO: finish this
-    fn get_operation(op: RawTickflowOp) -> Self {
-        match op.as_definition() {
+    pub fn try_from_raw(op: &RawTickflowOp) -> Option<Self> {
+        Some(match op.as_definition() {
             tf_op!(~0) => Self::CallSub {
-                sub: *op.args.first().expect("Missing required argument"),
+                sub: *op.args.first()?,
                 time: op.args.get(1).copied(),
                 cat: op.args.get(2).copied(),
             },
             tf_op!(~1<0>) => Self::CallFunc {
-                func: *op.args.first().expect("Missing required argument"),
+                func: *op.args.first()?,
                 time: op.args.get(1).copied(),
             },
             tf_op!(~1<1>) => Self::SetFunc {
-                func: *op.args.first().expect("Missing required argument"),
-                pos: (*op.args.get(1).expect("Missing required argument")).into(),
+                func: *op.args.first()?,
+                pos: (*op.args.get(1)?).into(),
             },
             tf_op!(~2) => Self::Call {
-                loc: (*op.args.first().expect("Missing required argument")).into(),
+                loc: (*op.args.first()?).into(),
                 time: op.args.get(1).copied(),
             },
 
             tf_op!(~3<0>) => Self::KillAll,
-            tf_op!(~3<1>) => Self::KillCat(*op.args.first().expect("Missing required argument")),
-            tf_op!(~3<2>) => Self::KillSub(*op.args.first().expect("Missing required argument")),
-            tf_op!(~3<3>) => {
-                Self::KillLoc((*op.args.first().expect("Missing required argument")).into())
-            }
+            tf_op!(~3<1>) => Self::KillCat(*op.args.first()?),
+            tf_op!(~3<2>) => Self::KillLoc((*op.args.first()?).into()),
+            tf_op!(~3<3>) => Self::KillSub(*op.args.first()?),
 
-            tf_op!(~4) => Self::CallSubSync(*op.args.first().expect("Missing required argument")),
-            tf_op!(~5) => Self::CallFuncSync(*op.args.first().expect("Missing required argument")),
-            tf_op!(~6) => Self::Call {
-                loc: (*op.args.first().expect("Missing required argument")).into(),
-                time: op.args.get(1).copied(),
-            },
+            tf_op!(~4) => Self::CallSubSync(*op.args.first()?),
+            tf_op!(~5) => Self::CallFuncSync(*op.args.first()?),
+            tf_op!(~6) => Self::CallSync((*op.args.first()?).into()),
 
             tf_op!(~7) => Self::Return,
             tf_op!(~8) => Self::Stop,
 
-            tf_op!(~9) => Self::Cat(*op.args.first().expect("Missing required argument")),
+            tf_op!(~9) => Self::Cat(*op.args.first()?),
 
-            tf_op!(~0xa) => {
-                Self::SetCondvar(*op.args.first().expect("Missing required argument") as i32)
-            }
-            tf_op!(~0xb) => {
-                Self::AddCondvar(*op.args.first().expect("Missing required argument") as i32)
-            }
+            tf_op!(~0xa) => Self::SetCondvar(*op.args.first()? as i32),
+            tf_op!(~0xb) => Self::AddCondvar(*op.args.first()? as i32),
             tf_op!(~0xc) => Self::PushCondvar,
             tf_op!(~0xd) => Self::PopCondvar,
 
             tf_op!(~0xe<=arg0=>) => Self::Rest(arg0),
             tf_op!(~0xf<0>) => Self::SetRest {
-                slot: *op.args.first().expect("Missing required argument"),
-                amount: *op.args.get(1).expect("Missing required argument"),
+                slot: *op.args.first()?,
+                amount: *op.args.get(1)?,
             },
-            tf_op!(~0xf<1>) => Self::GetRest(*op.args.first().expect("Missing required argument")),
+            tf_op!(~0xf<1>) => Self::GetRest(*op.args.first()?),
             tf_op!(~0x10<=arg0=>) => Self::Sleep(arg0),
             tf_op!(~0x11) => Self::RestReset,
             //TODO: check if this one is truly arg0
             tf_op!(~0x12<=arg0=>) => Self::Unrest(arg0),
 
-            _ => Self::Other(op),
-        }
+            tf_op!(~0x14) => Self::Label(*op.args.first()?),
+            tf_op!(~0x15) => Self::Goto(*op.args.first()?),
+            tf_op!(~0x16<=arg0=>) => {
+                let value = *op.args.first()? as i32;
+                match arg0 {
+                    0 => Self::IfEq(value),
+                    1 => Self::IfNe(value),
+                    2 => Self::IfLt(value),
+                    3 => Self::IfLe(value),
+                    4 => Self::IfGt(value),
+                    5 => Self::IfGe(value),
+                    _ => Self::Other(op.clone()),
+                }
+            }
+            tf_op!(~0x17) => Self::Else,
+            tf_op!(~0x18) => Self::EndIf,
+            tf_op!(~0x19) => Self::Switch,
+            tf_op!(~0x1A) => Self::Case(*op.args.first()? as i32),
+            tf_op!(~0x1B) => Self::BreakCase,
+            tf_op!(~0x1C) => Self::DefaultCase,
+            tf_op!(~0x1D) => Self::EndSwitch,
+
+            tf_op!(~0x1E) => Self::SetCountdown(*op.args.first()? as i32),
+            tf_op!(~0x1F) => Self::SetCountdownCondvar,
+            tf_op!(~0x20) => Self::GetCountdownInit,
+            tf_op!(~0x21) => Self::GetCountdownProgress,
+            tf_op!(~0x22) => Self::GetCountdown,
+            tf_op!(~0x23) => Self::DecCountdown,
+
+            //TODO: check these against more games
+            tf_op!(~0x24<0>) => Self::Tempo(*op.args.first()?),
+            tf_op!(~0x24<1>) => Self::TempoRel {
+                factor: *op.args.first()?,
+                lower: *op.args.get(1)?,
+                upper: *op.args.get(2)?,
+            },
+            tf_op!(~0x24<2>) => Self::TempoID(*op.args.first()?),
+            tf_op!(~0x25<0>) => Self::Speed(*op.args.first()?),
+            tf_op!(~0x25<1>) => Self::SpeedRel {
+                factor: *op.args.first()?,
+                lower: *op.args.get(1)?,
+                upper: *op.args.get(2)?,
+            },
+
+            tf_op!(~0x28<0>) => Self::Scene(*op.args.first()?),
+
+            _ => Self::Other(op.clone()),
+        })
+    }
+}
+
+impl OperationSet for MegamixOp {
+    const BTKS_TICKFLOW_TYPE: BtksType = BtksType::MegamixIntl;
+    const ENDIAN: ByteOrder = ByteOrder::LittleEndian;
+
+    tickflow_binaries::cached_op_index!();
+
+    fn get_operation(op: RawTickflowOp) -> Self {
+        Self::try_from_raw(&op).expect("Missing required argument")
     }
 
     fn get_call_operations() -> Vec<ArgsTickflowOpDef> {
@@ -238,8 +340,25 @@ impl OperationSet for MegamixOp {
             0x10A, [(0)], 0x39;
         ]
     }
-    fn get_array_operations() -> Vec<ArgsTickflowOpDef> {
-        todo!();
+    fn get_array_operations() -> Vec<ArrayTickflowOpDef> {
+        //TODO: none of Megamix's operations are known to take an array yet. Until they're
+        // documented, array pointers are extracted as plain integers and the data they point to
+        // isn't copied.
+        array_tf_op_vec![]
+    }
+    fn get_signed_operations() -> Vec<ArgsTickflowOpDef> {
+        args_tf_op_vec![
+            0xA, [(0)];
+            0xB, [(0)];
+            0x16, [(0)];
+            0x1A, [(0)];
+            0x1E, [(0)];
+        ]
+    }
+    fn get_tempo_operations() -> Vec<ArgsTickflowOpDef> {
+        args_tf_op_vec![
+            0x24<2>, [(0)];
+        ]
     }
     fn get_depth_operations() -> Vec<TickflowOpDef> {
         tf_op_vec![
@@ -261,4 +380,171 @@ impl OperationSet for MegamixOp {
     fn get_return_operations() -> Vec<TickflowOpDef> {
         tf_op_vec![0x7, 0x8]
     }
+    fn get_aliases() -> Vec<(&'static str, TickflowOpDef)> {
+        vec![
+            ("async_sub", tf_op!(0<0>)),
+            ("get_async", tf_op!(1<0>)),
+            ("set_func", tf_op!(1<1>)),
+            ("async_call", tf_op!(2<0>)),
+            ("kill_all", tf_op!(3<0>)),
+            ("kill_cat", tf_op!(3<1>)),
+            ("kill_loc", tf_op!(3<2>)),
+            ("kill_sub", tf_op!(3<3>)),
+            ("sub", tf_op!(4<0>)),
+            ("get_sync", tf_op!(5<0>)),
+            ("call", tf_op!(6<0>)),
+            ("return", tf_op!(7<0>)),
+            ("stop", tf_op!(8<0>)),
+            ("set_cat", tf_op!(9<0>)),
+            ("set_condvar", tf_op!(0xA<0>)),
+            ("add_condvar", tf_op!(0xB<0>)),
+            ("push_condvar", tf_op!(0xC<0>)),
+            ("pop_condvar", tf_op!(0xD<0>)),
+            ("rest", tf_op!(0xE)),
+            ("setrest", tf_op!(0xF<0>)),
+            ("getrest", tf_op!(0xF<1>)),
+            ("rest_reset", tf_op!(0x11<0>)),
+            ("unrest", tf_op!(0x12)),
+            ("label", tf_op!(0x14<0>)),
+            ("goto", tf_op!(0x15<0>)),
+            ("if", tf_op!(0x16<0>)),
+            ("if_neq", tf_op!(0x16<1>)),
+            ("if_lt", tf_op!(0x16<2>)),
+            ("if_leq", tf_op!(0x16<3>)),
+            ("if_gt", tf_op!(0x16<4>)),
+            ("if_geq", tf_op!(0x16<5>)),
+            ("else", tf_op!(0x17<0>)),
+            ("endif", tf_op!(0x18<0>)),
+            ("switch", tf_op!(0x19<0>)),
+            ("case", tf_op!(0x1A<0>)),
+            ("break", tf_op!(0x1B<0>)),
+            ("default", tf_op!(0x1C<0>)),
+            ("endswitch", tf_op!(0x1D<0>)),
+            ("set_countdown", tf_op!(0x1E<0>)),
+            ("set_countdown_condvar", tf_op!(0x1F<0>)),
+            ("get_countdown_init", tf_op!(0x20<0>)),
+            ("get_countdown_prog", tf_op!(0x21<0>)),
+            ("get_countdown", tf_op!(0x22<0>)),
+            ("dec_countdown", tf_op!(0x23<0>)),
+            //TODO: check these against Tickompiler. `input` and the rest of the engine-specific
+            // operations are missing because their numbers haven't been documented, which is also
+            // why timelines take their input operations from the caller.
+            ("sleep", tf_op!(0x10)),
+            ("tempo", tf_op!(0x24<0>)),
+            ("tempo_rel", tf_op!(0x24<1>)),
+            ("tempo_id", tf_op!(0x24<2>)),
+            ("speed", tf_op!(0x25<0>)),
+            ("speed_rel", tf_op!(0x25<1>)),
+            ("engine", tf_op!(0x28<0>)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::{
+        data::{
+            btks::{FlowSection, BTKS},
+            Arg,
+        },
+        extract::{self, PointerType},
+    };
+
+    use super::*;
+
+    /// Every argument that's a location pointer to tickflow is decoded as one
+    #[test]
+    fn call_operations_decode_to_pointers() {
+        for def in MegamixOp::get_call_operations() {
+            let op = RawTickflowOp {
+                op: def.op,
+                arg0: def.arg0.unwrap_or(0),
+                args: vec![0x100; 3],
+                scene: -1,
+            };
+            let pointer = match MegamixOp::try_from_raw(&op).unwrap() {
+                MegamixOp::SetFunc { pos: c, .. }
+                | MegamixOp::Call { loc: c, .. }
+                | MegamixOp::KillLoc(c)
+                | MegamixOp::CallSync(c) => c,
+                c => panic!("{:#x}<{}> decoded as {c:?}", def.op, op.arg0),
+            };
+            assert!(matches!(pointer, Pointer::Raw(0x100)));
+        }
+    }
+
+    #[test]
+    fn aliases_match_decoding() {
+        let decode = |name| {
+            let def = MegamixOp::alias_by_name(name).unwrap();
+            MegamixOp::try_from_raw(&RawTickflowOp {
+                op: def.op,
+                arg0: def.arg0.unwrap_or(0),
+                args: vec![1],
+                scene: -1,
+            })
+        };
+        assert!(matches!(decode("kill_loc"), Some(MegamixOp::KillLoc(_))));
+        assert!(matches!(decode("kill_sub"), Some(MegamixOp::KillSub(1))));
+        assert!(matches!(decode("kill_cat"), Some(MegamixOp::KillCat(1))));
+        assert!(matches!(decode("call"), Some(MegamixOp::CallSync(_))));
+        assert!(matches!(decode("async_call"), Some(MegamixOp::Call { .. })));
+    }
+
+    /// BTKS with the given operations as `(op, arg0, args)`, data pointers at `data`, and STRD
+    fn btks(ops: &[(u16, u32, &[u32])], data: &[usize], strd: Vec<u8>) -> BTKS {
+        let mut flow = vec![];
+        for (op, arg0, args) in ops {
+            let op_int = *op as u32 | (args.len() as u32) << 10 | arg0 << 14;
+            flow.extend(op_int.to_le_bytes());
+            for arg in *args {
+                flow.extend(arg.to_le_bytes());
+            }
+        }
+        BTKS {
+            btks_type: BtksType::MegamixIntl,
+            flow: FlowSection {
+                start_offset: 0,
+                data: flow,
+            },
+            ptro: Some(
+                data.iter()
+                    .map(|c| extract::Pointer {
+                        at: *c,
+                        points_to: 0,
+                        ptype: PointerType::Data,
+                    })
+                    .collect(),
+            ),
+            tmpo: None,
+            strd,
+        }
+    }
+
+    fn string_args(btks: &BTKS) -> Vec<Arg> {
+        btks.to_tickflow_ops::<MegamixOp>()
+            .unwrap()
+            .into_iter()
+            .filter_map(|(_, op)| op.args.get(1).cloned())
+            .collect()
+    }
+
+    #[test]
+    fn null_strings_stay_integers() {
+        let btks = btks(
+            &[(0x31, 0, &[0, 0]), (0x31, 0, &[0, 0])],
+            &[8],
+            vec![b'h', 0, b'i', 0, 0, 0, 0, 0],
+        );
+        let args = string_args(&btks);
+        assert!(matches!(args[0], Arg::String(ref c) if c.value == "hi"));
+        assert!(matches!(args[1], Arg::Unknown(0)));
+    }
+
+    #[test]
+    fn unrelocated_strings_stay_integers() {
+        // STRD is empty, so reading the string would fail
+        let btks = btks(&[(0x31, 0, &[0, 8])], &[], vec![]);
+        assert!(matches!(string_args(&btks)[..], [Arg::Unknown(8)]));
+    }
 }
@@ -1,3 +1,5 @@
+use tickflow_binaries::extract::{symbols::SymbolMap, tempo::TempoTable};
+
 pub const CODE_OFFSET: u32 = 0x100000;
 
 pub enum Region {
@@ -7,7 +9,7 @@ pub enum Region {
     KR,
 }
 
-pub fn extract_tickflow_from_code(region: Region) {
+pub fn extract_tickflow_from_code(_region: Region) {
     todo!();
 }
 
@@ -143,6 +145,26 @@ pub struct MegamixLocations {
     pub gate_practices: NamedLocations,
     pub subs: SceneLocations,
     pub misc: NamedLocations,
+    pub tempos: Option<TempoTable>,
+}
+
+impl MegamixLocations {
+    /// Returns the name of the game, gate or sub located at `address`, if known
+    pub fn name_of(&self, address: u32) -> Option<&'static str> {
+        self.games
+            .iter()
+            .chain(self.gates.iter())
+            .chain(self.gate_practices.iter())
+            .chain(self.subs.iter().flat_map(|(_, c)| c.iter()))
+            .chain(self.misc.iter())
+            .find(|(_, pos)| *pos == address)
+            .map(|(name, _)| *name)
+    }
+
+    /// Names every symbol in the map that matches one of these locations
+    pub fn name_symbols(&self, symbols: &mut SymbolMap) {
+        symbols.name_with(|c| self.name_of(c.address).map(ToString::to_string))
+    }
 }
 
 pub const LOCATIONS_US: MegamixLocations = MegamixLocations {
@@ -365,4 +387,6 @@ pub const LOCATIONS_US: MegamixLocations = MegamixLocations {
         ]
     )],
     misc: &[],
+    //TODO: the tempo table hasn't been found in the US version, so no tempos are extracted
+    tempos: None,
 };
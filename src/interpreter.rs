@@ -0,0 +1,728 @@
+//! Deterministic interpreter for Megamix tickflow
+//!
+//! Simulates the threads spawned by a sub and outputs a timeline of every operation ran, with
+//! the tick it ran on. This doesn't emulate anything outside of tickflow itself (inputs, scene
+//! state, etc.), so operations that depend on the game are only recorded. Tempo operations are
+//! the exception, their effects are kept in [`Interpreter::tempo_changes`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+};
+
+use thiserror::Error;
+use tickflow_binaries::data::{btks::BTKS, Pointer};
+
+use crate::{data::megamix::MegamixOp, tempo};
+
+/// Amount of ticks in a beat, the unit used by rest operations
+pub const TICKS_PER_BEAT: u32 = 0x30;
+
+#[derive(Debug, Error)]
+pub enum InterpreterError {
+    #[error("no operation at offset {0:#x}")]
+    InvalidOffset(u32),
+    #[error("pointer to label \"{0}\" can't be followed")]
+    UnresolvedLabel(String),
+    #[error("goto to undefined label {1} at offset {0:#x}")]
+    UndefinedLabel(u32, u32),
+    #[error("pop_condvar with an empty condvar stack at offset {0:#x}")]
+    EmptyCondvarStack(u32),
+    #[error("simulation went over the limit of {0} steps")]
+    StepLimit(usize),
+    #[error("tick count overflowed at offset {0:#x}")]
+    TickOverflow(u32),
+}
+
+pub type Result<T> = std::result::Result<T, InterpreterError>;
+
+/// Decoded tickflow, addressable by FLOW offset
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub ops: Vec<(u32, MegamixOp)>,
+    positions: HashMap<u32, usize>,
+    /// arg0 of every operation, by offset, if they were read from a BTKS
+    arg0s: HashMap<u32, u32>,
+    /// Offsets pointed to by operations, which are where subs start
+    entries: BTreeSet<u32>,
+}
+
+impl Program {
+    pub fn new(ops: Vec<(u32, MegamixOp)>) -> Self {
+        let positions = ops
+            .iter()
+            .enumerate()
+            .map(|(i, (offset, _))| (*offset, i))
+            .collect();
+        let entries = ops
+            .iter()
+            .filter_map(|(_, op)| match op {
+                MegamixOp::Call { loc: c, .. }
+                | MegamixOp::CallSync(c)
+                | MegamixOp::SetFunc { pos: c, .. }
+                | MegamixOp::KillLoc(c) => match c {
+                    Pointer::Raw(c) => Some(*c),
+                    Pointer::Label(_) => None,
+                },
+                _ => None,
+            })
+            .collect();
+        Self {
+            ops,
+            positions,
+            entries,
+            arg0s: HashMap::new(),
+        }
+    }
+
+    pub fn from_btks(btks: &BTKS) -> io::Result<Self> {
+        let ops = btks.read_raw_ops::<MegamixOp>()?;
+        let arg0s = ops.iter().map(|(offset, op)| (*offset, op.arg0)).collect();
+        Ok(Self {
+            arg0s,
+            ..Self::new(
+                ops.into_iter()
+                    .map(|(offset, op)| {
+                        let decoded = MegamixOp::try_from_raw(&op);
+                        (offset, decoded.unwrap_or(MegamixOp::Other(op)))
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// arg0 the operation at `offset` was stored with, which decoding doesn't always keep. Only
+    /// known if the program was read from a BTKS.
+    pub fn arg0(&self, offset: u32) -> Option<u32> {
+        self.arg0s.get(&offset).copied()
+    }
+
+    pub fn position_of(&self, offset: u32) -> Result<usize> {
+        self.positions
+            .get(&offset)
+            .copied()
+            .ok_or(InterpreterError::InvalidOffset(offset))
+    }
+}
+
+/// An operation ran by the interpreter
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub tick: u32,
+    pub thread: usize,
+    pub offset: u32,
+    pub op: MegamixOp,
+    /// arg0 the operation was stored with, if known (see [`Program::arg0`])
+    pub arg0: Option<u32>,
+}
+
+/// Base tempo set by tickflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseTempo {
+    /// Argument of `Tempo`, whose unit isn't known
+    Raw(u32),
+    /// ID of a tempo from the TMPO section, set by `TempoID`
+    Id(u32),
+}
+
+/// Tempo of the song, which is shared by every thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoState {
+    /// Last base tempo set, if any
+    pub base: Option<BaseTempo>,
+    /// Speed multiplier, see [`tempo::speed_after`]
+    pub speed: f64,
+}
+
+impl Default for TempoState {
+    fn default() -> Self {
+        Self {
+            base: None,
+            speed: 1.0,
+        }
+    }
+}
+
+impl TempoState {
+    /// State after running `op`, if `op` changes it
+    pub fn after(&self, op: &MegamixOp) -> Option<Self> {
+        let base = match op {
+            MegamixOp::Tempo(c) => Some(BaseTempo::Raw(*c)),
+            MegamixOp::TempoID(c) => Some(BaseTempo::Id(*c)),
+            _ => None,
+        };
+        let speed = tempo::speed_after(self.speed, op);
+        (base.is_some() || speed.is_some()).then(|| Self {
+            base: base.or(self.base),
+            speed: speed.unwrap_or(self.speed),
+        })
+    }
+}
+
+impl Event {
+    pub fn beat(&self) -> f64 {
+        self.tick as f64 / TICKS_PER_BEAT as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Thread {
+    id: usize,
+    /// Position of the first operation, used by kill_loc
+    entry: u32,
+    /// Position of the first operation of the sub being run, which changes with synchronous calls
+    frame: u32,
+    /// Sub number this thread was started from, used by kill_sub
+    sub: Option<u32>,
+    pc: usize,
+    wake: u32,
+    alive: bool,
+    cat: u32,
+    condvar: i32,
+    condvar_stack: Vec<i32>,
+    /// Position to return to and frame of every synchronous call
+    call_stack: Vec<(usize, u32)>,
+    rests: HashMap<u32, u32>,
+    countdown: i32,
+    countdown_init: i32,
+}
+
+/// Timeline simulator for a [`Program`]
+#[derive(Debug, Clone)]
+pub struct Interpreter<'a> {
+    program: &'a Program,
+    /// Positions of the subs called by number (`CallSub`, `KillSub`...), if known
+    pub subs: HashMap<u32, u32>,
+    /// The simulation stops at the first thread that wakes up after this tick
+    pub max_ticks: u32,
+    /// The simulation fails after running this many operations, to stop infinite loops
+    pub max_steps: usize,
+    funcs: HashMap<u32, u32>,
+    threads: Vec<Thread>,
+    tempo_changes: Vec<(u32, TempoState)>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            subs: HashMap::new(),
+            max_ticks: u32::MAX,
+            max_steps: 1_000_000,
+            funcs: HashMap::new(),
+            threads: vec![],
+            tempo_changes: vec![],
+        }
+    }
+
+    /// Tick of every tempo change in the last run, along with the tempo from then on
+    pub fn tempo_changes(&self) -> &[(u32, TempoState)] {
+        &self.tempo_changes
+    }
+
+    /// Runs the program starting from the operation at `start`, until every thread ends
+    pub fn run(&mut self, start: u32) -> Result<Vec<Event>> {
+        self.threads.clear();
+        self.funcs.clear();
+        self.tempo_changes.clear();
+        let mut events = vec![];
+        self.spawn(start, None, 0, 0, 0)?;
+
+        let program = self.program;
+        let mut steps = 0;
+        // pick the thread that wakes up first, earliest spawned first on a tie
+        while let Some(current) = self
+            .threads
+            .iter()
+            .filter(|c| c.alive)
+            .min_by_key(|c| (c.wake, c.id))
+            .map(|c| c.id)
+        {
+            let now = self.threads[current].wake;
+            if now > self.max_ticks {
+                break;
+            }
+            loop {
+                steps += 1;
+                if steps > self.max_steps {
+                    Err(InterpreterError::StepLimit(self.max_steps))?
+                }
+                let Some((offset, op)) = program.ops.get(self.threads[current].pc) else {
+                    self.threads[current].alive = false;
+                    break;
+                };
+                events.push(Event {
+                    tick: now,
+                    thread: current,
+                    offset: *offset,
+                    op: op.clone(),
+                    arg0: program.arg0(*offset),
+                });
+                self.threads[current].pc += 1;
+                let yielded = self.step(current, now, *offset, op)?;
+                if yielded || !self.threads[current].alive {
+                    break;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn spawn(
+        &mut self,
+        pos: u32,
+        sub: Option<u32>,
+        wake: u32,
+        cat: u32,
+        condvar: i32,
+    ) -> Result<()> {
+        let pc = self.program.position_of(pos)?;
+        let id = self.threads.len();
+        self.threads.push(Thread {
+            id,
+            entry: pos,
+            frame: pos,
+            sub,
+            pc,
+            wake,
+            alive: true,
+            cat,
+            condvar,
+            condvar_stack: vec![],
+            call_stack: vec![],
+            rests: HashMap::new(),
+            countdown: 0,
+            countdown_init: 0,
+        });
+        Ok(())
+    }
+
+    fn resolve(pointer: &Pointer) -> Result<u32> {
+        match pointer {
+            Pointer::Raw(c) => Ok(*c),
+            Pointer::Label(c) => Err(InterpreterError::UnresolvedLabel(c.clone())),
+        }
+    }
+
+    fn jump(&mut self, thread: usize, pos: u32) -> Result<()> {
+        let pc = self.program.position_of(pos)?;
+        let thread = &mut self.threads[thread];
+        thread.call_stack.push((thread.pc, thread.frame));
+        thread.pc = pc;
+        thread.frame = pos;
+        Ok(())
+    }
+
+    /// Tick `amount` ticks after `now`
+    fn later(now: u32, amount: u32, offset: u32) -> Result<u32> {
+        now.checked_add(amount)
+            .ok_or(InterpreterError::TickOverflow(offset))
+    }
+
+    /// Position range of the sub that starts at `frame`, which ends where the next one starts
+    fn sub_bounds(&self, frame: u32) -> Result<std::ops::Range<usize>> {
+        let start = self.program.position_of(frame)?;
+        let next = self
+            .program
+            .entries
+            .iter()
+            .chain(self.subs.values())
+            .filter(|c| **c > frame)
+            .min();
+        let end = match next {
+            Some(c) => self.program.position_of(*c)?,
+            None => self.program.ops.len(),
+        };
+        Ok(start..end)
+    }
+
+    /// Runs one operation, returns whether the thread has to wait
+    fn step(&mut self, current: usize, now: u32, offset: u32, op: &MegamixOp) -> Result<bool> {
+        let (cat, condvar) = (self.threads[current].cat, self.threads[current].condvar);
+        match op {
+            MegamixOp::CallSub { sub, time, cat } => {
+                if let Some(pos) = self.subs.get(sub).copied() {
+                    self.spawn(
+                        pos,
+                        Some(*sub),
+                        Self::later(now, time.unwrap_or(0), offset)?,
+                        cat.unwrap_or(0),
+                        condvar,
+                    )?;
+                }
+            }
+            MegamixOp::CallFunc { func, time } => {
+                if let Some(pos) = self.funcs.get(func).copied() {
+                    let wake = Self::later(now, time.unwrap_or(0), offset)?;
+                    self.spawn(pos, None, wake, cat, condvar)?;
+                }
+            }
+            MegamixOp::SetFunc { func, pos } => {
+                self.funcs.insert(*func, Self::resolve(pos)?);
+            }
+            MegamixOp::Call { loc, time } => {
+                self.spawn(
+                    Self::resolve(loc)?,
+                    None,
+                    Self::later(now, time.unwrap_or(0), offset)?,
+                    cat,
+                    condvar,
+                )?;
+            }
+            MegamixOp::KillAll => self.kill(current, |_| true),
+            MegamixOp::KillCat(c) => self.kill(current, |t| t.cat == *c),
+            MegamixOp::KillSub(c) => self.kill(current, |t| t.sub == Some(*c)),
+            MegamixOp::KillLoc(c) => {
+                let pos = Self::resolve(c)?;
+                self.kill(current, |t| t.entry == pos)
+            }
+            MegamixOp::CallSubSync(c) => {
+                if let Some(pos) = self.subs.get(c).copied() {
+                    self.jump(current, pos)?;
+                }
+            }
+            MegamixOp::CallFuncSync(c) => {
+                if let Some(pos) = self.funcs.get(c).copied() {
+                    self.jump(current, pos)?;
+                }
+            }
+            MegamixOp::CallSync(c) => self.jump(current, Self::resolve(c)?)?,
+            MegamixOp::Return => {
+                let thread = &mut self.threads[current];
+                match thread.call_stack.pop() {
+                    Some((pc, frame)) => (thread.pc, thread.frame) = (pc, frame),
+                    None => thread.alive = false,
+                }
+            }
+            MegamixOp::Stop => self.threads[current].alive = false,
+            MegamixOp::Cat(c) => self.threads[current].cat = *c,
+
+            MegamixOp::SetCondvar(c) => self.threads[current].condvar = *c,
+            MegamixOp::AddCondvar(c) => {
+                self.threads[current].condvar = condvar.wrapping_add(*c);
+            }
+            MegamixOp::PushCondvar => self.threads[current].condvar_stack.push(condvar),
+            MegamixOp::PopCondvar => {
+                let thread = &mut self.threads[current];
+                thread.condvar = thread
+                    .condvar_stack
+                    .pop()
+                    .ok_or(InterpreterError::EmptyCondvarStack(offset))?;
+            }
+
+            MegamixOp::Rest(c) | MegamixOp::Sleep(c) => {
+                self.threads[current].wake = Self::later(now, *c, offset)?;
+                return Ok(true);
+            }
+            MegamixOp::SetRest { slot, amount } => {
+                self.threads[current].rests.insert(*slot, *amount);
+            }
+            MegamixOp::GetRest(c) => {
+                let amount = self.threads[current].rests.get(c).copied().unwrap_or(0);
+                self.threads[current].wake = Self::later(now, amount, offset)?;
+                return Ok(true);
+            }
+            MegamixOp::RestReset => self.threads[current].rests.clear(),
+
+            MegamixOp::Goto(c) => {
+                // labels are local to the sub being run
+                let pc = self
+                    .sub_bounds(self.threads[current].frame)?
+                    .find(|i| matches!(self.program.ops[*i].1, MegamixOp::Label(l) if l == *c))
+                    .ok_or(InterpreterError::UndefinedLabel(offset, *c))?;
+                self.threads[current].pc = pc + 1;
+            }
+            MegamixOp::IfEq(c) => self.branch(current, condvar == *c),
+            MegamixOp::IfNe(c) => self.branch(current, condvar != *c),
+            MegamixOp::IfLt(c) => self.branch(current, condvar < *c),
+            MegamixOp::IfLe(c) => self.branch(current, condvar <= *c),
+            MegamixOp::IfGt(c) => self.branch(current, condvar > *c),
+            MegamixOp::IfGe(c) => self.branch(current, condvar >= *c),
+            // reached the end of a taken branch
+            MegamixOp::Else => {
+                let pc = self.skip_block(self.threads[current].pc, false);
+                self.threads[current].pc = pc;
+            }
+            MegamixOp::Switch => {
+                let pc = self.find_case(self.threads[current].pc, condvar);
+                self.threads[current].pc = pc;
+            }
+            MegamixOp::BreakCase => {
+                let pc = self.skip_switch(self.threads[current].pc);
+                self.threads[current].pc = pc;
+            }
+
+            MegamixOp::SetCountdown(c) => {
+                let thread = &mut self.threads[current];
+                (thread.countdown, thread.countdown_init) = (*c, *c);
+            }
+            MegamixOp::SetCountdownCondvar => {
+                let thread = &mut self.threads[current];
+                (thread.countdown, thread.countdown_init) = (condvar, condvar);
+            }
+            MegamixOp::GetCountdownInit => {
+                let thread = &mut self.threads[current];
+                thread.condvar = thread.countdown_init;
+            }
+            MegamixOp::GetCountdownProgress => {
+                let thread = &mut self.threads[current];
+                thread.condvar = thread.countdown_init.wrapping_sub(thread.countdown);
+            }
+            MegamixOp::GetCountdown => {
+                let thread = &mut self.threads[current];
+                thread.condvar = thread.countdown;
+            }
+            MegamixOp::DecCountdown => {
+                let thread = &mut self.threads[current];
+                thread.countdown = thread.countdown.wrapping_sub(1);
+            }
+
+            MegamixOp::Tempo(_)
+            | MegamixOp::TempoRel { .. }
+            | MegamixOp::TempoID(_)
+            | MegamixOp::Speed(_)
+            | MegamixOp::SpeedRel { .. } => {
+                let tempo = self.tempo_changes.last().map(|c| c.1).unwrap_or_default();
+                if let Some(c) = tempo.after(op) {
+                    // only the last change on a tick matters
+                    if self.tempo_changes.last().is_some_and(|c| c.0 == now) {
+                        self.tempo_changes.pop();
+                    }
+                    self.tempo_changes.push((now, c));
+                }
+            }
+
+            // everything else only matters to the game, and is just recorded in the timeline
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Kills every thread that matches `filter`, except for the one doing the killing
+    fn kill(&mut self, current: usize, filter: impl Fn(&Thread) -> bool) {
+        for thread in &mut self.threads {
+            if thread.id != current && filter(thread) {
+                thread.alive = false;
+            }
+        }
+    }
+
+    fn branch(&mut self, current: usize, condition: bool) {
+        if !condition {
+            let pc = self.skip_block(self.threads[current].pc, true);
+            self.threads[current].pc = pc;
+        }
+    }
+
+    /// Finds the position right after the end of the current block. If `stop_at_else` is set,
+    /// an `Else` at the same depth also ends the block.
+    fn skip_block(&self, mut pc: usize, stop_at_else: bool) -> usize {
+        let mut depth = 0;
+        while let Some((_, op)) = self.program.ops.get(pc) {
+            pc += 1;
+            match op {
+                MegamixOp::IfEq(_)
+                | MegamixOp::IfNe(_)
+                | MegamixOp::IfLt(_)
+                | MegamixOp::IfLe(_)
+                | MegamixOp::IfGt(_)
+                | MegamixOp::IfGe(_)
+                | MegamixOp::Switch => depth += 1,
+                MegamixOp::EndIf | MegamixOp::EndSwitch if depth == 0 => break,
+                MegamixOp::EndIf | MegamixOp::EndSwitch => depth -= 1,
+                MegamixOp::Else if depth == 0 && stop_at_else => break,
+                _ => {}
+            }
+        }
+        pc
+    }
+
+    /// Finds the position right after the `EndSwitch` of the current switch
+    fn skip_switch(&self, mut pc: usize) -> usize {
+        let mut depth = 0;
+        while let Some((_, op)) = self.program.ops.get(pc) {
+            pc += 1;
+            match op {
+                MegamixOp::Switch => depth += 1,
+                MegamixOp::EndSwitch if depth == 0 => break,
+                MegamixOp::EndSwitch => depth -= 1,
+                _ => {}
+            }
+        }
+        pc
+    }
+
+    /// Finds the position right after the case of this switch that matches `condvar`
+    fn find_case(&self, mut pc: usize, condvar: i32) -> usize {
+        let mut depth = 0;
+        let mut default = None;
+        while let Some((_, op)) = self.program.ops.get(pc) {
+            pc += 1;
+            match op {
+                MegamixOp::IfEq(_)
+                | MegamixOp::IfNe(_)
+                | MegamixOp::IfLt(_)
+                | MegamixOp::IfLe(_)
+                | MegamixOp::IfGt(_)
+                | MegamixOp::IfGe(_)
+                | MegamixOp::Switch => depth += 1,
+                MegamixOp::EndIf | MegamixOp::EndSwitch if depth > 0 => depth -= 1,
+                MegamixOp::EndSwitch => return default.unwrap_or(pc),
+                MegamixOp::Case(c) if depth == 0 && *c == condvar => return pc,
+                MegamixOp::DefaultCase if depth == 0 => default = default.or(Some(pc)),
+                _ => {}
+            }
+        }
+        default.unwrap_or(pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MegamixOp::*;
+
+    /// Program with every operation 4 bytes after the previous one
+    fn program(ops: Vec<MegamixOp>) -> Program {
+        Program::new(
+            ops.into_iter()
+                .enumerate()
+                .map(|(i, op)| (i as u32 * 4, op))
+                .collect(),
+        )
+    }
+
+    fn run(program: &Program) -> Result<Vec<Event>> {
+        Interpreter::new(program).run(0)
+    }
+
+    /// Tick and arg0 of every `Rest` ran
+    fn rests(events: &[Event]) -> Vec<(u32, u32)> {
+        events
+            .iter()
+            .filter_map(|c| match c.op {
+                Rest(c2) => Some((c.tick, c2)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn goto_stays_in_the_called_sub() {
+        let program = program(vec![
+            // 0x0
+            Label(1),
+            CallSync(Pointer::Raw(0x10)),
+            Rest(1),
+            Stop,
+            // 0x10, loops twice
+            SetCondvar(0),
+            Label(1),
+            AddCondvar(1),
+            Rest(2),
+            IfLt(2),
+            Goto(1),
+            EndIf,
+            Return,
+        ]);
+        let events = run(&program).unwrap();
+        assert_eq!(rests(&events), vec![(0, 2), (2, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn goto_to_a_label_in_another_sub() {
+        let program = program(vec![
+            CallSync(Pointer::Raw(0xC)),
+            Label(1),
+            Stop,
+            // 0xC
+            Goto(1),
+            Return,
+        ]);
+        assert!(matches!(
+            run(&program),
+            Err(InterpreterError::UndefinedLabel(0xC, 1))
+        ));
+    }
+
+    #[test]
+    fn switch() {
+        for (condvar, expected) in [(1, vec![(0, 1)]), (2, vec![(0, 2)]), (3, vec![(0, 3)])] {
+            let program = program(vec![
+                SetCondvar(condvar),
+                Switch,
+                Case(1),
+                Rest(1),
+                // nested blocks don't end the case
+                IfEq(0),
+                EndIf,
+                Switch,
+                EndSwitch,
+                BreakCase,
+                Case(2),
+                Rest(2),
+                BreakCase,
+                DefaultCase,
+                Rest(3),
+                BreakCase,
+                EndSwitch,
+                Stop,
+            ]);
+            assert_eq!(rests(&run(&program).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn async_calls() {
+        let program = program(vec![
+            Call {
+                loc: Pointer::Raw(0x10),
+                time: Some(0x30),
+            },
+            Rest(0x10),
+            Rest(0x40),
+            Stop,
+            // 0x10
+            Rest(1),
+            Stop,
+        ]);
+        let events = run(&program).unwrap();
+        assert_eq!(rests(&events), vec![(0, 0x10), (0x10, 0x40), (0x30, 1)]);
+        assert_eq!(events.iter().filter(|c| c.thread == 1).count(), 2);
+    }
+
+    #[test]
+    fn tick_overflow() {
+        let program = program(vec![Rest(1), Rest(u32::MAX)]);
+        assert!(matches!(
+            run(&program),
+            Err(InterpreterError::TickOverflow(4))
+        ));
+    }
+
+    #[test]
+    fn tempo_changes() {
+        let program = program(vec![
+            TempoID(5),
+            Rest(0x30),
+            // twice as fast, then half of that on the same tick
+            Speed(0x200),
+            SpeedRel {
+                factor: 0x80,
+                lower: 0,
+                upper: 0,
+            },
+            Stop,
+        ]);
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.run(0).unwrap();
+        let base = Some(BaseTempo::Id(5));
+        assert_eq!(
+            interpreter.tempo_changes(),
+            [
+                (0, TempoState { base, speed: 1.0 }),
+                (0x30, TempoState { base, speed: 1.0 })
+            ]
+        );
+    }
+}
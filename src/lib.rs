@@ -1,2 +1,7 @@
+pub mod analysis;
 pub mod data;
 pub mod extract;
+pub mod interpreter;
+pub mod midi;
+pub mod tempo;
+pub mod tickscript;
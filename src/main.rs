@@ -1,13 +1,20 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::{Result, Write},
 };
 use tickflow::{
-    data::{fever::FeverUsOp, gold::GoldOp, megamix::MegamixOp, OperationSet},
+    analysis::callgraph::CallGraph,
+    data::{fever::FeverUsOp, megamix::MegamixOp, Arg, OperationSet, Pointer},
     extract::{
-        self, dol::DolFile, fever::CODE_OFFSET as OFFSET_RHF, gold::TICKOVY_OFFSET_US, megamix::CODE_OFFSET as OFFSET_RHM
+        self, dol::DolFile, fever::CODE_OFFSET as OFFSET_RHF, megamix::CODE_OFFSET as OFFSET_RHM
     },
 };
+use tickflow_parse::old::{
+    cst::SyntaxFile,
+    format::{format, FormatOptions},
+    Identifier, Statement, Value,
+};
 
 const MEGAMIX_GAME: usize = 0;
 
@@ -15,37 +22,62 @@ const MEGAMIX_POS: u32 = extract::megamix::LOCATIONS_US.games[MEGAMIX_GAME].1;
 const MEGAMIX_NAME: &str = extract::megamix::LOCATIONS_US.games[MEGAMIX_GAME].0;
 
 fn main() -> Result<()> {
+    // `tickflow fmt <files>` formats old Tickflow files in place
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("fmt") {
+        return format_files(&args[1..]);
+    }
+
     let mut f = File::open("test_files/code.bin")?;
-    let btks = extract::extract::<MegamixOp>(&mut f, OFFSET_RHM, &[MEGAMIX_POS])?;
+    let (btks, mut symbols) = extract::extract::<MegamixOp>(
+        &mut f,
+        OFFSET_RHM,
+        &[MEGAMIX_POS],
+        extract::megamix::LOCATIONS_US.tempos.as_ref(),
+    )?;
     let mut fw = File::create(format!("test_files/{MEGAMIX_NAME}.btk", ))?;
 
     btks.to_btks_file(&mut fw, MegamixOp::ENDIAN)?;
 
+    extract::megamix::LOCATIONS_US.name_symbols(&mut symbols);
+    let mut fw = File::create(format!("test_files/{MEGAMIX_NAME}.sym.json"))?;
+    symbols.to_json_file(&mut fw)?;
+
+    let graph = CallGraph::from_btks(&btks, Some(&symbols), &HashMap::new())?;
+    let mut fw = File::create(format!("test_files/{MEGAMIX_NAME}.calls.dot"))?;
+    graph.to_dot(&mut fw)?;
+
     //let mut f = File::open("test_files/ovy9_90.bin")?;
     //extract::extract::<GoldOp>(&mut f, TICKOVY_OFFSET_US, &[])?;
 
     let mut f = DolFile::new(File::open("test_files/main.dol")?, FeverUsOp::ENDIAN)?;
     let mut fw = File::create("test_files/characterIntro.btk")?;
-    let btks = extract::extract::<FeverUsOp>(&mut f, OFFSET_RHF, &[0x802B5D40])?;
+    let (btks, _) = extract::extract::<FeverUsOp>(&mut f, OFFSET_RHF, &[0x802B5D40], None)?;
 
     let mut fw2 = File::create("test_files/characterIntro.btk.out")?;
 
     writeln!(fw2, "{:#08x?}", btks.ptro)?;
 
-    for op in btks.to_raw_tickflow_ops(FeverUsOp::ENDIAN)? {
-        let op = tickflow_parse::old::Statement::Command {
-            cmd: tickflow_parse::old::CommandName::Raw(op.op as i32),
-            arg0: {
-                let temp = match op.arg0 {
-                tickflow::data::Arg0::Signed(c) => c,
-                tickflow::data::Arg0::Unsigned(c) => c as i32,
-                tickflow::data::Arg0::Unknown(c) => c as i32,
-            }; if temp == 0 {
-                None
-            } else {Some(tickflow_parse::old::Value::Integer(temp))}
-            },
-            args: op.args.iter().map(|c| tickflow_parse::old::Value::Integer(match c { tickflow::data::Arg::Unknown(c) => *c as i32, _ => panic!()})).collect(),
-        };
+    let ops = btks.to_tickflow_ops::<FeverUsOp>()?;
+    let labels: Vec<u32> = ops
+        .iter()
+        .flat_map(|(_, op)| op.args.iter())
+        .filter_map(|c| match c {
+            Arg::Pointer(Pointer::Raw(c)) => Some(*c),
+            _ => None,
+        })
+        .collect();
+
+    for (offset, op) in ops {
+        if labels.contains(&offset) {
+            writeln!(fw2, "{}", Statement::Label(label_name(offset)))?;
+        }
+        let op = Statement::command::<FeverUsOp>(
+            op.op,
+            u32::from(op.arg0),
+            op.scene,
+            op.args.into_iter().map(arg_to_value).collect(),
+        );
         writeln!(fw2, "{op}")?;
     }
 
@@ -53,3 +85,48 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn format_files(paths: &[String]) -> Result<()> {
+    let mut failed = false;
+    for path in paths {
+        let text = std::fs::read_to_string(path)?;
+        let (file, errors) = SyntaxFile::parse(path, &text);
+        // files with errors are left alone, since the formatter can't make sense of them
+        if !errors.is_empty() {
+            for error in errors {
+                eprintln!("{}", error.render(&text));
+            }
+            failed = true;
+            continue;
+        }
+        let formatted = format(&file, &FormatOptions::default());
+        if formatted != text {
+            std::fs::write(path, formatted)?;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn label_name(offset: u32) -> Identifier {
+    Identifier::new(format!("loc_{offset:x}"), "", 0).unwrap()
+}
+
+fn arg_to_value(arg: Arg) -> Value {
+    match arg {
+        Arg::Signed(c) => Value::Integer(c),
+        Arg::Unsigned(c) | Arg::Unknown(c) => Value::Integer(c as i32),
+        Arg::String(c) => Value::String {
+            is_unicode: c.is_unicode(),
+            value: c.value,
+        },
+        Arg::Pointer(Pointer::Raw(c)) => Value::Constant(label_name(c)),
+        Arg::Pointer(Pointer::Label(c)) => Value::Constant(Identifier::new(c, "", 0).unwrap()),
+        // old tickflow has no way to represent these
+        Arg::Array(_) | Arg::Struct(_) => {
+            Value::Constant(Identifier::new("unsupported_arg", "", 0).unwrap())
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Conversion of interpreter runs into MIDI files
+//!
+//! The first track has the tempo changes. Every call target and every other kind of cue gets a
+//! track of its own, with a note for each time it happens.
+
+use std::collections::BTreeMap;
+
+use tickflow_binaries::data::{btks::Tempo, Pointer, TickflowOpDef};
+
+use super::{MidiEvent, MidiFile, Track, DIVISION};
+use crate::{
+    analysis::timeline::Timeline,
+    data::megamix::MegamixOp,
+    interpreter::{Event, TempoState, TICKS_PER_BEAT},
+};
+
+/// Key of every exported note (middle C)
+pub const NOTE_KEY: u8 = 60;
+/// Length of every exported note, in ticks
+pub const NOTE_LENGTH: u32 = TICKS_PER_BEAT / 4;
+/// Tempo used until the first `TempoID` operation, in BPM
+pub const DEFAULT_BPM: f64 = 120.0;
+
+/// Converts the events of an interpreter run into a MIDI file. Tempo changes come from the
+/// `TempoID` operations ran, looked up in `tempos`, and from the speed operations (see
+/// [`TempoState`]). `Tempo` operations are left out, since the unit of their argument isn't
+/// known.
+//TODO: take Tempo into account once its unit is known
+pub fn events_to_midi(events: &[Event], tempos: &[Tempo], input_ops: &[TickflowOpDef]) -> MidiFile {
+    let mut tracks = vec![tempo_track(events, tempos)];
+
+    let timeline = Timeline::from_events("", events, input_ops);
+    let mut cue_tracks: BTreeMap<String, Track> = BTreeMap::new();
+    for cue in &timeline.cues {
+        let name = match &cue.op {
+            MegamixOp::Call {
+                loc: Pointer::Raw(c),
+                ..
+            }
+            | MegamixOp::CallSync(Pointer::Raw(c)) => format!("loc_{c:x}"),
+            MegamixOp::CallSub { sub: c, .. } | MegamixOp::CallSubSync(c) => format!("sub {c:#x}"),
+            MegamixOp::CallFunc { func: c, .. } | MegamixOp::CallFuncSync(c) => {
+                format!("func {c:#x}")
+            }
+            _ => cue.kind.name().to_string(),
+        };
+        let track = cue_tracks
+            .entry(name)
+            .or_insert_with_key(|c| Track::named(c.clone()));
+        track.events.push((
+            cue.tick,
+            MidiEvent::NoteOn {
+                channel: 0,
+                key: NOTE_KEY,
+                velocity: 100,
+            },
+        ));
+        track.events.push((
+            cue.tick.saturating_add(NOTE_LENGTH),
+            MidiEvent::NoteOff {
+                channel: 0,
+                key: NOTE_KEY,
+                velocity: 0,
+            },
+        ));
+    }
+    tracks.extend(cue_tracks.into_values());
+
+    MidiFile {
+        division: DIVISION,
+        tracks,
+    }
+}
+
+fn tempo_track(events: &[Event], tempos: &[Tempo]) -> Track {
+    let end = events.last().map(|c| c.tick).unwrap_or(0);
+
+    // base tempos, in microseconds per beat, and speed factors
+    let mut bases: Vec<(u32, Vec<(u32, f64)>)> = vec![];
+    let mut speeds: Vec<(u32, f64)> = vec![(0, 1.0)];
+    let mut state = TempoState::default();
+    for event in events {
+        let Some(new) = state.after(&event.op) else {
+            continue;
+        };
+        if new.speed != state.speed {
+            speeds.push((event.tick, new.speed));
+        }
+        if let MegamixOp::TempoID(c) = &event.op {
+            if let Some(tempo) = tempos.iter().find(|t| t.id == *c) {
+                bases.push((event.tick, tempo_segments(tempo, event.tick, end)));
+            }
+        }
+        state = new;
+    }
+
+    // every base tempo lasts until the next one starts
+    let mut changes: BTreeMap<u32, (Option<f64>, Option<f64>)> = BTreeMap::new();
+    changes.insert(0, (Some(60_000_000.0 / DEFAULT_BPM), None));
+    for (i, (start, segments)) in bases.iter().enumerate() {
+        let next = bases.get(i + 1).map(|c| c.0).unwrap_or(u32::MAX);
+        for (tick, us) in segments {
+            if *tick >= *start && *tick < next {
+                changes.entry(*tick).or_default().0 = Some(*us);
+            }
+        }
+    }
+    for (tick, speed) in speeds {
+        changes.entry(tick).or_default().1 = Some(speed);
+    }
+
+    let mut track = Track::named("tempo");
+    let (mut base, mut speed) = (60_000_000.0 / DEFAULT_BPM, 1.0);
+    for (tick, (new_base, new_speed)) in changes {
+        base = new_base.unwrap_or(base);
+        speed = new_speed.unwrap_or(speed);
+        let event = MidiEvent::Tempo((base / speed).round() as u32);
+        if speed > 0.0 && track.events.last().map(|c| &c.1) != Some(&event) {
+            track.events.push((tick, event));
+        }
+    }
+    track
+}
+
+/// Start tick and microseconds per beat of every segment of a tempo, looping until `end`
+fn tempo_segments(tempo: &Tempo, start: u32, end: u32) -> Vec<(u32, f64)> {
+    let data = &tempo.data;
+    let loop_start = data
+        .iter()
+        .position(|c| c.loop_val != 0)
+        .filter(|c| data[*c..].iter().any(|c| c.beats > 0.0));
+
+    let mut segments = vec![];
+    let mut tick = start as f64;
+    let mut i = 0;
+    while i < data.len() && tick <= end as f64 {
+        let segment = &data[i];
+        if segment.beats > 0.0 {
+            let seconds = segment.time as f64 / tempo.sample_rate as f64;
+            segments.push((
+                tick.round() as u32,
+                seconds * 1_000_000.0 / segment.beats as f64,
+            ));
+        }
+        tick += segment.beats as f64 * TICKS_PER_BEAT as f64;
+        i += 1;
+        if i == data.len() {
+            if let Some(c) = loop_start {
+                i = c;
+            }
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::btks::{TempoStream, TempoVal};
+
+    use super::*;
+
+    fn event(tick: u32, op: MegamixOp) -> Event {
+        Event {
+            tick,
+            thread: 0,
+            offset: 0,
+            op,
+            arg0: None,
+        }
+    }
+
+    #[test]
+    fn tempo_track_follows_tempo_ids_and_speed() {
+        let tempos = [Tempo {
+            id: 5,
+            data: vec![TempoVal {
+                beats: 4.0,
+                time: 48000,
+                loop_val: 0,
+            }],
+            sample_rate: 48000,
+            stream: TempoStream::Streamed,
+        }];
+        let events = [
+            event(0, MegamixOp::Tempo(200)),
+            event(0x30, MegamixOp::TempoID(5)),
+            event(0x60, MegamixOp::Speed(0x200)),
+        ];
+        let midi = events_to_midi(&events, &tempos, &[]);
+        assert_eq!(
+            midi.tracks[0].events[1..],
+            [
+                (0, MidiEvent::Tempo(500_000)),
+                (0x30, MidiEvent::Tempo(250_000)),
+                (0x60, MidiEvent::Tempo(125_000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn notes_end_at_the_last_tick() {
+        let events = [event(u32::MAX - 1, MegamixOp::CallSubSync(1))];
+        let midi = events_to_midi(&events, &[], &[]);
+        let cues = &midi.tracks[1].events;
+        assert_eq!(cues[cues.len() - 1].0, u32::MAX);
+    }
+}
@@ -0,0 +1,254 @@
+//! Conversion of MIDI files into tickflow skeletons
+//!
+//! Every note that starts on a mapped channel (and key, if given) is a cue, which turns into a
+//! call to the mapped sub. The time between cues turns into `rest` operations.
+//!
+//! Tempo changes can't be turned into `Tempo` operations, since the unit of its argument isn't
+//! known. Instead, they're turned into a [`Tempo`] for the TMPO section, with a segment for
+//! every change, which the skeleton sets with a `tempo_id` operation.
+
+use tickflow_binaries::data::btks::{Tempo, TempoStream, TempoVal, DEFAULT_SAMPLE_RATE};
+use tickflow_parse::old::{Identifier, Statement, Value};
+
+use super::{MidiEvent, MidiFile};
+use crate::{data::megamix::MegamixOp, interpreter::TICKS_PER_BEAT};
+
+/// Longest rest that fits in a single operation, since its length is given in arg0
+pub const MAX_REST: u32 = 0x3FFFF;
+
+const OP_CALL: u16 = 2;
+const OP_CALL_SYNC: u16 = 6;
+const OP_RETURN: u16 = 7;
+const OP_REST: u16 = 0xE;
+const OP_TEMPO: u16 = 0x24;
+const ARG0_TEMPO_ID: u32 = 2;
+
+/// Tempo of a MIDI file until its first tempo change, in microseconds per beat (120 BPM)
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Which notes are cues, and what they call
+#[derive(Debug, Clone)]
+pub struct CueMap {
+    pub channel: u8,
+    /// Key of the notes, or `None` for every note on the channel
+    pub key: Option<u8>,
+    /// Label or constant of the sub to call
+    pub target: Identifier,
+    /// Whether to wait for the sub to return instead of running it in a new thread
+    pub sync: bool,
+}
+
+impl CueMap {
+    fn matches(&self, channel: u8, key: u8) -> bool {
+        self.channel == channel && self.key.is_none_or(|c| c == key)
+    }
+}
+
+/// Converts a MIDI file into an old Tickflow sub named `name`. Notes from every track are taken
+/// into account, and a note matching more than one of `cues` calls every sub it matches.
+///
+/// If `tempo_id` is given, the sub starts by setting the tempo with that ID, which is returned
+/// along with the sub and follows the tempo changes of the file (see [`tempo_from_midi`]).
+pub fn midi_to_tickflow(
+    midi: &MidiFile,
+    cues: &[CueMap],
+    name: Identifier,
+    tempo_id: Option<u32>,
+) -> (Vec<Statement>, Option<Tempo>) {
+    // absolute MIDI tick and the statement to emit there
+    let mut events = vec![];
+    for track in &midi.tracks {
+        for (tick, event) in &track.events {
+            if let MidiEvent::NoteOn { channel, key, .. } = event {
+                for cue in cues.iter().filter(|c| c.matches(*channel, *key)) {
+                    events.push((*tick, call(cue)));
+                }
+            }
+        }
+    }
+    events.sort_by_key(|c| c.0);
+
+    let mut statements = vec![Statement::Label(name)];
+    if let Some(id) = tempo_id {
+        statements.push(command(
+            OP_TEMPO,
+            ARG0_TEMPO_ID,
+            vec![Value::Integer(id as i32)],
+        ));
+    }
+    let mut last = 0;
+    for (tick, statement) in events {
+        let tick = to_tickflow_ticks(tick, midi.division);
+        statements.extend(rests(tick - last));
+        last = tick;
+        statements.push(statement);
+    }
+    statements.push(command(OP_RETURN, 0, vec![]));
+    (statements, tempo_id.map(|c| tempo_from_midi(midi, c)))
+}
+
+/// Builds a tempo with the given ID from the tempo changes in every track of a MIDI file. Every
+/// change is a segment that lasts until the next one, and the last tempo goes on forever. The
+/// kind of audio is guessed from the ID.
+pub fn tempo_from_midi(midi: &MidiFile, id: u32) -> Tempo {
+    let division = midi.division.max(1) as f64;
+    let segment = |ticks: u32, us: u32| {
+        let beats = ticks as f64 / division;
+        TempoVal {
+            beats: beats as f32,
+            time: (beats * us as f64 * DEFAULT_SAMPLE_RATE as f64 / 1_000_000.0).round() as u32,
+            loop_val: 0,
+        }
+    };
+
+    let mut changes: Vec<(u32, u32)> = midi
+        .tracks
+        .iter()
+        .flat_map(|c| &c.events)
+        .filter_map(|(tick, event)| match event {
+            MidiEvent::Tempo(us) if *us != 0 => Some((*tick, *us)),
+            _ => None,
+        })
+        .collect();
+    changes.sort_by_key(|c| c.0);
+
+    let mut data = vec![];
+    let (mut start, mut current) = (0, DEFAULT_TEMPO);
+    for (tick, us) in changes {
+        if tick > start {
+            data.push(segment(tick - start, current));
+        }
+        (start, current) = (tick, us);
+    }
+    data.push(segment(midi.division.max(1) as u32, current));
+
+    Tempo {
+        id,
+        data,
+        sample_rate: DEFAULT_SAMPLE_RATE,
+        stream: TempoStream::guess(id),
+    }
+}
+
+/// Converts a MIDI tick into a tickflow tick, rounding to the closest one
+fn to_tickflow_ticks(tick: u32, division: u16) -> u32 {
+    let division = division.max(1) as u64;
+    ((tick as u64 * TICKS_PER_BEAT as u64 + division / 2) / division) as u32
+}
+
+fn rests(mut ticks: u32) -> Vec<Statement> {
+    let mut statements = vec![];
+    while ticks > 0 {
+        let rest = ticks.min(MAX_REST);
+        statements.push(command(OP_REST, rest, vec![]));
+        ticks -= rest;
+    }
+    statements
+}
+
+fn call(cue: &CueMap) -> Statement {
+    command(
+        if cue.sync { OP_CALL_SYNC } else { OP_CALL },
+        0,
+        vec![Value::Constant(cue.target.clone())],
+    )
+}
+
+fn command(op: u16, arg0: u32, args: Vec<Value>) -> Statement {
+    Statement::command::<MegamixOp>(op, arg0, -1, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::Track;
+
+    fn note(channel: u8, key: u8) -> MidiEvent {
+        MidiEvent::NoteOn {
+            channel,
+            key,
+            velocity: 100,
+        }
+    }
+
+    fn ident(name: &str) -> Identifier {
+        Identifier::new(name, "test", 1).unwrap()
+    }
+
+    #[test]
+    fn imports_rests_and_calls() {
+        // 96 ticks per beat, at 120 BPM and then 60 BPM from beat 2
+        let midi = MidiFile {
+            division: 96,
+            tracks: vec![
+                Track {
+                    events: vec![(192, MidiEvent::Tempo(1_000_000))],
+                },
+                Track {
+                    events: vec![(48, note(0, 60)), (96, note(1, 60)), (96, note(0, 61))],
+                },
+            ],
+        };
+        let mut smf = vec![];
+        midi.to_smf(&mut smf).unwrap();
+        let midi = MidiFile::from_smf(&mut smf.as_slice()).unwrap();
+
+        let cues = [
+            CueMap {
+                channel: 0,
+                key: Some(60),
+                target: ident("HIT"),
+                sync: false,
+            },
+            CueMap {
+                channel: 1,
+                key: None,
+                target: ident("WAIT"),
+                sync: true,
+            },
+        ];
+        let (statements, tempo) = midi_to_tickflow(&midi, &cues, ident("chart"), Some(0x100));
+        let statements: Vec<_> = statements.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            statements,
+            [
+                "chart:",
+                "tempo_id 0x100",
+                "rest<0x18>",
+                "async_call HIT",
+                "rest<0x18>",
+                "call WAIT",
+                "return",
+            ]
+        );
+
+        let tempo = tempo.unwrap();
+        assert_eq!(tempo.id, 0x100);
+        assert_eq!(
+            tempo.data,
+            [
+                TempoVal {
+                    beats: 2.0,
+                    time: DEFAULT_SAMPLE_RATE,
+                    loop_val: 0,
+                },
+                TempoVal {
+                    beats: 1.0,
+                    time: DEFAULT_SAMPLE_RATE,
+                    loop_val: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_the_tempo_without_an_id() {
+        let midi = MidiFile {
+            division: 48,
+            tracks: vec![],
+        };
+        let (statements, tempo) = midi_to_tickflow(&midi, &[], ident("chart"), None);
+        assert_eq!(statements.len(), 2);
+        assert!(tempo.is_none());
+    }
+}
@@ -0,0 +1,343 @@
+//! Standard MIDI Files, and conversion between them and tickflow
+
+use std::io::{self, Read, Write};
+
+pub mod export;
+pub mod import;
+
+/// Ticks per quarter note used in exported files, the same as in tickflow
+pub const DIVISION: u16 = crate::interpreter::TICKS_PER_BEAT as u16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiEvent {
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    /// Microseconds per quarter note
+    Tempo(u32),
+    TrackName(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    /// Events and the tick they happen on, counted from the start of the song
+    pub events: Vec<(u32, MidiEvent)>,
+}
+
+impl Track {
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            events: vec![(0, MidiEvent::TrackName(name.into()))],
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.events.iter().find_map(|(_, c)| match c {
+            MidiEvent::TrackName(c) => Some(c.as_str()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiFile {
+    /// Ticks per quarter note
+    pub division: u16,
+    pub tracks: Vec<Track>,
+}
+
+impl MidiFile {
+    /// Reads a format 0 or 1 SMF. Events other than notes, tempo changes and track names are
+    /// skipped.
+    pub fn from_smf<F: Read>(f: &mut F) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut data = vec![];
+        f.read_to_end(&mut data)?;
+        let mut chunks = Chunks {
+            data: &data,
+            pos: 0,
+        };
+
+        let (magic, header) = chunks.next_chunk()?;
+        if magic != b"MThd" || header.len() < 6 {
+            return Err(invalid("not a MIDI file"));
+        }
+        let division = u16::from_be_bytes([header[4], header[5]]);
+        if division & 0x8000 != 0 {
+            return Err(invalid("SMPTE time divisions aren't supported"));
+        }
+
+        let mut tracks = vec![];
+        while chunks.pos < data.len() {
+            let (magic, chunk) = chunks.next_chunk()?;
+            if magic == b"MTrk" {
+                tracks.push(read_track(chunk).ok_or_else(|| invalid("invalid MIDI track"))?);
+            }
+        }
+
+        Ok(Self { division, tracks })
+    }
+
+    /// Writes the file as a format 1 SMF
+    pub fn to_smf<F: Write>(&self, f: &mut F) -> io::Result<()> {
+        f.write_all(b"MThd")?;
+        f.write_all(&6u32.to_be_bytes())?;
+        f.write_all(&1u16.to_be_bytes())?;
+        f.write_all(&(self.tracks.len() as u16).to_be_bytes())?;
+        f.write_all(&self.division.to_be_bytes())?;
+
+        for track in &self.tracks {
+            let mut events = track.events.clone();
+            events.sort_by_key(|(tick, c)| (*tick, !matches!(c, MidiEvent::NoteOff { .. })));
+
+            let mut data = vec![];
+            let mut last_tick = 0;
+            for (tick, event) in events {
+                write_var_len(&mut data, tick - last_tick)?;
+                last_tick = tick;
+                match event {
+                    MidiEvent::NoteOn {
+                        channel,
+                        key,
+                        velocity,
+                    } => data.extend([0x90 | (channel & 0xF), key & 0x7F, velocity & 0x7F]),
+                    MidiEvent::NoteOff {
+                        channel,
+                        key,
+                        velocity,
+                    } => data.extend([0x80 | (channel & 0xF), key & 0x7F, velocity & 0x7F]),
+                    MidiEvent::Tempo(c) => {
+                        data.extend([0xFF, 0x51, 0x03]);
+                        data.extend(&c.min(0xFFFFFF).to_be_bytes()[1..]);
+                    }
+                    MidiEvent::TrackName(c) => {
+                        data.extend([0xFF, 0x03]);
+                        write_var_len(&mut data, c.len() as u32)?;
+                        data.extend(c.as_bytes());
+                    }
+                }
+            }
+            // end of track
+            data.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+            f.write_all(b"MTrk")?;
+            f.write_all(&(data.len() as u32).to_be_bytes())?;
+            f.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Largest value that fits in a variable-length quantity, which has 4 bytes of 7 bits at most
+const MAX_VAR_LEN: u32 = 0x0FFFFFFF;
+
+fn write_var_len(data: &mut Vec<u8>, value: u32) -> io::Result<()> {
+    if value > MAX_VAR_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{value:#x} is too large for a MIDI variable-length quantity"),
+        ));
+    }
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value != 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+    Ok(())
+}
+
+struct Chunks<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Chunks<'a> {
+    fn next_chunk(&mut self) -> io::Result<(&'a [u8], &'a [u8])> {
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let header = self.data.get(self.pos..self.pos + 8).ok_or_else(eof)?;
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let chunk = self
+            .data
+            .get(self.pos + 8..self.pos + 8 + len)
+            .ok_or_else(eof)?;
+        self.pos += 8 + len;
+        Ok((&header[..4], chunk))
+    }
+}
+
+fn read_track(data: &[u8]) -> Option<Track> {
+    let mut track = Track::default();
+    let mut pos = 0;
+    let mut tick = 0u32;
+    let mut running_status = None;
+    while pos < data.len() {
+        tick = tick.checked_add(read_var_len(data, &mut pos)?)?;
+
+        let mut status = *data.get(pos)?;
+        if status < 0x80 {
+            // running status, this byte is already the first data byte
+            status = running_status?;
+        } else {
+            pos += 1;
+        }
+
+        match status {
+            0xFF => {
+                let kind = *data.get(pos)?;
+                pos += 1;
+                let len = read_var_len(data, &mut pos)? as usize;
+                let contents = data.get(pos..pos + len)?;
+                pos += len;
+                match kind {
+                    0x03 => track.events.push((
+                        tick,
+                        MidiEvent::TrackName(String::from_utf8_lossy(contents).into_owned()),
+                    )),
+                    0x51 if len == 3 => track.events.push((
+                        tick,
+                        MidiEvent::Tempo(u32::from_be_bytes([
+                            0,
+                            contents[0],
+                            contents[1],
+                            contents[2],
+                        ])),
+                    )),
+                    0x2F => break,
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = read_var_len(data, &mut pos)? as usize;
+                pos += len;
+            }
+            _ => {
+                running_status = Some(status);
+                let channel = status & 0xF;
+                let len = match status & 0xF0 {
+                    0xC0 | 0xD0 => 1,
+                    _ => 2,
+                };
+                let args = data.get(pos..pos + len)?;
+                pos += len;
+                match status & 0xF0 {
+                    0x90 if args[1] != 0 => track.events.push((
+                        tick,
+                        MidiEvent::NoteOn {
+                            channel,
+                            key: args[0],
+                            velocity: args[1],
+                        },
+                    )),
+                    0x80 | 0x90 => track.events.push((
+                        tick,
+                        MidiEvent::NoteOff {
+                            channel,
+                            key: args[0],
+                            velocity: args[1],
+                        },
+                    )),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Some(track)
+}
+
+fn read_var_len(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smf_round_trip() {
+        let midi = MidiFile {
+            division: DIVISION,
+            tracks: vec![
+                Track {
+                    events: vec![
+                        (0, MidiEvent::TrackName("tempo".to_string())),
+                        (0, MidiEvent::Tempo(500_000)),
+                        (0x60, MidiEvent::Tempo(250_000)),
+                    ],
+                },
+                Track {
+                    events: vec![
+                        (0, MidiEvent::TrackName("cues".to_string())),
+                        (
+                            0x30,
+                            MidiEvent::NoteOn {
+                                channel: 1,
+                                key: 60,
+                                velocity: 100,
+                            },
+                        ),
+                        (
+                            0x3C,
+                            MidiEvent::NoteOff {
+                                channel: 1,
+                                key: 60,
+                                velocity: 0,
+                            },
+                        ),
+                        (
+                            0x4000,
+                            MidiEvent::NoteOn {
+                                channel: 1,
+                                key: 62,
+                                velocity: 100,
+                            },
+                        ),
+                    ],
+                },
+            ],
+        };
+        let mut data = vec![];
+        midi.to_smf(&mut data).unwrap();
+        let read = MidiFile::from_smf(&mut data.as_slice()).unwrap();
+        assert_eq!(read.division, midi.division);
+        assert_eq!(read.tracks.len(), midi.tracks.len());
+        for (read, track) in read.tracks.iter().zip(&midi.tracks) {
+            assert_eq!(read.events, track.events);
+        }
+    }
+
+    #[test]
+    fn var_len_limit() {
+        let mut data = vec![];
+        write_var_len(&mut data, MAX_VAR_LEN).unwrap();
+        assert_eq!(data, [0xFF, 0xFF, 0xFF, 0x7F]);
+        assert_eq!(read_var_len(&data, &mut 0), Some(MAX_VAR_LEN));
+        assert!(write_var_len(&mut data, MAX_VAR_LEN + 1).is_err());
+
+        let midi = MidiFile {
+            division: DIVISION,
+            tracks: vec![Track {
+                events: vec![(MAX_VAR_LEN + 1, MidiEvent::Tempo(500_000))],
+            }],
+        };
+        assert!(midi.to_smf(&mut vec![]).is_err());
+    }
+}
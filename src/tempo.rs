@@ -0,0 +1,309 @@
+//! Conversion between beat positions and timestamps in a song, using TMPO data
+//!
+//! Every [`TempoVal`] in a tempo is a segment of the song that lasts `beats` beats and `time`
+//! samples. If a segment has a non-zero `loop_val`, the song loops back to the first one of those
+//! once it reaches its end. Otherwise, the last segment's tempo goes on forever. Segments that
+//! last no beats or no samples are skipped over when they can't be used for a position.
+//!
+//! The song's speed can change while it plays (see [`speed_after`]). Every change lasts from
+//! the tick of the operation that made it until the next one. A speed of 0 stops the song for
+//! good, so beats after it are never reached.
+
+use tickflow_binaries::data::btks::{Tempo, TempoVal};
+
+use crate::{data::megamix::MegamixOp, interpreter::TICKS_PER_BEAT};
+
+/// Value of 1.0 in the fixed-point factors used by `TempoRel` and `SpeedRel`
+pub const REL_FACTOR_ONE: u32 = 0x100;
+
+#[derive(Debug, Clone)]
+pub struct TempoMap<'a> {
+    tempo: &'a Tempo,
+    /// Sample rate of the timestamps given to and returned by this map
+    pub sample_rate: u32,
+    /// Beat where every speed multiplier starts, and the multiplier, in order. Changed by
+    /// `Speed`/`TempoRel`/`SpeedRel`.
+    speeds: Vec<(f64, f64)>,
+}
+
+impl<'a> TempoMap<'a> {
+    /// Finds the tempo with the given ID
+    pub fn new(tempos: &'a [Tempo], id: u32, sample_rate: u32) -> Option<Self> {
+        tempos
+            .iter()
+            .find(|c| c.id == id)
+            .map(|c| Self::from_tempo(c, sample_rate))
+    }
+
+    pub fn from_tempo(tempo: &'a Tempo, sample_rate: u32) -> Self {
+        Self {
+            tempo,
+            sample_rate,
+            speeds: vec![(0.0, 1.0)],
+        }
+    }
+
+    /// Speed multiplier after every operation applied so far
+    pub fn speed(&self) -> f64 {
+        self.speeds.last().map_or(1.0, |c| c.1)
+    }
+
+    /// Applies the effects of a tempo-modifying operation run at `tick`, from that tick on. Any
+    /// other operation is ignored. Operations must be applied in the order they run, since a
+    /// change replaces any that were applied at the same tick or later.
+    pub fn apply(&mut self, tick: u32, op: &MegamixOp) {
+        if let Some(speed) = speed_after(self.speed(), op) {
+            let beat = tick as f64 / TICKS_PER_BEAT as f64;
+            self.speeds.retain(|c| c.0 < beat);
+            self.speeds.push((beat, speed));
+        }
+    }
+
+    fn segments(&self) -> &[TempoVal] {
+        &self.tempo.data
+    }
+
+    fn loop_start(&self) -> Option<usize> {
+        let segments = self.segments();
+        let start = segments.iter().position(|c| c.loop_val != 0)?;
+        // an empty loop would never advance
+        let looped = &segments[start..];
+        (looped.iter().any(|c| c.beats > 0.0) && looped.iter().any(|c| c.time > 0)).then_some(start)
+    }
+
+    /// Last segment that lasts both some beats and some samples, whose tempo goes on forever if
+    /// the song doesn't loop
+    fn last_moving(&self) -> Option<&TempoVal> {
+        self.segments()
+            .iter()
+            .rev()
+            .find(|c| c.beats > 0.0 && c.time > 0)
+    }
+
+    /// Position of the song at `beat`, in samples at the tempo's own sample rate
+    fn beat_to_tempo_samples(&self, beat: f64) -> f64 {
+        let segments = self.segments();
+        if segments.is_empty() || beat <= 0.0 {
+            return 0.0;
+        }
+
+        let (mut beats, mut samples) = (0.0, 0.0);
+        for segment in segments {
+            let len = segment.beats as f64;
+            if len > 0.0 && beat < beats + len {
+                return samples + (beat - beats) / len * segment.time as f64;
+            }
+            beats += len;
+            samples += segment.time as f64;
+        }
+
+        match self.loop_start() {
+            Some(start) => {
+                let loop_beats: f64 = segments[start..].iter().map(|c| c.beats as f64).sum();
+                let loop_samples: f64 = segments[start..].iter().map(|c| c.time as f64).sum();
+                let start_beats = beats - loop_beats;
+                let start_samples = samples - loop_samples;
+                let loops = ((beat - beats) / loop_beats).floor();
+                let remaining = beat - beats - loops * loop_beats;
+                samples + loops * loop_samples + self.beat_to_tempo_samples(start_beats + remaining)
+                    - start_samples
+            }
+            None => match self.last_moving() {
+                Some(last) => samples + (beat - beats) / last.beats as f64 * last.time as f64,
+                None => samples,
+            },
+        }
+    }
+
+    /// Beat at a position of the song, in samples at the tempo's own sample rate
+    fn tempo_samples_to_beat(&self, time: f64) -> f64 {
+        let segments = self.segments();
+        if segments.is_empty() || time <= 0.0 {
+            return 0.0;
+        }
+
+        let (mut beats, mut samples) = (0.0, 0.0);
+        for segment in segments {
+            let len = segment.time as f64;
+            if len > 0.0 && time < samples + len {
+                return beats + (time - samples) / len * segment.beats as f64;
+            }
+            beats += segment.beats as f64;
+            samples += len;
+        }
+
+        match self.loop_start() {
+            Some(start) => {
+                let loop_beats: f64 = segments[start..].iter().map(|c| c.beats as f64).sum();
+                let loop_samples: f64 = segments[start..].iter().map(|c| c.time as f64).sum();
+                let start_beats = beats - loop_beats;
+                let start_samples = samples - loop_samples;
+                let loops = ((time - samples) / loop_samples).floor();
+                let remaining = time - samples - loops * loop_samples;
+                beats + loops * loop_beats + self.tempo_samples_to_beat(start_samples + remaining)
+                    - start_beats
+            }
+            None => match self.last_moving() {
+                Some(last) => beats + (time - samples) / last.time as f64 * last.beats as f64,
+                None => beats,
+            },
+        }
+    }
+
+    /// Timestamp of `beat`, in samples at this map's sample rate
+    pub fn beat_to_samples(&self, beat: f64) -> f64 {
+        let mut samples = 0.0;
+        for (i, (start, speed)) in self.speeds.iter().enumerate() {
+            if beat <= *start {
+                break;
+            }
+            let end = self.speeds.get(i + 1).map_or(beat, |c| c.0.min(beat));
+            if *speed <= 0.0 {
+                return f64::INFINITY;
+            }
+            samples +=
+                (self.beat_to_tempo_samples(end) - self.beat_to_tempo_samples(*start)) / speed;
+        }
+        samples * self.sample_rate as f64 / self.tempo.sample_rate as f64
+    }
+
+    pub fn beat_to_seconds(&self, beat: f64) -> f64 {
+        self.beat_to_samples(beat) / self.sample_rate as f64
+    }
+
+    /// Beat at a timestamp, given in samples at this map's sample rate
+    pub fn samples_to_beat(&self, samples: f64) -> f64 {
+        let time = samples * self.tempo.sample_rate as f64 / self.sample_rate as f64;
+        let mut played = 0.0;
+        for (i, (start, speed)) in self.speeds.iter().enumerate() {
+            if *speed <= 0.0 {
+                return *start;
+            }
+            let start_samples = self.beat_to_tempo_samples(*start);
+            if let Some((end, _)) = self.speeds.get(i + 1) {
+                let len = (self.beat_to_tempo_samples(*end) - start_samples) / speed;
+                if time >= played + len {
+                    played += len;
+                    continue;
+                }
+            }
+            return self.tempo_samples_to_beat(start_samples + (time - played) * speed);
+        }
+        0.0
+    }
+
+    pub fn seconds_to_beat(&self, seconds: f64) -> f64 {
+        self.samples_to_beat(seconds * self.sample_rate as f64)
+    }
+}
+
+/// Speed multiplier after running `op` with a speed of `speed`, if `op` changes it
+pub fn speed_after(speed: f64, op: &MegamixOp) -> Option<f64> {
+    let rel = |c: u32| c as f64 / REL_FACTOR_ONE as f64;
+    match op {
+        MegamixOp::TempoRel {
+            factor,
+            lower,
+            upper,
+        }
+        | MegamixOp::SpeedRel {
+            factor,
+            lower,
+            upper,
+        } => {
+            let speed = speed * rel(*factor);
+            if *upper != 0 && lower <= upper {
+                Some(speed.clamp(rel(*lower), rel(*upper)))
+            } else {
+                Some(speed)
+            }
+        }
+        MegamixOp::Speed(c) => Some(rel(*c)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::btks::TempoStream;
+
+    use super::*;
+
+    fn tempo_with(data: &[(f32, u32, u32)]) -> Tempo {
+        Tempo {
+            id: 0,
+            data: data
+                .iter()
+                .map(|&(beats, time, loop_val)| TempoVal {
+                    beats,
+                    time,
+                    loop_val,
+                })
+                .collect(),
+            sample_rate: 48000,
+            stream: TempoStream::Streamed,
+        }
+    }
+
+    #[test]
+    fn goes_through_every_segment() {
+        let tempo = tempo_with(&[(1.0, 48000, 0), (2.0, 48000, 0), (1.0, 24000, 0)]);
+        let map = TempoMap::from_tempo(&tempo, 48000);
+        for (beat, seconds) in [(0.5, 0.5), (1.0, 1.0), (2.0, 1.5), (3.5, 2.25), (5.0, 3.0)] {
+            assert_eq!(map.beat_to_seconds(beat), seconds);
+            assert_eq!(map.seconds_to_beat(seconds), beat);
+        }
+    }
+
+    #[test]
+    fn wraps_past_the_loop_point() {
+        let tempo = tempo_with(&[(1.0, 48000, 0), (2.0, 48000, 1)]);
+        let map = TempoMap::from_tempo(&tempo, 48000);
+        for (beat, seconds) in [(3.0, 2.0), (4.0, 2.5), (6.0, 3.5), (9.0, 5.0)] {
+            assert_eq!(map.beat_to_seconds(beat), seconds);
+            assert_eq!(map.seconds_to_beat(seconds), beat);
+        }
+    }
+
+    #[test]
+    fn skips_empty_segments() {
+        let tempo = tempo_with(&[(0.0, 0, 0), (1.0, 48000, 0), (0.0, 24000, 0)]);
+        let map = TempoMap::from_tempo(&tempo, 48000);
+        assert_eq!(map.beat_to_seconds(0.75), 0.75);
+        assert_eq!(map.beat_to_seconds(2.0), 2.5);
+        assert_eq!(map.seconds_to_beat(1.25), 1.0);
+        assert_eq!(map.seconds_to_beat(2.5), 2.0);
+
+        let tempo = tempo_with(&[(0.0, 0, 1), (1.0, 0, 0)]);
+        let map = TempoMap::from_tempo(&tempo, 48000);
+        assert_eq!(map.beat_to_seconds(2.0), 0.0);
+        assert_eq!(map.seconds_to_beat(2.0), 1.0);
+    }
+
+    #[test]
+    fn zero_speed_stops_the_song() {
+        let tempo = tempo_with(&[(1.0, 48000, 0)]);
+        let mut map = TempoMap::from_tempo(&tempo, 48000);
+        map.apply(TICKS_PER_BEAT, &MegamixOp::Speed(0));
+        assert_eq!(map.beat_to_seconds(1.0), 1.0);
+        assert_eq!(map.beat_to_seconds(2.0), f64::INFINITY);
+        assert_eq!(map.seconds_to_beat(5.0), 1.0);
+    }
+
+    #[test]
+    fn speed_changes_last_from_their_tick() {
+        let tempo = tempo_with(&[(1.0, 48000, 0)]);
+        let mut map = TempoMap::from_tempo(&tempo, 48000);
+        map.apply(2 * TICKS_PER_BEAT, &MegamixOp::Speed(2 * REL_FACTOR_ONE));
+        assert_eq!(map.speed(), 2.0);
+        assert_eq!(map.beat_to_seconds(1.0), 1.0);
+        assert_eq!(map.beat_to_seconds(3.0), 2.5);
+        assert_eq!(map.seconds_to_beat(1.0), 1.0);
+        assert_eq!(map.seconds_to_beat(2.5), 3.0);
+
+        // a later change at the same tick replaces the earlier one
+        map.apply(2 * TICKS_PER_BEAT, &MegamixOp::Speed(REL_FACTOR_ONE / 2));
+        assert_eq!(map.beat_to_seconds(3.0), 4.0);
+        assert_eq!(map.seconds_to_beat(4.0), 3.0);
+    }
+}
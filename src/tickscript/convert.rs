@@ -0,0 +1,477 @@
+//! Conversion of old Tickflow into Tickscript
+//!
+//! - `#index` and `#include` are kept, and every `#alias` becomes a command definition
+//! - constants become `const`s. Tickscript values can't have operations in them, so expressions
+//!   are evaluated
+//! - every label that's used as an argument starts a sub, and so does `start`, which is renamed
+//!   to [`START_SUB`]. Other labels are left out.
+//! - commands become the Tickscript command for their operation, or a `raw_op` if there isn't one
+//!
+//! Subs are kept in the same order, so code that runs from a label into the next one still does
+//! once lowered. `#start` and `#assets` have no equivalent, so they're left out.
+
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+use tickflow_binaries::data::OperationSet;
+use tickflow_parse::{
+    error::OldTfError,
+    eval,
+    new::ast::{ArgType, File, Item, Statement, Sub, Value},
+    old::{self, CommandName},
+};
+
+use super::{command_by_name, lower::START_SUB, statement_for_op};
+use crate::data::megamix::MegamixOp;
+
+/// Sub for the commands before the first label, if there's a `start` label elsewhere
+const TOP_SUB: &str = "_top";
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("line {0}: unknown command \"{1}\"")]
+    UnknownCommand(usize, String),
+    #[error("line {0}: invalid arguments for \"#{1}\"")]
+    InvalidDirective(usize, String),
+    #[error("line {0}: labels and strings can't be used in expressions")]
+    InvalidExpression(usize),
+    #[error("line {0}: constant \"{1}\" is defined in terms of itself")]
+    RecursiveConstant(usize, String),
+    #[error("line {0}: {1}")]
+    Evaluation(usize, OldTfError),
+}
+
+pub type Result<T> = std::result::Result<T, ConvertError>;
+
+/// Converts the statements of a file, as read by [`old::parse_from_text`]
+pub fn convert(statements: &[(usize, old::Statement, old::StatementSpans)]) -> Result<File> {
+    let converter = Converter::new(statements);
+    let mut file = File {
+        items: vec![Item::Directive {
+            name: "tickscript".to_string(),
+            args: vec![],
+        }],
+    };
+    let mut sub: Option<Sub> = None;
+
+    for (l, statement, _) in statements {
+        match statement {
+            old::Statement::Directive { name, args } => match (name.as_str(), &args[..]) {
+                ("index", [value]) => file.items.push(Item::Directive {
+                    name: "index".to_string(),
+                    args: vec![converter.value(value, *l)?],
+                }),
+                ("include", [old::Value::String { value, .. }]) => {
+                    file.items.push(Item::Directive {
+                        name: "include".to_string(),
+                        args: vec![Value::String {
+                            value: value.clone(),
+                            is_unicode: false,
+                        }],
+                    })
+                }
+                ("alias", [old::Value::Constant(name), old::Value::Integer(_)]) => {
+                    file.items.push(converter.command_def(name));
+                }
+                ("index" | "include" | "alias", _) => {
+                    return Err(ConvertError::InvalidDirective(*l, name.to_string()))
+                }
+                _ => {}
+            },
+            old::Statement::Constant { name, value } => file.items.push(Item::Const {
+                name: name.to_string(),
+                value: converter.value(value, *l)?,
+            }),
+            old::Statement::Label(c) if converter.targets.contains(c.as_str()) => {
+                if let Some(sub) = sub.take() {
+                    file.items.push(Item::Sub(sub));
+                }
+                sub = Some(Sub {
+                    name: converter.rename(c).to_string(),
+                    sync: false,
+                    body: vec![],
+                });
+            }
+            old::Statement::Label(_) => {}
+            old::Statement::Command { cmd, arg0, args } => {
+                let statement = converter.command(cmd, arg0.as_ref(), args, *l)?;
+                sub.get_or_insert_with(|| Sub {
+                    name: if converter.targets.contains("start") {
+                        TOP_SUB.to_string()
+                    } else {
+                        START_SUB.to_string()
+                    },
+                    sync: false,
+                    body: vec![],
+                })
+                .body
+                .push(statement);
+            }
+        }
+    }
+    if let Some(sub) = sub {
+        file.items.push(Item::Sub(sub));
+    }
+
+    mark_sync_subs(&mut file);
+    Ok(file)
+}
+
+/// Makes subs that are only called with `call` (operation 6) synchronous, like the decompiler does
+fn mark_sync_subs(file: &mut File) {
+    let mut sync_calls = HashSet::new();
+    let mut other_uses = HashSet::new();
+    for sub in file.subs() {
+        for statement in &sub.body {
+            let (is_sync, args) = match statement {
+                Statement::Command { name, args } => {
+                    (command_by_name(name).is_some_and(|c| c.op == 6), args)
+                }
+                Statement::RawOp { op, args, .. } => (*op == 6, args),
+                _ => continue,
+            };
+            for arg in args {
+                if let Value::Identifier(c) = arg {
+                    if is_sync {
+                        sync_calls.insert(c.clone());
+                    } else {
+                        other_uses.insert(c.clone());
+                    }
+                }
+            }
+        }
+    }
+    for item in &mut file.items {
+        if let Item::Sub(sub) = item {
+            sub.sync = sync_calls.contains(&sub.name) && !other_uses.contains(&sub.name);
+        }
+    }
+}
+
+struct Converter {
+    /// Operation and arg0 of every `#alias`
+    aliases: HashMap<String, (u16, u32)>,
+    /// Number of arguments of every alias, taken from its first use
+    alias_args: HashMap<String, usize>,
+    /// Labels that start a sub
+    targets: HashSet<String>,
+    /// Value of every constant, so they can be used in expressions before they're defined
+    constants: HashMap<String, old::Value>,
+}
+
+impl Converter {
+    fn new(statements: &[(usize, old::Statement, old::StatementSpans)]) -> Self {
+        let mut aliases = HashMap::new();
+        let mut alias_args = HashMap::new();
+        let mut labels = HashSet::new();
+        let mut used = HashSet::new();
+        let mut constants = HashMap::new();
+        for (_, statement, _) in statements {
+            match statement {
+                // malformed `#alias`es are reported once they're converted
+                old::Statement::Directive { name, args } if name.as_str() == "alias" => {
+                    if let [old::Value::Constant(alias), old::Value::Integer(raw)] = &args[..] {
+                        let raw = *raw as u32;
+                        aliases.insert(alias.to_string(), ((raw & 0x3FF) as u16, raw >> 14));
+                    }
+                }
+                old::Statement::Label(c) => {
+                    labels.insert(c.to_string());
+                }
+                old::Statement::Constant { name, value } => {
+                    identifiers(value, &mut used);
+                    constants.insert(name.to_string(), value.clone());
+                }
+                old::Statement::Command { cmd, args, .. } => {
+                    if let CommandName::Named(c) = cmd {
+                        alias_args.entry(c.to_string()).or_insert(args.len());
+                    }
+                    for arg in args {
+                        identifiers(arg, &mut used);
+                    }
+                }
+                _ => {}
+            }
+        }
+        used.insert("start".to_string());
+        Self {
+            aliases,
+            alias_args,
+            targets: labels.intersection(&used).cloned().collect(),
+            constants,
+        }
+    }
+
+    fn rename<'a>(&self, name: &'a str) -> &'a str {
+        if name == "start" && self.targets.contains(name) {
+            START_SUB
+        } else {
+            name
+        }
+    }
+
+    /// Command definition for an `#alias`, with `any` arguments
+    fn command_def(&self, name: &str) -> Item {
+        let (op, arg0) = self.aliases[name];
+        let args: Vec<_> = (1..=self.alias_args.get(name).copied().unwrap_or(0))
+            .map(|c| (format!("arg{c}"), ArgType::Any))
+            .collect();
+        Item::Command {
+            name: name.to_string(),
+            body: Statement::RawOp {
+                op,
+                arg0,
+                args: args
+                    .iter()
+                    .map(|(c, _)| Value::Identifier(c.clone()))
+                    .collect(),
+            },
+            args,
+        }
+    }
+
+    fn command(
+        &self,
+        cmd: &CommandName,
+        arg0: Option<&old::Value>,
+        args: &[old::Value],
+        line: usize,
+    ) -> Result<Statement> {
+        let args = args
+            .iter()
+            .map(|c| self.value(c, line))
+            .collect::<Result<Vec<_>>>()?;
+        let arg0 = arg0
+            .map(|c| self.evaluate(c, line, &mut vec![]).map(|c| c as u32))
+            .transpose()?;
+        let (op, preset_arg0) = match cmd {
+            CommandName::Raw(c) => ((*c & 0x3FF) as u16, *c as u32 >> 14),
+            CommandName::Named(name) => {
+                if let Some(alias) = self.aliases.get(name.as_str()) {
+                    // aliases can only be used as commands the way they're defined
+                    if arg0.is_none() && self.alias_args.get(name.as_str()) == Some(&args.len()) {
+                        return Ok(Statement::Command {
+                            name: name.to_string(),
+                            args,
+                        });
+                    }
+                    *alias
+                } else if let Some(def) = MegamixOp::alias_by_name(name) {
+                    (def.op, def.arg0.unwrap_or(0))
+                } else {
+                    return Err(ConvertError::UnknownCommand(line, name.to_string()));
+                }
+            }
+        };
+        Ok(statement_for_op(op, arg0.unwrap_or(preset_arg0), args))
+    }
+
+    fn value(&self, value: &old::Value, line: usize) -> Result<Value> {
+        Ok(match value {
+            old::Value::Integer(c) => Value::Integer(*c as i64),
+            old::Value::String { value, is_unicode } => Value::String {
+                value: value.clone(),
+                is_unicode: *is_unicode,
+            },
+            old::Value::Constant(c) => Value::Identifier(self.rename(c).to_string()),
+            _ => Value::Integer(self.evaluate(value, line, &mut vec![])? as i64),
+        })
+    }
+
+    /// Evaluates an integer expression. `evaluating` has the constants whose values are being
+    /// evaluated, so constants defined in terms of themselves are an error.
+    fn evaluate<'a>(
+        &'a self,
+        value: &'a old::Value,
+        line: usize,
+        evaluating: &mut Vec<&'a str>,
+    ) -> Result<i32> {
+        let checked = |c: std::result::Result<i32, OldTfError>| {
+            c.map_err(|e| ConvertError::Evaluation(line, e))
+        };
+        match value {
+            old::Value::Integer(c) => Ok(*c),
+            old::Value::Constant(c) => match self.constants.get(c.as_str()) {
+                Some(_) if evaluating.contains(&c.as_str()) => {
+                    Err(ConvertError::RecursiveConstant(line, c.to_string()))
+                }
+                Some(value) => {
+                    evaluating.push(c);
+                    let out = self.evaluate(value, line, evaluating);
+                    evaluating.pop();
+                    out
+                }
+                None => Err(ConvertError::InvalidExpression(line)),
+            },
+            old::Value::Operation { op, values } => checked(op.apply(
+                self.evaluate(&values[0], line, evaluating)?,
+                self.evaluate(&values[1], line, evaluating)?,
+            )),
+            old::Value::Negated(c) => checked(eval::negate(self.evaluate(c, line, evaluating)?)),
+            old::Value::BitwiseNot(c) => Ok(eval::bitwise_not(self.evaluate(c, line, evaluating)?)),
+            old::Value::String { .. } => Err(ConvertError::InvalidExpression(line)),
+        }
+    }
+}
+
+/// Finds the names of every constant or label in a value
+fn identifiers(value: &old::Value, out: &mut HashSet<String>) {
+    match value {
+        old::Value::Constant(c) => {
+            out.insert(c.to_string());
+        }
+        old::Value::Operation { values, .. } => {
+            identifiers(&values[0], out);
+            identifiers(&values[1], out);
+        }
+        old::Value::Negated(c) | old::Value::BitwiseNot(c) => identifiers(c, out),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Vec<(usize, old::Statement, old::StatementSpans)> {
+        old::parse_from_text("test.tickflow", &mut text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn constants_used_before_their_definition() {
+        let file = convert(&parse(
+            "#index 0x56\nstart:\nrest A * 2\nA = B + 1\nB = 0x17\n",
+        ))
+        .unwrap();
+        let rest = file
+            .subs()
+            .flat_map(|c| &c.body)
+            .find_map(|c| match c {
+                Statement::Command { name, args } if name == "rest" => Some(args),
+                _ => None,
+            })
+            .unwrap();
+        assert!(
+            matches!(rest.last(), Some(Value::Integer(0x30))),
+            "{rest:?}"
+        );
+    }
+
+    #[test]
+    fn recursive_constants() {
+        let statements = parse("start:\nrest A + 1\nA = B\nB = A * 2\n");
+        assert!(matches!(
+            convert(&statements),
+            Err(ConvertError::RecursiveConstant(2, _))
+        ));
+    }
+
+    #[test]
+    fn used_labels_start_subs() {
+        let file = convert(&parse(
+            "#index 0x56\nstart:\n0x1 0\nunused:\ncall loop\nloop:\nrest 0x30\nasync_call elsewhere\nelsewhere:\nrest 1\n",
+        ))
+        .unwrap();
+        let subs: Vec<_> = file
+            .subs()
+            .map(|c| (c.name.as_str(), c.sync, c.body.len()))
+            .collect();
+        assert_eq!(
+            subs,
+            [
+                (START_SUB, false, 2),
+                ("loop", true, 2),
+                ("elsewhere", false, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn aliases_become_commands() {
+        let file = convert(&parse(
+            "#alias spin 0x4105\nstart:\nspin 1, 2\nspin<3> 4, 5\nspin 6\n",
+        ))
+        .unwrap();
+        let arg = |c: &str| Value::Identifier(c.to_string());
+        assert_eq!(
+            file.items[1],
+            Item::Command {
+                name: "spin".to_string(),
+                args: vec![
+                    ("arg1".to_string(), ArgType::Any),
+                    ("arg2".to_string(), ArgType::Any)
+                ],
+                body: Statement::RawOp {
+                    op: 0x105,
+                    arg0: 1,
+                    args: vec![arg("arg1"), arg("arg2")],
+                },
+            }
+        );
+        let body = &file.subs().next().unwrap().body;
+        assert_eq!(
+            body[..],
+            [
+                Statement::Command {
+                    name: "spin".to_string(),
+                    args: vec![Value::Integer(1), Value::Integer(2)],
+                },
+                // used differently from its first use, so it can't be the command
+                Statement::RawOp {
+                    op: 0x105,
+                    arg0: 3,
+                    args: vec![Value::Integer(4), Value::Integer(5)],
+                },
+                Statement::RawOp {
+                    op: 0x105,
+                    arg0: 1,
+                    args: vec![Value::Integer(6)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn operations_without_a_command_stay_raw() {
+        let file = convert(&parse("start:\n0x3FF<2> 1\nrest 0x30\n")).unwrap();
+        let body = &file.subs().next().unwrap().body;
+        assert_eq!(
+            body[..],
+            [
+                Statement::RawOp {
+                    op: 0x3FF,
+                    arg0: 2,
+                    args: vec![Value::Integer(1)],
+                },
+                // Tickscript commands without a preset arg0 take it as their first argument
+                Statement::Command {
+                    name: "rest".to_string(),
+                    args: vec![Value::Integer(0), Value::Integer(0x30)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_directives() {
+        let directive = |name: &str, args| {
+            let name = old::Identifier::new(name, "test.tickflow", 1).unwrap();
+            vec![(
+                1,
+                old::Statement::Directive { name, args },
+                old::StatementSpans::default(),
+            )]
+        };
+        let constant = old::Value::Constant(old::Identifier::new("CONST", "", 1).unwrap());
+        for statements in [
+            directive("alias", vec![constant.clone(), constant]),
+            directive("include", vec![old::Value::Integer(3)]),
+            directive("index", vec![]),
+        ] {
+            assert!(matches!(
+                convert(&statements),
+                Err(ConvertError::InvalidDirective(1, _))
+            ));
+        }
+    }
+}
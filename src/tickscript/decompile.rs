@@ -0,0 +1,663 @@
+//! Decompilation of Megamix BTKS files into Tickscript
+//!
+//! The FLOW section is split into subs at its start and at every call target. In every sub,
+//! `if`/`else`/`endif` and `switch`/`case`/`endswitch` become blocks, and a label followed by a
+//! `goto` to it becomes a `loop` (or a `while`, if the `goto` is at the end of an `if`).
+//!
+//! Every decompiled sub is lowered back with [`lower_sub`] and compared with the original
+//! operations. If they don't match, the sub is decompiled again without loops, and then without
+//! any blocks. If even that doesn't match, for example because a pointer doesn't point to the
+//! start of a sub, decompilation fails, so the output always compiles back to the same
+//! operations.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    ops::Range,
+};
+
+use tickflow_binaries::{
+    data::{btks::BTKS, Arg, Array, OperationSet, Pointer, TickflowOp},
+    extract::{symbols::SymbolMap, PointerType},
+    link::LinkItem,
+};
+use tickflow_parse::new::ast::{Case, Comparison, File, IntType, Item, Statement, Sub, Value};
+
+use super::{
+    lower::{lower_file, lower_sub, Commands, Constants, START_SUB},
+    statement_for_op,
+};
+use crate::data::megamix::MegamixOp;
+
+/// Decompiles a BTKS. `symbols` names the subs where possible, and the rest are named after
+/// their offset in the FLOW section.
+pub fn decompile(btks: &BTKS, symbols: Option<&SymbolMap>) -> io::Result<File> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let ops: Vec<_> = btks
+        .read_raw_ops::<MegamixOp>()?
+        .into_iter()
+        .zip(btks.to_tickflow_ops::<MegamixOp>()?)
+        .map(|((offset, raw), (_, op))| (offset, op, MegamixOp::try_from_raw(&raw)))
+        .collect();
+    let offsets: HashSet<u32> = ops.iter().map(|c| c.0).collect();
+
+    // subs start at every call target
+    let mut entries: HashSet<u32> = btks
+        .ptro
+        .iter()
+        .flatten()
+        .filter(|c| c.ptype == PointerType::Tickflow)
+        .map(|c| c.points_to)
+        .collect();
+    let mut sync_calls = HashSet::new();
+    let mut async_calls = HashSet::new();
+    for (_, op, decoded) in &ops {
+        for arg in &op.args {
+            if let Arg::Pointer(Pointer::Raw(c)) = arg {
+                entries.insert(*c);
+                if matches!(decoded, Some(MegamixOp::CallSync(_))) {
+                    sync_calls.insert(*c);
+                } else {
+                    async_calls.insert(*c);
+                }
+            }
+        }
+    }
+    entries.insert(btks.flow.start_offset);
+    if let Some((offset, ..)) = ops.first() {
+        entries.insert(*offset);
+    }
+    entries.retain(|c| offsets.contains(c));
+
+    let mut names = HashMap::new();
+    let mut used_names = HashSet::new();
+    let mut sorted_entries: Vec<u32> = entries.into_iter().collect();
+    sorted_entries.sort();
+    for offset in &sorted_entries {
+        let name = if *offset == btks.flow.start_offset {
+            START_SUB.to_string()
+        } else {
+            symbols
+                .and_then(|c| c.by_offset(*offset))
+                .and_then(|c| c.name.clone())
+                .filter(|c| is_identifier(c) && !used_names.contains(c))
+                .unwrap_or_else(|| format!("loc_{offset:x}"))
+        };
+        used_names.insert(name.clone());
+        names.insert(*offset, name);
+    }
+
+    let mut file = File {
+        items: vec![Item::Directive {
+            name: "tickscript".to_string(),
+            args: vec![],
+        }],
+    };
+    let consts = Constants::new();
+    let commands = Commands::new();
+    let mut start = 0;
+    while start < ops.len() {
+        let end = ops[start + 1..]
+            .iter()
+            .position(|c| names.contains_key(&c.0))
+            .map(|c| c + start + 1)
+            .unwrap_or(ops.len());
+        let offset = ops[start].0;
+        let sub_ops: Vec<_> = ops[start..end].iter().map(|(_, a, b)| (a, b)).collect();
+        let name = names[&offset].clone();
+
+        let expected = expected_items(&name, &sub_ops, &names);
+        let mut sub = Sub {
+            name,
+            sync: sync_calls.contains(&offset) && !async_calls.contains(&offset),
+            body: vec![],
+        };
+        for loops in [true, false] {
+            let Some(body) = Structurer::new(&sub_ops, &names, loops)
+                .and_then(|c| c.block(0..sub_ops.len(), false))
+            else {
+                continue;
+            };
+            sub.body = body;
+            if lower_sub(&sub, &consts, &commands).is_ok_and(|c| same_items(&c, &expected)) {
+                break;
+            }
+            sub.body.clear();
+        }
+        if sub.body.is_empty() {
+            sub.body = sub_ops
+                .iter()
+                .map(|c| plain(c.0, &names))
+                .collect::<Option<_>>()
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "{} has a pointer to an offset that isn't the start of a sub",
+                        sub.name
+                    ))
+                })?;
+            if !lower_sub(&sub, &consts, &commands).is_ok_and(|c| same_items(&c, &expected)) {
+                return Err(invalid(format!(
+                    "{} doesn't compile back to the same operations",
+                    sub.name
+                )));
+            }
+        }
+
+        file.items.push(Item::Sub(sub));
+        start = end;
+    }
+
+    let linked = lower_file(&file)
+        .map_err(|e| invalid(e.to_string()))?
+        .link::<MegamixOp>()
+        .map_err(|e| invalid(format!("{e:?}")))?;
+    if !same_btks(&linked, btks)? {
+        return Err(invalid(
+            "the decompiled file doesn't compile back to the same FLOW section".to_string(),
+        ));
+    }
+    Ok(file)
+}
+
+/// Whether two BTKS files have the same FLOW section. Data pointers are compared by the
+/// arguments they point to, since STRD may be laid out differently.
+fn same_btks(a: &BTKS, b: &BTKS) -> io::Result<bool> {
+    let pointers = |btks: &BTKS| {
+        let mut pointers: Vec<_> = btks
+            .ptro
+            .iter()
+            .flatten()
+            .map(|c| match c.ptype {
+                PointerType::Tickflow => (c.at, Some(c.points_to)),
+                PointerType::Data => (c.at, None),
+            })
+            .collect();
+        pointers.sort();
+        pointers
+    };
+    let (a_pointers, b_pointers) = (pointers(a), pointers(b));
+    if a.flow.start_offset != b.flow.start_offset
+        || a.flow.data.len() != b.flow.data.len()
+        || a_pointers != b_pointers
+    {
+        return Ok(false);
+    }
+
+    let data: HashSet<usize> = a_pointers
+        .iter()
+        .filter(|c| c.1.is_none())
+        .map(|c| c.0)
+        .collect();
+    let words_match = a
+        .flow
+        .data
+        .chunks(4)
+        .zip(b.flow.data.chunks(4))
+        .enumerate()
+        .all(|(i, (a, b))| a == b || data.contains(&(i * 4)));
+    let args_match = a
+        .to_tickflow_ops::<MegamixOp>()?
+        .iter()
+        .zip(&b.to_tickflow_ops::<MegamixOp>()?)
+        .all(|((_, a), (_, b))| {
+            a.args
+                .iter()
+                .zip(&b.args)
+                .all(|(a, b)| Word::from(a) == Word::from(b))
+        });
+    Ok(words_match && args_match)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Decompiles an argument, or returns `None` if it's a pointer to an offset without a name,
+/// which couldn't be relocated
+fn value(arg: &Arg, names: &HashMap<u32, String>) -> Option<Value> {
+    Some(match arg {
+        Arg::Signed(c) => Value::Integer(*c as i64),
+        Arg::Unsigned(c) | Arg::Unknown(c) => Value::Integer(*c as i64),
+        Arg::Pointer(Pointer::Raw(c)) => Value::Identifier(names.get(c)?.clone()),
+        Arg::Pointer(Pointer::Label(c)) => Value::Identifier(c.clone()),
+        Arg::String(c) => Value::String {
+            value: c.value.clone(),
+            is_unicode: c.is_unicode(),
+        },
+        Arg::Array(c) => {
+            macro_rules! array {
+                ($int_type:ident, $values:expr) => {
+                    Value::Array {
+                        int_type: Some(IntType::$int_type),
+                        values: $values.iter().map(|c| Value::Integer(*c as i64)).collect(),
+                    }
+                };
+            }
+            match c {
+                Array::Word(c) => array!(U32, c),
+                Array::SignedWord(c) => array!(I32, c),
+                Array::Byte(c) => array!(U8, c),
+                Array::SignedByte(c) => array!(I8, c),
+                Array::Half(c) => array!(U16, c),
+                Array::SignedHalf(c) => array!(I16, c),
+            }
+        }
+        Arg::Struct(c) => Value::Array {
+            int_type: Some(IntType::U8),
+            values: c.iter().map(|c| Value::Integer(*c as i64)).collect(),
+        },
+    })
+}
+
+/// Decompiles an operation as a command, or as a raw operation if it doesn't have a name
+fn plain(op: &TickflowOp, names: &HashMap<u32, String>) -> Option<Statement> {
+    let args = op
+        .args
+        .iter()
+        .map(|c| value(c, names))
+        .collect::<Option<_>>()?;
+    Some(statement_for_op(op.op, u32::from(op.arg0.clone()), args))
+}
+
+/// Link items of a sub exactly as extracted
+fn expected_items(
+    name: &str,
+    ops: &[(&TickflowOp, &Option<MegamixOp>)],
+    names: &HashMap<u32, String>,
+) -> Vec<LinkItem> {
+    let mut items = vec![LinkItem::Label(name.to_string())];
+    for (op, _) in ops {
+        let mut op = (*op).clone();
+        for arg in &mut op.args {
+            if let Arg::Pointer(Pointer::Raw(c)) = arg {
+                if let Some(name) = names.get(c) {
+                    *arg = Arg::Pointer(Pointer::Label(name.clone()));
+                }
+            }
+        }
+        items.push(LinkItem::Op(op));
+    }
+    items
+}
+
+/// Argument as written by the linker
+#[derive(Debug, PartialEq)]
+enum Word<'a> {
+    Integer(u32),
+    Raw(u32),
+    Label(&'a str),
+    String(&'a tickflow_binaries::data::strings::TickflowString),
+    Data(Vec<u8>),
+}
+
+impl<'a> From<&'a Arg> for Word<'a> {
+    fn from(arg: &'a Arg) -> Self {
+        match arg {
+            Arg::Signed(c) => Self::Integer(*c as u32),
+            Arg::Unsigned(c) | Arg::Unknown(c) => Self::Integer(*c),
+            Arg::Pointer(Pointer::Raw(c)) => Self::Raw(*c),
+            Arg::Pointer(Pointer::Label(c)) => Self::Label(c),
+            Arg::String(c) => Self::String(c),
+            Arg::Array(c) => Self::Data(c.to_bytes(MegamixOp::ENDIAN)),
+            Arg::Struct(c) => Self::Data(c.clone()),
+        }
+    }
+}
+
+/// Whether two lists of items would be linked into the same bytes
+fn same_items(a: &[LinkItem], b: &[LinkItem]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|c| match c {
+            (LinkItem::Label(a), LinkItem::Label(b)) => a == b,
+            (LinkItem::Op(a), LinkItem::Op(b)) => {
+                a.op == b.op
+                    && u32::from(a.arg0.clone()) == u32::from(b.arg0.clone())
+                    && a.args.len() == b.args.len()
+                    && a.args
+                        .iter()
+                        .zip(&b.args)
+                        .all(|(a, b)| Word::from(a) == Word::from(b))
+            }
+            _ => false,
+        })
+}
+
+/// Recovers the blocks of a sub
+struct Structurer<'a> {
+    ops: &'a [(&'a TickflowOp, &'a Option<MegamixOp>)],
+    names: &'a HashMap<u32, String>,
+    /// Position of the `else` (if any) and the end of every block, by the position of its start
+    ends: HashMap<usize, (Option<usize>, usize)>,
+    loops: bool,
+    /// How many times every label number is set or jumped to
+    label_uses: HashMap<u32, usize>,
+}
+
+impl<'a> Structurer<'a> {
+    /// Matches every block start with its end, or returns `None` if they aren't balanced
+    fn new(
+        ops: &'a [(&'a TickflowOp, &'a Option<MegamixOp>)],
+        names: &'a HashMap<u32, String>,
+        loops: bool,
+    ) -> Option<Self> {
+        let mut ends = HashMap::new();
+        let mut label_uses = HashMap::new();
+        let mut stack: Vec<(usize, bool, Option<usize>)> = vec![];
+        for (i, (_, op)) in ops.iter().enumerate() {
+            match op {
+                Some(c) if condition(c).is_some() => stack.push((i, false, None)),
+                Some(MegamixOp::Switch) => stack.push((i, true, None)),
+                Some(MegamixOp::Else) => match stack.last_mut() {
+                    Some((_, false, c @ None)) => *c = Some(i),
+                    _ => return None,
+                },
+                Some(MegamixOp::EndIf) => match stack.pop() {
+                    Some((start, false, otherwise)) => {
+                        ends.insert(start, (otherwise, i));
+                    }
+                    _ => return None,
+                },
+                Some(MegamixOp::EndSwitch) => match stack.pop() {
+                    Some((start, true, _)) => {
+                        ends.insert(start, (None, i));
+                    }
+                    _ => return None,
+                },
+                Some(MegamixOp::Label(c) | MegamixOp::Goto(c)) => {
+                    *label_uses.entry(*c).or_default() += 1
+                }
+                _ => {}
+            }
+        }
+        stack.is_empty().then_some(Self {
+            ops,
+            names,
+            ends,
+            loops,
+            label_uses,
+        })
+    }
+
+    fn op(&self, i: usize) -> Option<&MegamixOp> {
+        self.ops.get(i).and_then(|c| c.1.as_ref())
+    }
+
+    /// First argument of an operation, as a value
+    fn first_arg(&self, i: usize) -> Option<Value> {
+        value(self.ops[i].0.args.first()?, self.names)
+    }
+
+    /// Position right after the block or operation at `i`
+    fn skip(&self, i: usize) -> usize {
+        self.ends.get(&i).map(|c| c.1).unwrap_or(i) + 1
+    }
+
+    fn block(&self, range: Range<usize>, in_switch: bool) -> Option<Vec<Statement>> {
+        let mut statements = vec![];
+        let mut i = range.start;
+        while i < range.end {
+            let next = self.skip(i);
+            if next > range.end {
+                return None;
+            }
+            match self.op(i) {
+                Some(c) if condition(c).is_some() => {
+                    let (otherwise, end) = self.ends[&i];
+                    statements.push(Statement::If {
+                        cmp: condition(c)?,
+                        value: self.first_arg(i)?,
+                        then: self.block(i + 1..otherwise.unwrap_or(end), in_switch)?,
+                        otherwise: match otherwise {
+                            Some(c) => Some(self.block(c + 1..end, in_switch)?),
+                            None => None,
+                        },
+                    });
+                }
+                Some(MegamixOp::Switch) => {
+                    statements.push(Statement::Switch(self.cases(i + 1..next - 1)?));
+                }
+                Some(MegamixOp::BreakCase) if in_switch => statements.push(Statement::Break),
+                Some(MegamixOp::Label(c)) if self.loops && self.label_uses[c] == 2 => {
+                    match self.loop_at(i, *c, range.end, in_switch) {
+                        Some((statement, end)) => {
+                            statements.push(statement);
+                            i = end;
+                            continue;
+                        }
+                        None => statements.push(plain(self.ops[i].0, self.names)?),
+                    }
+                }
+                Some(
+                    MegamixOp::Else
+                    | MegamixOp::EndIf
+                    | MegamixOp::Case(_)
+                    | MegamixOp::BreakCase
+                    | MegamixOp::DefaultCase
+                    | MegamixOp::EndSwitch,
+                ) => return None,
+                _ => statements.push(plain(self.ops[i].0, self.names)?),
+            }
+            i = next;
+        }
+        Some(statements)
+    }
+
+    /// Recovers a loop starting with `label` at `i`, returning it and the position after it
+    fn loop_at(
+        &self,
+        i: usize,
+        label: u32,
+        end: usize,
+        in_switch: bool,
+    ) -> Option<(Statement, usize)> {
+        let is_goto = |c: usize| matches!(self.op(c), Some(MegamixOp::Goto(c)) if *c == label);
+
+        // while: the label is followed by an if that ends with the goto
+        if let Some(cmp) = self.op(i + 1).and_then(condition) {
+            if let Some((None, if_end)) = self.ends.get(&(i + 1)) {
+                if *if_end < end && *if_end > i + 2 && is_goto(if_end - 1) {
+                    return Some((
+                        Statement::While {
+                            cmp,
+                            value: self.first_arg(i + 1)?,
+                            body: self.block(i + 2..if_end - 1, in_switch)?,
+                        },
+                        if_end + 1,
+                    ));
+                }
+            }
+        }
+
+        // loop: the goto is in the same block as the label
+        let mut j = i + 1;
+        while j < end {
+            if is_goto(j) {
+                return Some((Statement::Loop(self.block(i + 1..j, in_switch)?), j + 1));
+            }
+            j = self.skip(j);
+        }
+        None
+    }
+
+    fn cases(&self, range: Range<usize>) -> Option<Vec<Case>> {
+        let mut starts = vec![];
+        let mut i = range.start;
+        while i < range.end {
+            if matches!(
+                self.op(i),
+                Some(MegamixOp::Case(_) | MegamixOp::DefaultCase)
+            ) {
+                starts.push(i);
+            } else if starts.is_empty() {
+                // operations before the first case can't be written in a switch block
+                return None;
+            }
+            i = self.skip(i);
+        }
+
+        let mut cases = vec![];
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(range.end);
+            cases.push(Case {
+                value: match self.op(*start) {
+                    Some(MegamixOp::Case(_)) => Some(self.first_arg(*start)?),
+                    _ => None,
+                },
+                body: self.block(start + 1..end, true)?,
+            });
+        }
+        Some(cases)
+    }
+}
+
+/// Comparison made by a conditional operation
+fn condition(op: &MegamixOp) -> Option<Comparison> {
+    Some(match op {
+        MegamixOp::IfEq(_) => Comparison::Eq,
+        MegamixOp::IfNe(_) => Comparison::Ne,
+        MegamixOp::IfLt(_) => Comparison::Lt,
+        MegamixOp::IfLe(_) => Comparison::Le,
+        MegamixOp::IfGt(_) => Comparison::Gt,
+        MegamixOp::IfGe(_) => Comparison::Ge,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::{
+        data::btks::{BtksType, FlowSection},
+        extract::Pointer as PtroPointer,
+    };
+
+    use super::*;
+
+    /// BTKS with a sub that calls the offset `call`, and has a `while` and a `switch`, and
+    /// another sub right after it
+    fn btks(call: u32) -> BTKS {
+        let ops: [(u16, u32, &[u32]); 14] = [
+            (2, 0, &[call]),
+            // while
+            (0x14, 0, &[0]),
+            (0x16, 0, &[3]),
+            (0xE, 0x30, &[]),
+            (0x15, 0, &[0]),
+            (0x18, 0, &[]),
+            // switch
+            (0x19, 0, &[]),
+            (0x1A, 0, &[1]),
+            (0xE, 1, &[]),
+            (0x1B, 0, &[]),
+            (0x1D, 0, &[]),
+            (7, 0, &[]),
+            // called sub, at 68
+            (0xE, 2, &[]),
+            (7, 0, &[]),
+        ];
+        BTKS {
+            btks_type: BtksType::MegamixIntl,
+            flow: FlowSection {
+                start_offset: 0,
+                data: flow(&ops),
+            },
+            ptro: Some(vec![PtroPointer {
+                at: 4,
+                points_to: call,
+                ptype: PointerType::Tickflow,
+            }]),
+            tmpo: None,
+            strd: vec![],
+        }
+    }
+
+    /// FLOW section with the given operations as `(op, arg0, args)`
+    fn flow(ops: &[(u16, u32, &[u32])]) -> Vec<u8> {
+        let mut data = vec![];
+        for (op, arg0, args) in ops {
+            let op_int = *op as u32 | (args.len() as u32) << 10 | arg0 << 14;
+            data.extend(op_int.to_le_bytes());
+            for arg in *args {
+                data.extend(arg.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn decompiles_back_to_the_same_bytes() {
+        let btks = btks(68);
+        let file = decompile(&btks, None).unwrap();
+        let linked = lower_file(&file).unwrap().link::<MegamixOp>().unwrap();
+        assert_eq!(linked.flow.start_offset, btks.flow.start_offset);
+        assert_eq!(linked.flow.data, btks.flow.data);
+        let pointers = |btks: &BTKS| -> Vec<_> {
+            btks.ptro
+                .iter()
+                .flatten()
+                .map(|c| (c.at, c.points_to, c.ptype == PointerType::Tickflow))
+                .collect()
+        };
+        assert_eq!(pointers(&linked), pointers(&btks));
+
+        let Some(Item::Sub(sub)) = file.items.get(1) else {
+            panic!("no sub");
+        };
+        assert!(matches!(sub.body[1], Statement::While { .. }));
+        assert!(matches!(sub.body[2], Statement::Switch(_)));
+    }
+
+    #[test]
+    fn pointers_inside_operations_are_errors() {
+        // points to the argument of the first operation
+        assert!(decompile(&btks(4), None).is_err());
+    }
+
+    #[test]
+    fn keeps_null_strings_as_integers() {
+        // a null string and a relocated one
+        let btks = BTKS {
+            btks_type: BtksType::MegamixIntl,
+            flow: FlowSection {
+                start_offset: 0,
+                data: flow(&[(0x31, 0, &[5, 0]), (0x31, 0, &[7, 0]), (7, 0, &[])]),
+            },
+            ptro: Some(vec![PtroPointer {
+                at: 20,
+                points_to: 0,
+                ptype: PointerType::Data,
+            }]),
+            tmpo: None,
+            strd: vec![b'h', 0, b'i', 0, 0, 0, 0, 0],
+        };
+        let file = decompile(&btks, None).unwrap();
+        let Some(Item::Sub(sub)) = file.items.get(1) else {
+            panic!("no sub");
+        };
+        let args: Vec<_> = sub.body[..2]
+            .iter()
+            .map(|c| match c {
+                Statement::RawOp { args, .. } | Statement::Command { args, .. } => args[1].clone(),
+                c => panic!("{c:?} isn't an operation"),
+            })
+            .collect();
+        assert_eq!(
+            args,
+            [
+                Value::Integer(0),
+                Value::String {
+                    value: "hi".to_string(),
+                    is_unicode: true
+                }
+            ]
+        );
+
+        let linked = lower_file(&file).unwrap().link::<MegamixOp>().unwrap();
+        assert_eq!(linked.flow.data, btks.flow.data);
+        assert_eq!(linked.strd, btks.strd);
+    }
+}
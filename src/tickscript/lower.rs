@@ -0,0 +1,371 @@
+//! Lowering of Tickscript into Megamix operations
+//!
+//! Every sub becomes a label followed by its operations, ready for the [`Linker`]. Blocks are
+//! lowered like this:
+//!
+//! - `if`: `if<cmp> value`, the block, then `else` and the `else` block if there's one, and
+//!   `endif`
+//! - `switch`: `switch`, then `case value` or `default` followed by the block of every case, and
+//!   `endswitch`
+//! - `loop`: `label n`, the block, and `goto n`
+//! - `while`: `label n`, `if<cmp> value`, the block, `goto n` and `endif`
+//!
+//! Loop labels are numbered from 0 in every sub, skipping the ones used by `label` commands.
+//!
+//! Commands defined in the file are lowered as their body, with the names of their arguments
+//! replaced by the values they're given. Argument types aren't checked.
+
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+use tickflow_binaries::{
+    data::{
+        strings::{Encoding, TickflowString},
+        Arg, Arg0, Array, Pointer, TickflowOp,
+    },
+    link::{LinkItem, Linker},
+};
+use tickflow_parse::new::ast::{ArgType, File, IntType, Item, Statement, Sub, Value};
+
+use super::{
+    command_by_name, comparison_arg0, OP_BREAK, OP_CASE, OP_DEFAULT, OP_ELSE, OP_END_IF,
+    OP_END_SWITCH, OP_GOTO, OP_IF, OP_LABEL, OP_SWITCH,
+};
+
+/// Name of the sub the FLOW section starts executing from
+pub const START_SUB: &str = "_start";
+
+#[derive(Debug, Error)]
+pub enum LowerError {
+    #[error("unknown command \"{0}\"")]
+    UnknownCommand(String),
+    #[error("command \"{0}\" takes its arg0 as the first argument, which must be an integer")]
+    MissingArg0(String),
+    #[error("{0} is out of range")]
+    IntegerRange(i64),
+    #[error("arrays passed to commands can only contain integers")]
+    InvalidArray,
+    #[error("constant \"{0}\" is defined in terms of itself")]
+    RecursiveConstant(String),
+    #[error("command \"{0}\" takes {1} arguments, but was given {2}")]
+    ArgumentCount(String, usize, usize),
+    #[error("command \"{0}\" is defined in terms of itself")]
+    RecursiveCommand(String),
+}
+
+pub type Result<T> = std::result::Result<T, LowerError>;
+
+/// Constants defined in a file, by name
+pub type Constants<'a> = HashMap<&'a str, &'a Value>;
+
+/// Commands defined in a file, by name, with their arguments and body
+pub type Commands<'a> = HashMap<&'a str, (&'a [(String, ArgType)], &'a Statement)>;
+
+/// Lowers every sub in a file, in order. Execution starts at the sub named [`START_SUB`], or at
+/// the first one if there isn't one.
+pub fn lower_file(file: &File) -> Result<Linker> {
+    let consts: Constants = file
+        .items
+        .iter()
+        .filter_map(|c| match c {
+            Item::Const { name, value } => Some((name.as_str(), value)),
+            _ => None,
+        })
+        .collect();
+    let commands: Commands = file
+        .items
+        .iter()
+        .filter_map(|c| match c {
+            Item::Command { name, args, body } => Some((name.as_str(), (&args[..], body))),
+            _ => None,
+        })
+        .collect();
+
+    let mut linker = Linker::new();
+    for sub in file.subs() {
+        linker.items.extend(lower_sub(sub, &consts, &commands)?);
+        if sub.name == START_SUB {
+            linker.start_label = Some(sub.name.clone());
+        }
+    }
+    Ok(linker)
+}
+
+/// Lowers a sub into its label and operations. Identifiers that aren't in `consts` are taken as
+/// the names of subs.
+pub fn lower_sub(sub: &Sub, consts: &Constants, commands: &Commands) -> Result<Vec<LinkItem>> {
+    let mut used_labels = HashSet::new();
+    explicit_labels(&sub.body, &mut used_labels);
+    let mut lowerer = Lowerer {
+        items: vec![LinkItem::Label(sub.name.clone())],
+        consts,
+        commands,
+        expanding: vec![],
+        used_labels,
+        next_label: 0,
+    };
+    lowerer.block(&sub.body)?;
+    Ok(lowerer.items)
+}
+
+/// Finds the numbers of every label set with a `label` command or a raw operation
+fn explicit_labels(statements: &[Statement], labels: &mut HashSet<u32>) {
+    for statement in statements {
+        match statement {
+            Statement::Command { name, args } if name == "label" => {
+                if let Some(Value::Integer(c)) = args.first() {
+                    labels.insert(*c as u32);
+                }
+            }
+            Statement::RawOp { op, args, .. } if *op == OP_LABEL => {
+                if let Some(Value::Integer(c)) = args.first() {
+                    labels.insert(*c as u32);
+                }
+            }
+            Statement::If {
+                then, otherwise, ..
+            } => {
+                explicit_labels(then, labels);
+                explicit_labels(otherwise.as_deref().unwrap_or_default(), labels);
+            }
+            Statement::Switch(cases) => {
+                for case in cases {
+                    explicit_labels(&case.body, labels);
+                }
+            }
+            Statement::While { body, .. } | Statement::Loop(body) => explicit_labels(body, labels),
+            _ => {}
+        }
+    }
+}
+
+struct Lowerer<'a> {
+    items: Vec<LinkItem>,
+    consts: &'a Constants<'a>,
+    commands: &'a Commands<'a>,
+    /// Defined commands being lowered, to catch the ones that use themselves
+    expanding: Vec<&'a str>,
+    used_labels: HashSet<u32>,
+    next_label: u32,
+}
+
+impl Lowerer<'_> {
+    fn push(&mut self, op: u16, arg0: u32, args: Vec<Arg>) {
+        self.items.push(LinkItem::Op(TickflowOp {
+            op,
+            arg0: Arg0::Unsigned(arg0),
+            args,
+            scene: -1,
+        }));
+    }
+
+    fn new_label(&mut self) -> Arg {
+        while self.used_labels.contains(&self.next_label) {
+            self.next_label += 1;
+        }
+        self.next_label += 1;
+        Arg::Unsigned(self.next_label - 1)
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<()> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::Command { name, args } if command_by_name(name).is_none() => {
+                let Some((&name, &(params, body))) = self.commands.get_key_value(name.as_str())
+                else {
+                    return Err(LowerError::UnknownCommand(name.clone()));
+                };
+                if params.len() != args.len() {
+                    return Err(LowerError::ArgumentCount(
+                        name.to_string(),
+                        params.len(),
+                        args.len(),
+                    ));
+                }
+                if self.expanding.contains(&name) {
+                    return Err(LowerError::RecursiveCommand(name.to_string()));
+                }
+                let values: HashMap<&str, &Value> =
+                    params.iter().map(|c| c.0.as_str()).zip(args).collect();
+                self.expanding.push(name);
+                self.statement(&substitute(body, &values))?;
+                self.expanding.pop();
+            }
+            Statement::Command { name, args } => {
+                let def = command_by_name(name)
+                    .ok_or_else(|| LowerError::UnknownCommand(name.clone()))?;
+                let (arg0, args) = match def.arg0 {
+                    Some(c) => (c, &args[..]),
+                    None => match args.split_first().map(|(c, rest)| (self.resolve(c), rest)) {
+                        Some((Ok(Value::Integer(c)), rest))
+                            if (0..=u32::MAX as i64).contains(c) =>
+                        {
+                            (*c as u32, rest)
+                        }
+                        _ => return Err(LowerError::MissingArg0(name.clone())),
+                    },
+                };
+                let args = self.args(args)?;
+                self.push(def.op, arg0, args);
+            }
+            Statement::RawOp { op, arg0, args } => {
+                let args = self.args(args)?;
+                self.push(*op, *arg0, args);
+            }
+            Statement::If {
+                cmp,
+                value,
+                then,
+                otherwise,
+            } => {
+                let value = self.arg(value)?;
+                self.push(OP_IF, comparison_arg0(*cmp), vec![value]);
+                self.block(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.push(OP_ELSE, 0, vec![]);
+                    self.block(otherwise)?;
+                }
+                self.push(OP_END_IF, 0, vec![]);
+            }
+            Statement::Switch(cases) => {
+                self.push(OP_SWITCH, 0, vec![]);
+                for case in cases {
+                    match &case.value {
+                        Some(c) => {
+                            let value = self.arg(c)?;
+                            self.push(OP_CASE, 0, vec![value]);
+                        }
+                        None => self.push(OP_DEFAULT, 0, vec![]),
+                    }
+                    self.block(&case.body)?;
+                }
+                self.push(OP_END_SWITCH, 0, vec![]);
+            }
+            Statement::Break => self.push(OP_BREAK, 0, vec![]),
+            Statement::While { cmp, value, body } => {
+                let label = self.new_label();
+                let value = self.arg(value)?;
+                self.push(OP_LABEL, 0, vec![label.clone()]);
+                self.push(OP_IF, comparison_arg0(*cmp), vec![value]);
+                self.block(body)?;
+                self.push(OP_GOTO, 0, vec![label]);
+                self.push(OP_END_IF, 0, vec![]);
+            }
+            Statement::Loop(body) => {
+                let label = self.new_label();
+                self.push(OP_LABEL, 0, vec![label.clone()]);
+                self.block(body)?;
+                self.push(OP_GOTO, 0, vec![label]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces constants with their values
+    fn resolve<'v>(&'v self, mut value: &'v Value) -> Result<&'v Value> {
+        let mut seen = 0;
+        while let Value::Identifier(name) = value {
+            match self.consts.get(name.as_str()) {
+                Some(c) if seen < self.consts.len() => {
+                    value = c;
+                    seen += 1;
+                }
+                Some(_) => return Err(LowerError::RecursiveConstant(name.clone())),
+                None => break,
+            }
+        }
+        Ok(value)
+    }
+
+    fn args(&self, values: &[Value]) -> Result<Vec<Arg>> {
+        values.iter().map(|c| self.arg(c)).collect()
+    }
+
+    fn arg(&self, value: &Value) -> Result<Arg> {
+        Ok(match self.resolve(value)? {
+            Value::Integer(c) => {
+                if *c < 0 {
+                    Arg::Signed(i32::try_from(*c).map_err(|_| LowerError::IntegerRange(*c))?)
+                } else {
+                    Arg::Unsigned(u32::try_from(*c).map_err(|_| LowerError::IntegerRange(*c))?)
+                }
+            }
+            Value::String { value, is_unicode } => Arg::String(if *is_unicode {
+                TickflowString::new(value.clone(), Encoding::Utf16)
+            } else {
+                value.clone().into()
+            }),
+            Value::Array { int_type, values } => Arg::Array(self.array(*int_type, values)?),
+            Value::Identifier(c) => Arg::Pointer(Pointer::Label(c.clone())),
+        })
+    }
+
+    fn array(&self, int_type: Option<IntType>, values: &[Value]) -> Result<Array> {
+        let values = values
+            .iter()
+            .map(|c| match self.resolve(c)? {
+                Value::Integer(c) => Ok(*c),
+                _ => Err(LowerError::InvalidArray),
+            })
+            .collect::<Result<Vec<i64>>>()?;
+        macro_rules! convert {
+            ($variant:ident) => {
+                Array::$variant(
+                    values
+                        .iter()
+                        .map(|c| (*c).try_into().map_err(|_| LowerError::IntegerRange(*c)))
+                        .collect::<Result<_>>()?,
+                )
+            };
+        }
+        Ok(match int_type.unwrap_or(IntType::U32) {
+            IntType::U8 => convert!(Byte),
+            IntType::U16 => convert!(Half),
+            IntType::U32 => convert!(Word),
+            IntType::I8 => convert!(SignedByte),
+            IntType::I16 => convert!(SignedHalf),
+            IntType::I32 => convert!(SignedWord),
+        })
+    }
+}
+
+/// Replaces the names of the arguments of a defined command in its body
+fn substitute(statement: &Statement, values: &HashMap<&str, &Value>) -> Statement {
+    let substitute_all =
+        |args: &[Value]| args.iter().map(|c| substitute_value(c, values)).collect();
+    match statement {
+        Statement::Command { name, args } => Statement::Command {
+            name: name.clone(),
+            args: substitute_all(args),
+        },
+        Statement::RawOp { op, arg0, args } => Statement::RawOp {
+            op: *op,
+            arg0: *arg0,
+            args: substitute_all(args),
+        },
+        c => c.clone(),
+    }
+}
+
+fn substitute_value(value: &Value, values: &HashMap<&str, &Value>) -> Value {
+    match value {
+        Value::Identifier(c) => values
+            .get(c.as_str())
+            .map(|c| (*c).clone())
+            .unwrap_or_else(|| value.clone()),
+        Value::Array {
+            int_type,
+            values: c,
+        } => Value::Array {
+            int_type: *int_type,
+            values: c.iter().map(|c| substitute_value(c, values)).collect(),
+        },
+        c => c.clone(),
+    }
+}
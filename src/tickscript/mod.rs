@@ -0,0 +1,106 @@
+//! Tickscript support for Rhythm Heaven Megamix
+//!
+//! [`decompile`] turns a BTKS into Tickscript, recovering `if`, `switch`, `while` and `loop`
+//! blocks, and [`lower`] turns Tickscript back into operations for the [`Linker`]. [`convert`]
+//! turns old Tickflow source into Tickscript.
+//!
+//! [`Linker`]: tickflow_binaries::link::Linker
+
+pub mod convert;
+pub mod decompile;
+pub mod lower;
+
+use tickflow_binaries::data::OperationSet;
+use tickflow_parse::new::ast::{Comparison, Statement, Value};
+
+use crate::data::megamix::MegamixOp;
+
+/// Tickscript command for a Megamix operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandDef {
+    pub name: &'static str,
+    pub op: u16,
+    /// Preset arg0, or `None` if the arg0 is given as the first argument of the command
+    pub arg0: Option<u32>,
+}
+
+/// Every named command, which are the aliases of [`MegamixOp`]. Control flow operations aren't
+/// here, since they're written with `if`/`switch`/`while`/`loop` blocks.
+pub fn commands() -> impl Iterator<Item = CommandDef> {
+    MegamixOp::op_index()
+        .aliases
+        .iter()
+        .filter(|(_, def)| !(OP_IF..=OP_END_SWITCH).contains(&def.op))
+        .map(|(name, def)| CommandDef {
+            name,
+            op: def.op,
+            arg0: def.arg0,
+        })
+}
+
+pub fn command_by_name(name: &str) -> Option<CommandDef> {
+    commands().find(|c| c.name == name)
+}
+
+/// Finds the command for an operation, preferring one with a preset arg0
+pub fn command_by_op(op: u16, arg0: u32) -> Option<CommandDef> {
+    commands()
+        .find(|c| c.op == op && c.arg0 == Some(arg0))
+        .or_else(|| commands().find(|c| c.op == op && c.arg0.is_none()))
+}
+
+/// Statement for an operation: its command if it has one, or a raw operation otherwise
+pub fn statement_for_op(op: u16, arg0: u32, args: Vec<Value>) -> Statement {
+    match command_by_op(op, arg0) {
+        Some(def) if def.arg0.is_some() => Statement::Command {
+            name: def.name.to_string(),
+            args,
+        },
+        Some(def) => Statement::Command {
+            name: def.name.to_string(),
+            args: [Value::Integer(arg0 as i64)]
+                .into_iter()
+                .chain(args)
+                .collect(),
+        },
+        None => Statement::RawOp { op, arg0, args },
+    }
+}
+
+// Control flow operations
+pub(crate) const OP_LABEL: u16 = 0x14;
+pub(crate) const OP_GOTO: u16 = 0x15;
+pub(crate) const OP_IF: u16 = 0x16;
+pub(crate) const OP_ELSE: u16 = 0x17;
+pub(crate) const OP_END_IF: u16 = 0x18;
+pub(crate) const OP_SWITCH: u16 = 0x19;
+pub(crate) const OP_CASE: u16 = 0x1A;
+pub(crate) const OP_BREAK: u16 = 0x1B;
+pub(crate) const OP_DEFAULT: u16 = 0x1C;
+pub(crate) const OP_END_SWITCH: u16 = 0x1D;
+
+/// Arg0 of the `if` operation for every comparison
+pub(crate) fn comparison_arg0(cmp: Comparison) -> u32 {
+    match cmp {
+        Comparison::Eq => 0,
+        Comparison::Ne => 1,
+        Comparison::Lt => 2,
+        Comparison::Le => 3,
+        Comparison::Gt => 4,
+        Comparison::Ge => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_the_aliases() {
+        assert_eq!(command_by_op(2, 0).unwrap().name, "async_call");
+        assert_eq!(command_by_op(0xE, 0x30).unwrap().name, "rest");
+        assert_eq!(command_by_name("setrest").unwrap().op, 0xF);
+        // control flow is written with blocks
+        assert!(command_by_name("if").is_none());
+    }
+}
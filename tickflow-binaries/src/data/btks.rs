@@ -1,11 +1,20 @@
-use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::{
+    collections::HashSet,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+};
 
-use crate::{data::{TickflowOp}, extract::{self, Pointer}};
+use crate::{
+    data::{Array, ArrayType, OperationSet, RawTickflowOp, TickflowOp},
+    extract::{self, Pointer},
+};
 
-use bytestream::{ByteOrder, StreamWriter};
+use bytestream::{ByteOrder, StreamReader, StreamWriter};
 
 type Result<T> = std::io::Result<T>; //TODO: make my own error type
 
+/// Sample rate of the tempos in a BTKS file, which isn't stored in the file itself
+pub const DEFAULT_SAMPLE_RATE: u32 = 32000;
+
 #[derive(Debug, Clone)]
 pub struct BTKS {
     pub btks_type: BtksType,
@@ -21,14 +30,24 @@ pub struct FlowSection {
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Tempo {
     pub id: u32,
     pub data: Vec<TempoVal>,
     pub sample_rate: u32,
+    pub stream: TempoStream,
 }
 
-#[derive(Debug, Clone)]
+/// Kind of audio a tempo is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoStream {
+    /// Streamed audio (AAC)
+    Streamed,
+    /// Sequenced audio (BCGRP)
+    Sequence,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TempoVal {
     pub beats: f32,
     pub time: u32, // in samples
@@ -48,6 +67,21 @@ pub enum BtksType {
     Unspecified = -1,
 }
 
+impl BtksType {
+    pub fn from_i32(value: i32) -> Self {
+        match value {
+            0 => Self::MegamixIntl,
+            1 => Self::MegamixJp,
+            2 => Self::FeverJp,
+            3 => Self::FeverUs,
+            4 => Self::FeverEu,
+            5 => Self::FeverKr,
+            6 => Self::Gold,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
 impl BTKS {
     const REVISION: u32 = 2;
     const HEADER_SIZE: u32 = 0x18;
@@ -104,10 +138,11 @@ impl BTKS {
         if let Some(c) = &self.tmpo {
             num_sections += 1;
             f.write_all(b"TMPO")?; //magic
-            let mut tmpo_size: u32 = Self::TMPO_HEADER + c.len() as u32 * 8;
+            let mut tmpo_size: u32 = Self::TMPO_HEADER + c.len() as u32 * 0xC;
             for tempo in c {
-                tmpo_size += tempo.data.len() as u32 * 0x10;
+                tmpo_size += tempo.data.len() as u32 * 0xC;
             }
+            size += tmpo_size;
             tmpo_size.write_to(f, endian)?;
             (c.len() as u32).write_to(f, endian)?;
             for tempo in c {
@@ -133,6 +168,152 @@ impl BTKS {
         Ok(())
     }
 
+    pub fn from_btks_file<F: Read + Seek>(f: &mut F, endian: ByteOrder) -> Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // ------------
+        //    Header
+        // ------------
+        let mut magic = [0; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != b"BTKS" {
+            return Err(invalid("not a BTKS file"));
+        }
+        u32::read_from(f, endian)?; // filesize
+        if u32::read_from(f, endian)? != Self::REVISION {
+            return Err(invalid("unsupported BTKS revision"));
+        }
+        let header_size = u32::read_from(f, endian)?;
+        let num_sections = u32::read_from(f, endian)?;
+        let btks_type = BtksType::from_i32(i32::read_from(f, endian)?);
+        f.seek(SeekFrom::Start(header_size as u64))?;
+
+        let mut flow = None;
+        let mut ptro = None;
+        let mut tmpo = None;
+        let mut strd = None;
+        for _ in 0..num_sections {
+            let section_pos = f.stream_position()?;
+            f.read_exact(&mut magic)?;
+            let section_size = u32::read_from(f, endian)?;
+            match &magic {
+                b"FLOW" => {
+                    let start_offset = u32::read_from(f, endian)?;
+                    let mut data = vec![0; section_size.saturating_sub(Self::FLOW_HEADER) as usize];
+                    f.read_exact(&mut data)?;
+                    flow = Some(FlowSection { start_offset, data });
+                }
+                b"PTRO" => {
+                    let mut pointers = vec![];
+                    for _ in 0..u32::read_from(f, endian)? {
+                        let at = u32::read_from(f, endian)? as usize;
+                        let ptype = match u8::read_from(f, endian)? {
+                            0 => extract::PointerType::Data,
+                            1 => extract::PointerType::Tickflow,
+                            _ => return Err(invalid("invalid pointer type in PTRO section")),
+                        };
+                        pointers.push(Pointer {
+                            at,
+                            points_to: 0,
+                            ptype,
+                        });
+                    }
+                    ptro = Some(pointers);
+                }
+                b"TMPO" => {
+                    let mut tempos = vec![];
+                    for _ in 0..u32::read_from(f, endian)? {
+                        tempos.push(Tempo::read_from(f, endian)?);
+                    }
+                    tmpo = Some(tempos);
+                }
+                b"STRD" => {
+                    let mut data = vec![0; section_size.saturating_sub(Self::STRD_HEADER) as usize];
+                    f.read_exact(&mut data)?;
+                    strd = Some(data);
+                }
+                _ => return Err(invalid("unknown BTKS section")),
+            }
+            f.seek(SeekFrom::Start(section_pos + section_size as u64))?;
+        }
+
+        let Some(flow) = flow else {
+            return Err(invalid("missing FLOW section"));
+        };
+        // the pointers' targets are stored in FLOW itself
+        if let Some(pointers) = &mut ptro {
+            for pointer in pointers {
+                let mut value = flow
+                    .data
+                    .get(pointer.at..pointer.at + 4)
+                    .ok_or_else(|| invalid("pointer out of bounds of the FLOW section"))?;
+                pointer.points_to = u32::read_from(&mut value, endian)?;
+            }
+        }
+
+        Ok(Self {
+            btks_type,
+            flow,
+            ptro,
+            tmpo,
+            strd: strd.unwrap_or_default(),
+        })
+    }
+
+    /// Reads an array stored in the STRD section
+    pub fn read_array(
+        &self,
+        offset: u32,
+        atype: ArrayType,
+        len: Option<u32>,
+        endian: ByteOrder,
+    ) -> Result<Array> {
+        let mut data = Cursor::new(&self.strd);
+        data.set_position(offset as u64);
+        Array::read_from(&mut data, atype, len, endian)
+    }
+
+    /// Reads every operation in the FLOW section along with its offset, keeping track of the
+    /// current scene
+    pub fn read_raw_ops<T: OperationSet>(&self) -> Result<Vec<(u32, RawTickflowOp)>> {
+        let mut data = Cursor::new(&self.flow.data);
+        let mut ops = vec![];
+        let mut scene = -1;
+        while data.position() != data.get_ref().len() as u64 {
+            let offset = data.position() as u32;
+            let mut raw_op = extract::binary_to_raw_tf_op(&mut data, scene, T::ENDIAN)?.1;
+            if let Some(c) = T::is_scene_operation(&raw_op) {
+                scene = if c == -1 {
+                    raw_op.arg0
+                } else {
+                    raw_op.args.get(c as usize).copied().unwrap_or_default()
+                } as i32;
+                raw_op.scene = scene;
+            }
+            ops.push((offset, raw_op));
+        }
+        Ok(ops)
+    }
+
+    /// Reads every operation in the FLOW section with their arguments typed by `T`. String and
+    /// array arguments that aren't relocated by PTRO are null pointers, so they're left untyped.
+    pub fn to_tickflow_ops<T: OperationSet>(&self) -> Result<Vec<(u32, TickflowOp)>> {
+        let data_pointers: HashSet<usize> = self
+            .ptro
+            .iter()
+            .flatten()
+            .filter(|c| c.ptype == extract::PointerType::Data)
+            .map(|c| c.at)
+            .collect();
+        self.read_raw_ops::<T>()?
+            .into_iter()
+            .map(|(offset, op)| {
+                let relocated = |arg| data_pointers.contains(&(offset as usize + 4 * (arg + 1)));
+                Ok((offset, T::to_tickflow_op(op, &self.strd, relocated)?))
+            })
+            .collect()
+    }
+
     // for debugging reasons
     pub fn to_raw_tickflow_ops(&self, endian: ByteOrder) -> Result<Vec<TickflowOp>> {
         let mut data = Cursor::new(&self.flow.data);
@@ -147,8 +328,56 @@ impl BTKS {
 
 impl Tempo {
     pub fn is_streamed(&self) -> bool {
-        //TODO: currently always makes custom tempo IDs AAC since they can't be called for BCGRPs
-        !(self.id >= 0x01000101 && self.id <= 0x01000281)
+        self.stream == TempoStream::Streamed
+    }
+}
+
+impl TempoStream {
+    /// Guesses the kind of audio from the ID of a tempo, for when it isn't specified.
+    /// Only the game's own BCGRP tempos are detected as sequences.
+    pub fn guess(id: u32) -> Self {
+        if (0x01000101..=0x01000281).contains(&id) {
+            Self::Sequence
+        } else {
+            Self::Streamed
+        }
+    }
+}
+
+impl StreamReader for Tempo {
+    fn read_from<R: Read>(buffer: &mut R, order: ByteOrder) -> io::Result<Self> {
+        let id = u32::read_from(buffer, order)?;
+        let len = u32::read_from(buffer, order)?;
+        let stream = if u32::read_from(buffer, order)? != 0 {
+            TempoStream::Streamed
+        } else {
+            TempoStream::Sequence
+        };
+        let mut data = vec![];
+        for _ in 0..len {
+            data.push(TempoVal::read_from(buffer, order)?);
+        }
+        Ok(Self {
+            id,
+            data,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            stream,
+        })
+    }
+}
+
+impl StreamReader for TempoVal {
+    fn read_from<R: Read>(buffer: &mut R, order: ByteOrder) -> io::Result<Self> {
+        let mut beats = [0; 4];
+        buffer.read_exact(&mut beats)?;
+        Ok(Self {
+            beats: match order {
+                ByteOrder::BigEndian => f32::from_be_bytes(beats),
+                ByteOrder::LittleEndian => f32::from_le_bytes(beats),
+            },
+            time: u32::read_from(buffer, order)?,
+            loop_val: u32::read_from(buffer, order)?,
+        })
     }
 }
 
@@ -156,7 +385,7 @@ impl StreamWriter for Tempo {
     fn write_to<W: Write>(&self, buffer: &mut W, order: ByteOrder) -> io::Result<()> {
         self.id.write_to(buffer, order)?;
         (self.data.len() as u32).write_to(buffer, order)?;
-        (if self.is_streamed() { 1u32 } else { 0u32 }).write_to(buffer, order)?;
+        (self.is_streamed() as u32).write_to(buffer, order)?;
         for value in &self.data {
             buffer.write_all(&match order {
                 ByteOrder::BigEndian => value.beats.to_be_bytes(),
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use super::{ArgsTickflowOpDef, ArrayTickflowOpDef, OperationSet, RawTickflowOp, TickflowOpDef};
+
+/// Key for an operation definition: opcode, arg0 (`None` if any) and scene (-1 if any)
+type DefKey = (u16, Option<u32>, i32);
+
+/// Any kind of operation definition that can be stored in a [`DefTable`]
+pub trait OpDef {
+    fn key(&self) -> DefKey;
+}
+
+impl OpDef for TickflowOpDef {
+    fn key(&self) -> DefKey {
+        (self.op, self.arg0, self.scene)
+    }
+}
+
+impl OpDef for ArgsTickflowOpDef {
+    fn key(&self) -> DefKey {
+        (self.op, self.arg0, self.scene)
+    }
+}
+
+impl OpDef for ArrayTickflowOpDef {
+    fn key(&self) -> DefKey {
+        (self.op, self.arg0, self.scene)
+    }
+}
+
+/// List of operation definitions, hashed by opcode, arg0 and scene
+#[derive(Debug, Clone)]
+pub struct DefTable<T> {
+    defs: Vec<T>,
+    keys: HashMap<DefKey, usize>,
+}
+
+impl<T: OpDef> DefTable<T> {
+    pub fn new(defs: Vec<T>) -> Self {
+        let mut keys = HashMap::new();
+        for (i, def) in defs.iter().enumerate() {
+            keys.entry(def.key()).or_insert(i);
+        }
+        Self { defs, keys }
+    }
+
+    /// Finds the definition that matches `op`. If several do, the first one declared wins.
+    pub fn get(&self, op: &RawTickflowOp, scene: i32) -> Option<&T> {
+        [
+            (op.op, Some(op.arg0), scene),
+            (op.op, None, scene),
+            (op.op, Some(op.arg0), -1),
+            (op.op, None, -1),
+        ]
+        .iter()
+        .filter_map(|c| self.keys.get(c))
+        .min()
+        .map(|c| &self.defs[*c])
+    }
+
+    pub fn defs(&self) -> &[T] {
+        &self.defs
+    }
+}
+
+/// Every operation definition of an [`OperationSet`], ready for quick lookups
+#[derive(Debug, Clone)]
+pub struct OpIndex {
+    pub calls: DefTable<ArgsTickflowOpDef>,
+    pub strings: DefTable<ArgsTickflowOpDef>,
+    pub arrays: DefTable<ArrayTickflowOpDef>,
+    pub signed: DefTable<ArgsTickflowOpDef>,
+    pub tempos: DefTable<ArgsTickflowOpDef>,
+    pub depth: DefTable<TickflowOpDef>,
+    pub undepth: DefTable<TickflowOpDef>,
+    pub returns: DefTable<TickflowOpDef>,
+    pub scene: ArgsTickflowOpDef,
+    pub aliases: Vec<(&'static str, TickflowOpDef)>,
+}
+
+impl OpIndex {
+    pub fn new<T: OperationSet>() -> Self {
+        Self {
+            calls: DefTable::new(T::get_call_operations()),
+            strings: DefTable::new(T::get_string_operations()),
+            arrays: DefTable::new(T::get_array_operations()),
+            signed: DefTable::new(T::get_signed_operations()),
+            tempos: DefTable::new(T::get_tempo_operations()),
+            depth: DefTable::new(T::get_depth_operations()),
+            undepth: DefTable::new(T::get_undepth_operations()),
+            returns: DefTable::new(T::get_return_operations()),
+            scene: T::get_scene_operation(),
+            aliases: T::get_aliases(),
+        }
+    }
+}
+
+/// Implements [`OperationSet::op_index`] with an index of its own, built the first time it's
+/// requested. Has to be used inside every `impl OperationSet` block, since a generic function
+/// can't have a static for every type it's used with.
+#[macro_export]
+macro_rules! cached_op_index {
+    () => {
+        fn op_index() -> &'static $crate::data::index::OpIndex {
+            static INDEX: std::sync::OnceLock<$crate::data::index::OpIndex> =
+                std::sync::OnceLock::new();
+            INDEX.get_or_init($crate::data::index::OpIndex::new::<Self>)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(op: u16, arg0: Option<u32>, scene: i32) -> TickflowOpDef {
+        TickflowOpDef { op, arg0, scene }
+    }
+
+    fn lookup(table: &DefTable<TickflowOpDef>, arg0: u32, scene: i32) -> Option<DefKey> {
+        let op = RawTickflowOp {
+            op: 1,
+            arg0,
+            args: vec![],
+            scene,
+        };
+        table.get(&op, scene).map(OpDef::key)
+    }
+
+    #[test]
+    fn most_specific_definitions_come_first() {
+        let table = DefTable::new(vec![
+            def(1, Some(2), 5),
+            def(1, None, 5),
+            def(1, Some(2), -1),
+            def(1, None, -1),
+            def(2, None, -1),
+        ]);
+        assert_eq!(lookup(&table, 2, 5), Some((1, Some(2), 5)));
+        assert_eq!(lookup(&table, 3, 5), Some((1, None, 5)));
+        assert_eq!(lookup(&table, 2, 6), Some((1, Some(2), -1)));
+        assert_eq!(lookup(&table, 3, 6), Some((1, None, -1)));
+        assert_eq!(lookup(&DefTable::new(vec![def(2, None, -1)]), 3, 6), None);
+    }
+
+    #[test]
+    fn first_declared_definition_wins() {
+        let table = DefTable::new(vec![def(1, None, -1), def(1, Some(2), 5), def(1, None, -1)]);
+        assert_eq!(lookup(&table, 2, 5), Some((1, None, -1)));
+        assert_eq!(table.keys.len(), 2);
+    }
+}
@@ -1,8 +1,16 @@
 /// Data representation for the BTKS (Binary Tickflow Specification) file format
 pub mod btks;
+/// Hashed lookup tables for operation definitions
+pub mod index;
+/// String encoding and STRD section handling
+pub mod strings;
+
+use std::io::{self, Read};
 
 use btks::BtksType;
-use bytestream::ByteOrder;
+use index::OpIndex;
+use bytestream::{ByteOrder, StreamReader};
+use strings::TickflowString;
 
 //TODO: figure out if most of this should stay here or move to another library like tickflow-parse (i think this should stay here and be a dependency of tickflow-parse)
 
@@ -41,6 +49,15 @@ pub struct ArgsTickflowOpDef {
     pub scene: i32,
 }
 
+/// Tickflow operation definition with the array args indicated
+#[derive(Debug, Clone)]
+pub struct ArrayTickflowOpDef {
+    pub op: u16,
+    pub arg0: Option<u32>,
+    pub args: Vec<(i8, ArrayType, ArrayLength)>,
+    pub scene: i32,
+}
+
 impl RawTickflowOp {
     pub fn as_definition(&self) -> TickflowOpDef {
         TickflowOpDef {
@@ -92,7 +109,7 @@ impl From<Arg0> for u32 {
 pub enum Arg {
     Signed(i32),
     Unsigned(u32),
-    String(String),
+    String(TickflowString),
     Array(Array),
     Pointer(Pointer),
     Struct(Vec<u8>),
@@ -109,6 +126,27 @@ pub enum Array {
     SignedHalf(Vec<i16>),
 }
 
+/// Type of the elements of an [`Array`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayType {
+    Word,
+    SignedWord,
+    Byte,
+    SignedByte,
+    Half,
+    SignedHalf,
+}
+
+/// How the length of an array argument is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayLength {
+    Fixed(u32),
+    /// Length is the value of another argument of the operation
+    Arg(i8),
+    /// Array ends at (and includes) the first element equal to 0
+    ZeroTerminated,
+}
+
 #[derive(Debug, Clone)]
 pub enum Pointer {
     Raw(u32),
@@ -116,100 +154,56 @@ pub enum Pointer {
 }
 
 /// Trait for every type of Tickflow operation.
-pub trait OperationSet {
+pub trait OperationSet: Sized + 'static {
     const BTKS_TICKFLOW_TYPE: BtksType;
     const ENDIAN: ByteOrder;
 
-    //TODO: tempo operations, sub operations
+    //TODO: sub operations
     //TODO: adapt to Fever/DS' quirks
 
-    fn get_operation(op: RawTickflowOp) -> Self
-    where
-        Self: Sized;
+    fn get_operation(op: RawTickflowOp) -> Self;
+
+    /// Lookup index for every operation definition in this set, built once and then cached.
+    /// Implemented with [`cached_op_index`](crate::cached_op_index).
+    fn op_index() -> &'static OpIndex;
 
     fn get_call_operations() -> Vec<ArgsTickflowOpDef>;
     fn is_call_operation(op: &RawTickflowOp, scene: i32) -> Option<ArgsTickflowOpDef> {
-        for call_op in Self::get_call_operations() {
-            if op.op == call_op.op && (call_op.scene == -1 || call_op.scene == scene) {
-                match &call_op.arg0 {
-                    None => return Some(call_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(call_op);
-                        }
-                    }
-                }
-            }
-        }
-        None
+        Self::op_index().calls.get(op, scene).cloned()
     }
     fn get_string_operations() -> Vec<ArgsTickflowOpDef>;
     fn is_string_operation(op: &RawTickflowOp, scene: i32) -> Option<ArgsTickflowOpDef> {
-        for return_op in Self::get_string_operations() {
-            if op.op == return_op.op && (return_op.scene == -1 || return_op.scene == scene) {
-                match &return_op.arg0 {
-                    None => return Some(return_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(return_op);
-                        }
-                    }
-                }
-            }
-        }
-        None
+        Self::op_index().strings.get(op, scene).cloned()
     }
-    fn get_array_operations() -> Vec<ArgsTickflowOpDef>;
-    fn is_array_operation(op: &RawTickflowOp, scene: i32) -> Option<ArgsTickflowOpDef> {
-        for array_op in Self::get_array_operations() {
-            if op.op == array_op.op && (array_op.scene == -1 || array_op.scene == scene) {
-                match &array_op.arg0 {
-                    None => return Some(array_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(array_op);
-                        }
-                    }
-                }
-            }
-        }
-        None
+    fn get_array_operations() -> Vec<ArrayTickflowOpDef>;
+    fn is_array_operation(op: &RawTickflowOp, scene: i32) -> Option<ArrayTickflowOpDef> {
+        Self::op_index().arrays.get(op, scene).cloned()
     }
     fn get_depth_operations() -> Vec<TickflowOpDef>;
     fn is_depth_operation(op: &RawTickflowOp, scene: i32) -> Option<TickflowOpDef> {
-        for depth_op in Self::get_depth_operations() {
-            if op.op == depth_op.op && (depth_op.scene == -1 || depth_op.scene == scene) {
-                match &depth_op.arg0 {
-                    None => return Some(depth_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(depth_op);
-                        }
-                    }
-                }
-            }
-        }
-        None
+        Self::op_index().depth.get(op, scene).cloned()
     }
     fn get_undepth_operations() -> Vec<TickflowOpDef>;
     fn is_undepth_operation(op: &RawTickflowOp, scene: i32) -> Option<TickflowOpDef> {
-        for undepth_op in Self::get_undepth_operations() {
-            if op.op == undepth_op.op && (undepth_op.scene == -1 || undepth_op.scene == scene) {
-                match &undepth_op.arg0 {
-                    None => return Some(undepth_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(undepth_op);
-                        }
-                    }
-                }
-            }
-        }
-        None
+        Self::op_index().undepth.get(op, scene).cloned()
+    }
+    /// Operations with arguments that are signed integers
+    fn get_signed_operations() -> Vec<ArgsTickflowOpDef> {
+        vec![]
+    }
+    fn is_signed_operation(op: &RawTickflowOp, scene: i32) -> Option<ArgsTickflowOpDef> {
+        Self::op_index().signed.get(op, scene).cloned()
+    }
+    /// Operations with arguments that are IDs of tempos in the game's built-in tempo table
+    fn get_tempo_operations() -> Vec<ArgsTickflowOpDef> {
+        vec![]
+    }
+    fn is_tempo_operation(op: &RawTickflowOp, scene: i32) -> Option<ArgsTickflowOpDef> {
+        Self::op_index().tempos.get(op, scene).cloned()
     }
     fn get_scene_operation() -> ArgsTickflowOpDef;
     fn is_scene_operation(op: &RawTickflowOp) -> Option<i8> {
-        let scene_op = Self::get_scene_operation();
+        let scene_op = &Self::op_index().scene;
         if op.op == scene_op.op {
             match &scene_op.arg0 {
                 None => return Some(scene_op.args[0].0),
@@ -224,19 +218,101 @@ pub trait OperationSet {
     }
     fn get_return_operations() -> Vec<TickflowOpDef>;
     fn is_return_operation(op: &RawTickflowOp, scene: i32) -> Option<TickflowOpDef> {
-        for return_op in Self::get_return_operations() {
-            if op.op == return_op.op && (return_op.scene == -1 || return_op.scene == scene) {
-                match &return_op.arg0 {
-                    None => return Some(return_op),
-                    Some(c) => {
-                        if op.arg0 == *c {
-                            return Some(return_op);
-                        }
-                    }
+        Self::op_index().returns.get(op, scene).cloned()
+    }
+    /// Built-in command names, used when parsing and printing old Tickflow. A name with an arg0
+    /// stands for that arg0 only, while a name without one takes the arg0 given to the command.
+    fn get_aliases() -> Vec<(&'static str, TickflowOpDef)> {
+        vec![]
+    }
+    fn alias_by_name(name: &str) -> Option<&'static TickflowOpDef> {
+        Self::op_index()
+            .aliases
+            .iter()
+            .find(|c| c.0 == name)
+            .map(|c| &c.1)
+    }
+    /// Finds the name of an operation, preferring a name with its exact arg0
+    fn alias_for_op(
+        op: &RawTickflowOp,
+        scene: i32,
+    ) -> Option<(&'static str, &'static TickflowOpDef)> {
+        let aliases = &Self::op_index().aliases;
+        let matches = |c: &&(&str, TickflowOpDef), arg0| {
+            c.1.op == op.op && c.1.arg0 == arg0 && (c.1.scene == -1 || c.1.scene == scene)
+        };
+        aliases
+            .iter()
+            .find(|c| matches(c, Some(op.arg0)))
+            .or_else(|| aliases.iter().find(|c| matches(c, None)))
+            .map(|c| (c.0, &c.1))
+    }
+
+    /// Converts an operation read from a BTKS file into a [`TickflowOp`], typing every argument
+    /// that this operation set knows about. String and array arguments are read from `strd` if
+    /// `relocated` returns `true` for their position, meaning that PTRO has a data pointer for
+    /// them. Otherwise, they're null or dangling pointers, so they're left as [`Arg::Unknown`].
+    fn to_tickflow_op(
+        op: RawTickflowOp,
+        strd: &[u8],
+        relocated: impl Fn(usize) -> bool,
+    ) -> io::Result<TickflowOp> {
+        let scene = op.scene;
+        let mut args: Vec<Arg> = op.args.iter().map(|c| Arg::Unknown(*c)).collect();
+        let mut set_arg = |arg: i8, value: Arg| {
+            if let Some(c) = args.get_mut(arg as usize) {
+                *c = value;
+            }
+        };
+
+        if let Some(c) = Self::is_signed_operation(&op, scene) {
+            for (arg, _) in c.args {
+                if let Some(value) = op.args.get(arg as usize) {
+                    set_arg(arg, Arg::Signed(*value as i32));
                 }
             }
         }
-        None
+        if let Some(c) = Self::is_call_operation(&op, scene) {
+            for (arg, _) in c.args {
+                if let Some(value) = op.args.get(arg as usize) {
+                    set_arg(arg, Arg::Pointer(Pointer::Raw(*value)));
+                }
+            }
+        }
+        if let Some(c) = Self::is_string_operation(&op, scene) {
+            for (arg, is_unicode) in c.args {
+                if !relocated(arg as usize) {
+                    continue;
+                }
+                if let Some(value) = op.args.get(arg as usize) {
+                    let mut data = io::Cursor::new(strd);
+                    data.set_position(*value as u64);
+                    let string = TickflowString::read_from(&mut data, is_unicode, Self::ENDIAN)?;
+                    set_arg(arg, Arg::String(string));
+                }
+            }
+        }
+        if let Some(c) = Self::is_array_operation(&op, scene) {
+            for (arg, atype, len) in c.args {
+                if !relocated(arg as usize) {
+                    continue;
+                }
+                if let Some(value) = op.args.get(arg as usize) {
+                    let mut data = io::Cursor::new(strd);
+                    data.set_position(*value as u64);
+                    let array =
+                        Array::read_from(&mut data, atype, len.resolve(&op.args), Self::ENDIAN)?;
+                    set_arg(arg, Arg::Array(array));
+                }
+            }
+        }
+
+        Ok(TickflowOp {
+            op: op.op,
+            arg0: Arg0::Unsigned(op.arg0),
+            args,
+            scene,
+        })
     }
 }
 
@@ -265,6 +341,8 @@ impl OperationSet for TickflowOp {
     const BTKS_TICKFLOW_TYPE: BtksType = BtksType::Unspecified;
     const ENDIAN: ByteOrder = ByteOrder::LittleEndian;
 
+    crate::cached_op_index!();
+
     fn get_operation(op: RawTickflowOp) -> Self {
         op.into()
     }
@@ -274,7 +352,7 @@ impl OperationSet for TickflowOp {
     fn get_string_operations() -> Vec<ArgsTickflowOpDef> {
         unimplemented!("Operation types for generic TickflowOp")
     }
-    fn get_array_operations() -> Vec<ArgsTickflowOpDef> {
+    fn get_array_operations() -> Vec<ArrayTickflowOpDef> {
         unimplemented!("Operation types for generic TickflowOp")
     }
     fn get_depth_operations() -> Vec<TickflowOpDef> {
@@ -291,6 +369,118 @@ impl OperationSet for TickflowOp {
     }
 }
 
+impl ArrayLength {
+    /// Gets the amount of elements, if it can be known before reading the array
+    pub fn resolve(&self, args: &[u32]) -> Option<u32> {
+        match self {
+            Self::Fixed(c) => Some(*c),
+            Self::Arg(c) => args.get(*c as usize).copied(),
+            Self::ZeroTerminated => None,
+        }
+    }
+}
+
+impl ArrayType {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Word | Self::SignedWord => 4,
+            Self::Half | Self::SignedHalf => 2,
+            Self::Byte | Self::SignedByte => 1,
+        }
+    }
+}
+
+impl Array {
+    /// Reads an array, with `len` elements or until a 0 element if `len` is `None`
+    pub fn read_from<F: Read>(
+        f: &mut F,
+        atype: ArrayType,
+        len: Option<u32>,
+        endian: ByteOrder,
+    ) -> io::Result<Self> {
+        let mut values = vec![];
+        loop {
+            if let Some(c) = len {
+                if values.len() as u32 == c {
+                    break;
+                }
+            }
+            let value = match atype {
+                ArrayType::Word | ArrayType::SignedWord => u32::read_from(f, endian)?,
+                ArrayType::Half | ArrayType::SignedHalf => u16::read_from(f, endian)? as u32,
+                ArrayType::Byte | ArrayType::SignedByte => u8::read_from(f, endian)? as u32,
+            };
+            values.push(value);
+            if len.is_none() && value == 0 {
+                break;
+            }
+        }
+
+        Ok(match atype {
+            ArrayType::Word => Self::Word(values),
+            ArrayType::SignedWord => Self::SignedWord(values.into_iter().map(|c| c as i32).collect()),
+            ArrayType::Half => Self::Half(values.into_iter().map(|c| c as u16).collect()),
+            ArrayType::SignedHalf => {
+                Self::SignedHalf(values.into_iter().map(|c| c as u16 as i16).collect())
+            }
+            ArrayType::Byte => Self::Byte(values.into_iter().map(|c| c as u8).collect()),
+            ArrayType::SignedByte => {
+                Self::SignedByte(values.into_iter().map(|c| c as u8 as i8).collect())
+            }
+        })
+    }
+
+    pub fn array_type(&self) -> ArrayType {
+        match self {
+            Self::Word(_) => ArrayType::Word,
+            Self::SignedWord(_) => ArrayType::SignedWord,
+            Self::Byte(_) => ArrayType::Byte,
+            Self::SignedByte(_) => ArrayType::SignedByte,
+            Self::Half(_) => ArrayType::Half,
+            Self::SignedHalf(_) => ArrayType::SignedHalf,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Word(c) => c.len(),
+            Self::SignedWord(c) => c.len(),
+            Self::Byte(c) => c.len(),
+            Self::SignedByte(c) => c.len(),
+            Self::Half(c) => c.len(),
+            Self::SignedHalf(c) => c.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encodes the array as it would be stored in STRD
+    pub fn to_bytes(&self, endian: ByteOrder) -> Vec<u8> {
+        let mut out = vec![];
+        macro_rules! write_all {
+            ($vec:expr) => {
+                for value in $vec {
+                    out.extend(match endian {
+                        ByteOrder::BigEndian => value.to_be_bytes(),
+                        ByteOrder::LittleEndian => value.to_le_bytes(),
+                    })
+                }
+            };
+        }
+        match self {
+            Self::Word(c) => write_all!(c),
+            Self::SignedWord(c) => write_all!(c),
+            Self::Byte(c) => write_all!(c),
+            Self::SignedByte(c) => write_all!(c),
+            Self::Half(c) => write_all!(c),
+            Self::SignedHalf(c) => write_all!(c),
+        }
+        out
+    }
+}
+
 impl From<Vec<u32>> for Array {
     fn from(vec: Vec<u32>) -> Self {
         Self::Word(vec)
@@ -335,6 +525,12 @@ impl From<Array> for Arg {
 
 impl From<String> for Arg {
     fn from(string: String) -> Self {
+        Self::String(string.into())
+    }
+}
+
+impl From<TickflowString> for Arg {
+    fn from(string: TickflowString) -> Self {
         Self::String(string)
     }
 }
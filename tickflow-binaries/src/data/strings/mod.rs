@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind, Read},
+    sync::OnceLock,
+};
+
+use bytestream::{ByteOrder, StreamReader};
+
+mod sjis_table;
+
+const SJIS_TRAIL_COUNT: usize = 0xFD - 0x40;
+
+/// Text encoding of a string stored in STRD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Ascii,
+    ShiftJis,
+    Utf16,
+}
+
+/// String argument of a Tickflow operation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TickflowString {
+    pub value: String,
+    pub encoding: Encoding,
+    /// Shift-JIS bytes the string was read from (without the terminator), kept when encoding
+    /// `value` wouldn't give them back: when they aren't valid Shift-JIS (`value` has U+FFFD in
+    /// their place), or when they use one of the NEC/IBM duplicates of a character. Written
+    /// instead of `value` if set and the encoding is Shift-JIS.
+    pub raw: Option<Vec<u8>>,
+}
+
+impl TickflowString {
+    pub fn new(value: impl Into<String>, encoding: Encoding) -> Self {
+        Self {
+            value: value.into(),
+            encoding,
+            raw: None,
+        }
+    }
+
+    pub fn is_unicode(&self) -> bool {
+        self.encoding == Encoding::Utf16
+    }
+
+    /// Reads a null-terminated string. Non-unicode strings are read as ASCII if
+    /// possible, and as Shift-JIS otherwise, keeping their bytes if they can't be encoded back
+    /// exactly (see [`TickflowString::raw`]).
+    pub fn read_from<F: Read>(f: &mut F, is_unicode: bool, endian: ByteOrder) -> io::Result<Self> {
+        if is_unicode {
+            let mut units = vec![];
+            loop {
+                let chr = u16::read_from(f, endian)?;
+                if chr == 0 {
+                    break;
+                }
+                units.push(chr);
+            }
+            let value = char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            Ok(Self::new(value, Encoding::Utf16))
+        } else {
+            let mut bytes = vec![];
+            loop {
+                let chr = u8::read_from(f, endian)?;
+                if chr == 0 {
+                    break;
+                }
+                bytes.push(chr);
+            }
+            if bytes.is_ascii() {
+                Ok(Self::new(
+                    String::from_utf8(bytes).expect("ASCII is always valid UTF-8"),
+                    Encoding::Ascii,
+                ))
+            } else {
+                let (value, valid) = decode_sjis(&bytes);
+                let mut string = Self::new(value, Encoding::ShiftJis);
+                if !valid || encode_sjis(&string.value).ok().as_ref() != Some(&bytes) {
+                    string.raw = Some(bytes);
+                }
+                Ok(string)
+            }
+        }
+    }
+
+    /// Encodes the string with its null terminator, padded to a 4-byte boundary
+    pub fn to_bytes(&self, endian: ByteOrder) -> io::Result<Vec<u8>> {
+        let mut out = match self.encoding {
+            Encoding::Ascii => {
+                if !self.value.is_ascii() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("string {:?} is not valid ASCII", self.value),
+                    ));
+                }
+                let mut out = self.value.as_bytes().to_vec();
+                out.push(0);
+                out
+            }
+            Encoding::ShiftJis => {
+                let mut out = match &self.raw {
+                    Some(c) => c.clone(),
+                    None => encode_sjis(&self.value)?,
+                };
+                out.push(0);
+                out
+            }
+            Encoding::Utf16 => {
+                let mut out = vec![];
+                for unit in self.value.encode_utf16().chain([0]) {
+                    out.extend(match endian {
+                        ByteOrder::BigEndian => unit.to_be_bytes(),
+                        ByteOrder::LittleEndian => unit.to_le_bytes(),
+                    });
+                }
+                out
+            }
+        };
+        pad_to_alignment(&mut out);
+        Ok(out)
+    }
+}
+
+impl From<String> for TickflowString {
+    fn from(value: String) -> Self {
+        let encoding = if value.is_ascii() {
+            Encoding::Ascii
+        } else {
+            Encoding::ShiftJis
+        };
+        Self::new(value, encoding)
+    }
+}
+
+/// Builder for the STRD section, which stores every identical piece of data only once
+#[derive(Debug, Clone, Default)]
+pub struct StrdBuilder {
+    data: Vec<u8>,
+    offsets: HashMap<Vec<u8>, u32>,
+}
+
+impl StrdBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds raw data to the section, returning its offset
+    pub fn add(&mut self, mut bytes: Vec<u8>) -> u32 {
+        pad_to_alignment(&mut bytes);
+        if let Some(c) = self.offsets.get(&bytes) {
+            return *c;
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend(&bytes);
+        self.offsets.insert(bytes, offset);
+        offset
+    }
+
+    pub fn add_string(&mut self, string: &TickflowString, endian: ByteOrder) -> io::Result<u32> {
+        Ok(self.add(string.to_bytes(endian)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+fn pad_to_alignment(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
+
+fn sjis_lead_index(lead: u8) -> Option<usize> {
+    match lead {
+        0x81..=0x9F => Some((lead - 0x81) as usize),
+        0xE0..=0xFC => Some((lead - 0xE0) as usize + 0x1F),
+        _ => None,
+    }
+}
+
+/// Decodes Shift-JIS, replacing invalid characters with U+FFFD. Also returns whether every
+/// character was valid.
+fn decode_sjis(bytes: &[u8]) -> (String, bool) {
+    let mut out = String::new();
+    let mut valid = true;
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        let chr = match byte {
+            0..=0x7F => Some(byte as char),
+            0xA1..=0xDF => char::from_u32(0xFF61 + (byte - 0xA1) as u32),
+            _ => sjis_lead_index(byte).and_then(|lead| {
+                let trail = *bytes.next()?;
+                if !(0x40..0xFD).contains(&trail) {
+                    return None;
+                }
+                let code =
+                    sjis_table::DOUBLE_BYTE[lead * SJIS_TRAIL_COUNT + (trail - 0x40) as usize];
+                char::from_u32(code as u32).filter(|_| code != 0)
+            }),
+        };
+        valid &= chr.is_some();
+        out.push(chr.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    (out, valid)
+}
+
+fn encode_sjis(string: &str) -> io::Result<Vec<u8>> {
+    static ENCODER: OnceLock<HashMap<char, [u8; 2]>> = OnceLock::new();
+    let encoder = ENCODER.get_or_init(|| {
+        let mut map = HashMap::new();
+        for lead in (0x81..=0x9Fu8).chain(0xE0..=0xFC) {
+            let lead_index = sjis_lead_index(lead).unwrap();
+            for trail in 0x40..0xFDu8 {
+                let code = sjis_table::DOUBLE_BYTE
+                    [lead_index * SJIS_TRAIL_COUNT + (trail - 0x40) as usize];
+                if let Some(c) = char::from_u32(code as u32).filter(|_| code != 0) {
+                    // keep the first (standard) encoding for duplicated characters
+                    map.entry(c).or_insert([lead, trail]);
+                }
+            }
+        }
+        map
+    });
+
+    let mut out = vec![];
+    for chr in string.chars() {
+        match chr as u32 {
+            0..=0x7F => out.push(chr as u8),
+            c @ 0xFF61..=0xFF9F => out.push((c - 0xFF61) as u8 + 0xA1),
+            _ => out.extend(encoder.get(&chr).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("character {chr:?} can't be encoded as Shift-JIS"),
+                )
+            })?),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(string: &TickflowString, endian: ByteOrder) -> TickflowString {
+        let bytes = string.to_bytes(endian).unwrap();
+        assert_eq!(bytes.len() % 4, 0);
+        TickflowString::read_from(&mut bytes.as_slice(), string.is_unicode(), endian).unwrap()
+    }
+
+    #[test]
+    fn sjis_round_trip() {
+        let string = TickflowString::new("あ天国ｶﾀ!", Encoding::ShiftJis);
+        let bytes = string.to_bytes(ByteOrder::LittleEndian).unwrap();
+        assert_eq!(bytes[..2], [0x82, 0xA0]);
+        assert_eq!(bytes[6..9], [0xB6, 0xC0, b'!']);
+        assert_eq!(round_trip(&string, ByteOrder::LittleEndian), string);
+
+        let ascii = TickflowString::from("ascii".to_string());
+        assert_eq!(ascii.encoding, Encoding::Ascii);
+        assert_eq!(round_trip(&ascii, ByteOrder::BigEndian), ascii);
+
+        assert!(TickflowString::new("😀", Encoding::ShiftJis)
+            .to_bytes(ByteOrder::LittleEndian)
+            .is_err());
+    }
+
+    #[test]
+    fn utf16_round_trip() {
+        let string = TickflowString::new("é😀天", Encoding::Utf16);
+        for endian in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            assert_eq!(round_trip(&string, endian), string);
+        }
+        assert_eq!(
+            string.to_bytes(ByteOrder::BigEndian).unwrap()[..2],
+            [0x00, 0xE9]
+        );
+    }
+
+    #[test]
+    fn keeps_bytes_that_dont_encode_back() {
+        // NEC duplicate of U+2235, which is encoded as 0x81E6
+        let string = TickflowString::read_from(
+            &mut [0x87, 0x9A, 0].as_slice(),
+            false,
+            ByteOrder::LittleEndian,
+        )
+        .unwrap();
+        assert_eq!(string.value, "\u{2235}");
+        assert_eq!(string.raw.as_deref(), Some(&[0x87, 0x9A][..]));
+        assert_eq!(
+            string.to_bytes(ByteOrder::LittleEndian).unwrap(),
+            [0x87, 0x9A, 0, 0]
+        );
+
+        // not a lead byte, and a lead byte without a valid trail byte
+        let bytes = [b'a', 0xFD, 0x81, 0x20, 0];
+        let string =
+            TickflowString::read_from(&mut bytes.as_slice(), false, ByteOrder::LittleEndian)
+                .unwrap();
+        assert_eq!(string.value, "a\u{FFFD}\u{FFFD}");
+        assert_eq!(
+            string.to_bytes(ByteOrder::LittleEndian).unwrap(),
+            [b'a', 0xFD, 0x81, 0x20, 0, 0, 0, 0]
+        );
+
+        let string = round_trip(
+            &TickflowString::new("\u{2235}", Encoding::ShiftJis),
+            ByteOrder::LittleEndian,
+        );
+        assert_eq!(string.raw, None);
+    }
+}
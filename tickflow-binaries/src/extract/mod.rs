@@ -1,22 +1,28 @@
 use crate::data::{
     btks::{self, BTKS},
-    OperationSet, RawTickflowOp,
+    strings::{StrdBuilder, TickflowString},
+    Array, ArrayType, OperationSet, RawTickflowOp,
 };
 use bytestream::{ByteOrder, StreamReader, StreamWriter};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{Read, Seek, SeekFrom},
 };
 
 pub mod dol;
+pub mod symbols;
+pub mod tempo;
+
+use symbols::{Symbol, SymbolMap};
+use tempo::TempoTable;
 
 type Result<T> = std::io::Result<T>; //TODO: make my own error type
 
 #[derive(Debug, Clone)]
 pub struct Pointer {
-    at: usize,
-    points_to: u32,
-    ptype: PointerType,
+    pub at: usize,
+    pub points_to: u32,
+    pub ptype: PointerType,
 }
 
 impl Pointer {
@@ -64,30 +70,44 @@ pub fn extract<T: OperationSet>(
     file: &mut (impl Read + Seek),
     base_offset: u32,
     start_queue: &[u32],
-) -> Result<BTKS> {
+    tempo_table: Option<&TempoTable>,
+) -> Result<(BTKS, SymbolMap)> {
     //TODO: proper error instead of panic if start_queue is empty
 
     let mut functions = HashMap::new();
     let mut queue = vec![];
+    let mut queued = HashSet::new();
     for pos in start_queue {
-        queue.push((*pos, -1));
+        if queued.insert(*pos) {
+            queue.push((*pos, -1));
+        }
     }
     let mut bincmds = vec![];
-    let mut bindata = vec![];
+    let mut bindata = StrdBuilder::new();
     let mut pointers = vec![];
     let mut pos = 0;
+    let mut symbols = SymbolMap::default();
+    let mut tempo_ids = vec![];
     while pos < queue.len() {
         //TODO: hashmap? btreemap?
         functions.insert(queue[pos].0 - base_offset, bincmds.len() as u32);
-        pointers.extend(extract_tickflow_at::<T>(
+        symbols.symbols.push(Symbol {
+            address: queue[pos].0,
+            offset: bincmds.len() as u32,
+            scene: queue[pos].1,
+            name: None,
+        });
+        let (sub_pointers, sub_tempo_ids) = extract_tickflow_at::<T>(
             base_offset,
             file,
             &mut queue,
+            &mut queued,
             pos,
             &mut bincmds,
             &mut bindata,
-            T::ENDIAN,
-        )?);
+        )?;
+        pointers.extend(sub_pointers);
+        tempo_ids.extend(sub_tempo_ids);
         pos += 1
     }
 
@@ -111,9 +131,19 @@ pub fn extract<T: OperationSet>(
         }
     }
 
-    // TODO: tempos
+    let tmpo = match tempo_table {
+        Some(c) if !tempo_ids.is_empty() => Some(tempo::extract_tempos(
+            file,
+            base_offset,
+            c,
+            &tempo_ids,
+            T::ENDIAN,
+        )?),
+        _ => None,
+    };
+
     // TODO: handle related subs?
-    Ok(BTKS {
+    let btks = BTKS {
         btks_type: T::BTKS_TICKFLOW_TYPE,
         flow: btks::FlowSection {
             start_offset: 0,
@@ -124,26 +154,31 @@ pub fn extract<T: OperationSet>(
         } else {
             Some(pointers)
         },
-        strd: bindata,
-        tmpo: None,
-    })
+        strd: bindata.into_inner(),
+        tmpo,
+    };
+    Ok((btks, symbols))
 }
 
-/// Equivalent to Tickompiler's firstPass
+/// Equivalent to Tickompiler's firstPass. Returns every pointer found and the IDs of the tempos
+/// used by the sub.
 fn extract_tickflow_at<T: OperationSet>(
     base_offset: u32,
     file: &mut (impl Read + Seek),
     queue: &mut Vec<(u32, i32)>,
+    queued: &mut HashSet<u32>,
     pos: usize,
     bincmds: &mut Vec<u8>,
-    bindata: &mut Vec<u8>,
-    endian: ByteOrder,
-) -> Result<Vec<Pointer>> {
+    bindata: &mut StrdBuilder,
+) -> Result<(Vec<Pointer>, Vec<u32>)> {
+    let endian = T::ENDIAN;
     let mut scene = queue[pos].1;
     file.seek(SeekFrom::Start(queue[pos].0 as u64 - base_offset as u64))?;
     let mut done = false;
     let mut pointers = vec![];
+    let mut tempo_ids = vec![];
     let mut depth = 0;
+    let index = T::op_index();
     while !done {
         let (op_int, mut tf_op) = binary_to_raw_tf_op(file, scene, T::ENDIAN)?;
 
@@ -151,24 +186,18 @@ fn extract_tickflow_at<T: OperationSet>(
             scene = if c == -1 {
                 tf_op.arg0
             } else {
-                tf_op.args[c as usize]
+                tf_op.args.get(c as usize).copied().unwrap_or_default()
             } as i32;
         }
-        if let Some(c) = T::is_call_operation(&tf_op, scene) {
-            let pointer_pos = tf_op.args[c.args[0].0 as usize];
+        if let Some(c) = index.calls.get(&tf_op, scene) {
+            let arg = c.args[0].0 as usize;
+            let pointer_pos = tf_op.args.get(arg).copied().unwrap_or_default();
 
             if pointer_pos != 0 {
-                let mut is_in_queue = false;
-                'found: for (position, _) in &*queue {
-                    if *position == pointer_pos {
-                        is_in_queue = true;
-                        break 'found;
-                    }
-                }
-                if !is_in_queue {
+                if queued.insert(pointer_pos) {
                     queue.push((pointer_pos, scene));
                 }
-                tf_op.args[c.args[0].0 as usize] = pointer_pos - base_offset;
+                tf_op.args[arg] = pointer_pos - base_offset;
 
                 pointers.push(Pointer {
                     at: bincmds.len() + (4 * (c.args[0].0 + 1)) as usize,
@@ -177,31 +206,62 @@ fn extract_tickflow_at<T: OperationSet>(
                 });
             }
         }
-        if let Some(c) = T::is_string_operation(&tf_op, scene) {
+        if let Some(c) = index.strings.get(&tf_op, scene) {
             for (arg, is_special) in &c.args {
+                let Some(&pointer_pos) = tf_op.args.get(*arg as usize) else {
+                    continue;
+                };
+                if pointer_pos < base_offset {
+                    // null pointer, kept as is so it can be told apart from an empty string
+                    continue;
+                }
+                let string =
+                    read_string(base_offset, file, pointer_pos.into(), *is_special, endian)?;
                 pointers.push(Pointer {
                     at: bincmds.len() + (4 * (arg + 1)) as usize,
-                    points_to: bindata.len() as u32,
+                    points_to: bindata.add_string(&string, endian)?,
                     ptype: PointerType::Data,
                 });
-
-                bindata.extend(read_string(
+            }
+        }
+        if let Some(c) = index.arrays.get(&tf_op, scene) {
+            for (arg, atype, len) in &c.args {
+                let Some(&pointer_pos) = tf_op.args.get(*arg as usize) else {
+                    continue;
+                };
+                if pointer_pos < base_offset {
+                    // null pointer, nothing to copy
+                    continue;
+                }
+                let array = read_array(
                     base_offset,
                     file,
-                    tf_op.args[*arg as usize].into(),
-                    *is_special,
+                    pointer_pos.into(),
+                    *atype,
+                    len.resolve(&tf_op.args),
                     endian,
-                )?);
+                )?;
+                pointers.push(Pointer {
+                    at: bincmds.len() + (4 * (arg + 1)) as usize,
+                    points_to: bindata.add(array.to_bytes(endian)),
+                    ptype: PointerType::Data,
+                });
+            }
+        }
+        if let Some(c) = index.tempos.get(&tf_op, scene) {
+            for (arg, _) in &c.args {
+                if let Some(id) = tf_op.args.get(*arg as usize) {
+                    tempo_ids.push(*id);
+                }
             }
         }
-        //TODO: check if array_op
-        if T::is_depth_operation(&tf_op, scene).is_some() {
+        if index.depth.get(&tf_op, scene).is_some() {
             depth += 1;
         }
-        if T::is_undepth_operation(&tf_op, scene).is_some() && depth > 0 {
+        if index.undepth.get(&tf_op, scene).is_some() && depth > 0 {
             depth -= 1;
         }
-        if T::is_return_operation(&tf_op, scene).is_some() && depth <= 0 {
+        if index.returns.get(&tf_op, scene).is_some() && depth <= 0 {
             done = true;
         }
         op_int.write_to(bincmds, T::ENDIAN)?;
@@ -209,7 +269,22 @@ fn extract_tickflow_at<T: OperationSet>(
             arg.write_to(bincmds, T::ENDIAN)?;
         }
     }
-    Ok(pointers)
+    Ok((pointers, tempo_ids))
+}
+
+fn read_array<F: Read + Seek>(
+    base_offset: u32,
+    file: &mut F,
+    pos: u64,
+    atype: ArrayType,
+    len: Option<u32>,
+    endian: ByteOrder,
+) -> Result<Array> {
+    let og_pos = file.stream_position()?;
+    file.seek(SeekFrom::Start(pos - base_offset as u64))?;
+    let array = Array::read_from(file, atype, len, endian)?;
+    file.seek(SeekFrom::Start(og_pos))?;
+    Ok(array)
 }
 
 fn read_string<F: Read + Seek>(
@@ -218,35 +293,152 @@ fn read_string<F: Read + Seek>(
     pos: u64,
     is_unicode: bool,
     endian: ByteOrder,
-) -> Result<Vec<u8>> {
+) -> Result<TickflowString> {
     let og_pos = file.stream_position()?;
-    if pos < base_offset as u64 {
-        return Ok(vec![0, 0]);
-    }
     file.seek(SeekFrom::Start(pos - base_offset as u64))?;
-    let mut string_data = vec![];
-
-    if is_unicode {
-        loop {
-            let chr = u16::read_from(file, endian)?;
-            string_data.extend(chr.to_le_bytes()); // TODO: ???
-            if chr == 0 {
-                break;
-            }
+    let string = TickflowString::read_from(file, is_unicode, endian)?;
+    file.seek(SeekFrom::Start(og_pos))?;
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::data::{
+        btks::{BtksType, TempoStream, TempoVal},
+        ArgsTickflowOpDef, ArrayLength, ArrayTickflowOpDef, TickflowOpDef,
+    };
+
+    const BASE: u32 = 0x100000;
+
+    /// Operation set with a string operation (0x31), an array operation (0x40, with the length
+    /// in its second argument) and a tempo operation (0x24<2>)
+    struct TestOp;
+
+    fn args_def(op: u16, arg0: Option<u32>, args: Vec<(i8, bool)>) -> ArgsTickflowOpDef {
+        ArgsTickflowOpDef {
+            op,
+            arg0,
+            args,
+            scene: -1,
+        }
+    }
+
+    impl OperationSet for TestOp {
+        const BTKS_TICKFLOW_TYPE: BtksType = BtksType::MegamixIntl;
+        const ENDIAN: ByteOrder = ByteOrder::LittleEndian;
+
+        crate::cached_op_index!();
+
+        fn get_operation(_: RawTickflowOp) -> Self {
+            Self
+        }
+        fn get_call_operations() -> Vec<ArgsTickflowOpDef> {
+            vec![args_def(2, None, vec![(0, false)])]
         }
-    } else {
-        loop {
-            let chr = u8::read_from(file, endian)?;
-            string_data.push(chr);
-            if chr == 0 {
-                break;
+        fn get_string_operations() -> Vec<ArgsTickflowOpDef> {
+            vec![args_def(0x31, None, vec![(0, false)])]
+        }
+        fn get_array_operations() -> Vec<ArrayTickflowOpDef> {
+            vec![ArrayTickflowOpDef {
+                op: 0x40,
+                arg0: None,
+                args: vec![(0, ArrayType::Half, ArrayLength::Arg(1))],
+                scene: -1,
+            }]
+        }
+        fn get_tempo_operations() -> Vec<ArgsTickflowOpDef> {
+            vec![args_def(0x24, Some(2), vec![(0, false)])]
+        }
+        fn get_depth_operations() -> Vec<TickflowOpDef> {
+            vec![]
+        }
+        fn get_undepth_operations() -> Vec<TickflowOpDef> {
+            vec![]
+        }
+        fn get_scene_operation() -> ArgsTickflowOpDef {
+            args_def(0x28, Some(0), vec![(0, false)])
+        }
+        fn get_return_operations() -> Vec<TickflowOpDef> {
+            vec![TickflowOpDef {
+                op: 7,
+                arg0: None,
+                scene: -1,
+            }]
+        }
+    }
+
+    /// Builds a binary loaded at [`BASE`] from operations as `(op, arg0, args)`, followed by
+    /// `data`
+    fn binary(ops: &[(u16, u32, &[u32])], data: &[u8]) -> Vec<u8> {
+        let mut file = vec![];
+        for (op, arg0, args) in ops {
+            let op_int = *op as u32 | (args.len() as u32) << 10 | arg0 << 14;
+            file.extend(op_int.to_le_bytes());
+            for arg in *args {
+                file.extend(arg.to_le_bytes());
             }
         }
+        file.extend(data);
+        file
     }
 
-    //padding
-    string_data.extend(vec![0; 4 - string_data.len() % 4]); //TODO: is this needed anymore?
+    #[test]
+    fn copies_arrays_into_strd() {
+        // operations take 0x1C bytes, then the array
+        let array = BASE + 0x1C;
+        let file = binary(
+            &[(0x40, 0, &[array, 3]), (0x40, 0, &[0, 3]), (7, 0, &[])],
+            &[1, 0, 2, 0, 3, 0],
+        );
+        let (btks, _) = extract::<TestOp>(&mut Cursor::new(file), BASE, &[BASE], None).unwrap();
 
-    file.seek(SeekFrom::Start(og_pos))?;
-    Ok(string_data)
+        assert_eq!(btks.strd, [1, 0, 2, 0, 3, 0, 0, 0]);
+        // only the first array is relocated, and the null one is left as 0
+        let pointers: Vec<_> = btks
+            .ptro
+            .unwrap()
+            .iter()
+            .map(|c| (c.at, c.points_to, c.ptype))
+            .collect();
+        assert_eq!(pointers, [(4, 0, PointerType::Data)]);
+        assert_eq!(btks.flow.data[4..8], [0; 4]);
+        assert_eq!(btks.flow.data[16..20], [0; 4]);
+    }
+
+    #[test]
+    fn extracts_referenced_tempos() {
+        // operations take 0xC bytes, then the tempo table and the values of its only entry
+        let mut data = vec![];
+        for c in [5, 1, BASE + 0x1C, 0] {
+            data.extend(u32::to_le_bytes(c));
+        }
+        data.extend(2.0f32.to_le_bytes());
+        data.extend(u32::to_le_bytes(48000));
+        data.extend(u32::to_le_bytes(0));
+        let file = binary(&[(0x24, 2, &[5]), (7, 0, &[])], &data);
+        let table = TempoTable {
+            address: BASE + 0xC,
+            sample_rate: 32000,
+            sequences: 1..=1,
+        };
+        let (btks, _) =
+            extract::<TestOp>(&mut Cursor::new(file), BASE, &[BASE], Some(&table)).unwrap();
+
+        let tmpo = btks.tmpo.unwrap();
+        assert_eq!(tmpo.len(), 1);
+        assert_eq!(tmpo[0].id, 5);
+        assert_eq!(tmpo[0].sample_rate, 32000);
+        assert_eq!(tmpo[0].stream, TempoStream::Streamed);
+        assert_eq!(
+            tmpo[0].data,
+            [TempoVal {
+                beats: 2.0,
+                time: 48000,
+                loop_val: 0
+            }]
+        );
+    }
 }
@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+/// A single extracted sub, as found in the original binary
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    /// Address of the sub in the original binary (RAM address)
+    pub address: u32,
+    /// Offset of the sub inside the extracted FLOW section
+    pub offset: u32,
+    pub scene: i32,
+    pub name: Option<String>,
+}
+
+/// Map between the subs in an extracted BTKS and their original locations
+#[derive(Debug, Clone, Default)]
+pub struct SymbolMap {
+    pub symbols: Vec<Symbol>,
+}
+
+impl SymbolMap {
+    pub fn by_address(&self, address: u32) -> Option<&Symbol> {
+        self.symbols.iter().find(|c| c.address == address)
+    }
+
+    pub fn by_offset(&self, offset: u32) -> Option<&Symbol> {
+        self.symbols.iter().find(|c| c.offset == offset)
+    }
+
+    /// Fills in the name of every symbol `namer` knows about
+    pub fn name_with(&mut self, namer: impl Fn(&Symbol) -> Option<String>) {
+        for symbol in &mut self.symbols {
+            if let Some(name) = namer(symbol) {
+                symbol.name = Some(name);
+            }
+        }
+    }
+
+    /// Writes the map as a tab-separated text file, one symbol per line:
+    /// `address offset scene name`
+    pub fn to_text_file<F: Write>(&self, f: &mut F) -> io::Result<()> {
+        for symbol in &self.symbols {
+            writeln!(
+                f,
+                "{:08x}\t{:08x}\t{}\t{}",
+                symbol.address,
+                symbol.offset,
+                symbol.scene,
+                symbol.name.as_deref().unwrap_or("-")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the map as a JSON array of objects
+    pub fn to_json_file<F: Write>(&self, f: &mut F) -> io::Result<()> {
+        writeln!(f, "[")?;
+        for (i, symbol) in self.symbols.iter().enumerate() {
+            write!(
+                f,
+                "  {{\"address\": {}, \"offset\": {}, \"scene\": {}, \"name\": {}}}",
+                symbol.address,
+                symbol.offset,
+                symbol.scene,
+                match &symbol.name {
+                    Some(c) => json_string(c),
+                    None => "null".to_string(),
+                }
+            )?;
+            writeln!(f, "{}", if i + 1 == self.symbols.len() { "" } else { "," })?;
+        }
+        writeln!(f, "]")
+    }
+}
+
+/// Quotes and escapes a string for use in a JSON file
+pub fn json_string(string: &str) -> String {
+    let mut out = String::from("\"");
+    for chr in string.chars() {
+        match chr {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> SymbolMap {
+        SymbolMap {
+            symbols: vec![
+                Symbol {
+                    address: 0x52F000,
+                    offset: 0,
+                    scene: 0x10,
+                    name: Some("karate \"man\"".to_string()),
+                },
+                Symbol {
+                    address: 0x52F100,
+                    offset: 0x40,
+                    scene: -1,
+                    name: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn writes_text() {
+        let mut out = vec![];
+        map().to_text_file(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0052f000\t00000000\t16\tkarate \"man\"\n0052f100\t00000040\t-1\t-\n"
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut out = vec![];
+        map().to_json_file(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[\n  {\"address\": 5435392, \"offset\": 0, \"scene\": 16, \"name\": \"karate \\\"man\\\"\"},\n  \
+             {\"address\": 5435648, \"offset\": 64, \"scene\": -1, \"name\": null}\n]\n"
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_string("a\\b\nc\u{1}é"), "\"a\\\\b\\nc\\u0001é\"");
+    }
+}
@@ -0,0 +1,144 @@
+//! Extraction of the tempo tables built into the games
+//!
+//! The table is read as a list of `(id, value count, pointer to values)` entries ending with an
+//! ID of 0, and each value has the same layout as in a TMPO section: beats as a float, then the
+//! length in samples and the loop value.
+//!
+//! The entries don't say whether a tempo is used for streamed or sequenced audio, so that comes
+//! from the range of sequenced IDs given in the [`TempoTable`].
+//!
+//! No game's table has been located yet, so this layout is a guess based on the TMPO section and
+//! hasn't been checked against any game. Nothing calls this unless it's given a [`TempoTable`].
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    ops::RangeInclusive,
+};
+
+use bytestream::{ByteOrder, StreamReader};
+
+use crate::data::btks::{Tempo, TempoStream, TempoVal};
+
+type Result<T> = std::io::Result<T>;
+
+/// Where a game keeps its tempo table
+#[derive(Debug, Clone)]
+pub struct TempoTable {
+    pub address: u32,
+    pub sample_rate: u32,
+    /// IDs of the tempos used for sequenced (BCGRP) audio. Every other tempo is streamed.
+    pub sequences: RangeInclusive<u32>,
+}
+
+/// Reads the tempos in the table with the given IDs. IDs missing from the table are skipped.
+pub fn extract_tempos(
+    file: &mut (impl Read + Seek),
+    base_offset: u32,
+    table: &TempoTable,
+    ids: &[u32],
+    endian: ByteOrder,
+) -> Result<Vec<Tempo>> {
+    let og_pos = file.stream_position()?;
+    let mut tempos = vec![];
+
+    let mut entry_pos = table.address.checked_sub(base_offset).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "tempo table is located before the base offset",
+        )
+    })? as u64;
+    loop {
+        file.seek(SeekFrom::Start(entry_pos))?;
+        let id = u32::read_from(file, endian)?;
+        if id == 0 {
+            break;
+        }
+        let count = u32::read_from(file, endian)?;
+        let pointer = u32::read_from(file, endian)?;
+        entry_pos += 0xC;
+
+        if pointer < base_offset || !ids.contains(&id) || tempos.iter().any(|c: &Tempo| c.id == id)
+        {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start((pointer - base_offset) as u64))?;
+        let mut data = vec![];
+        for _ in 0..count {
+            data.push(TempoVal::read_from(file, endian)?);
+        }
+        tempos.push(Tempo {
+            id,
+            data,
+            sample_rate: table.sample_rate,
+            stream: if table.sequences.contains(&id) {
+                TempoStream::Sequence
+            } else {
+                TempoStream::Streamed
+            },
+        });
+    }
+
+    file.seek(SeekFrom::Start(og_pos))?;
+    Ok(tempos)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const BASE: u32 = 0x100000;
+
+    fn word(c: u32) -> [u8; 4] {
+        c.to_le_bytes()
+    }
+
+    #[test]
+    fn reads_requested_tempos() {
+        let table = TempoTable {
+            address: BASE,
+            sample_rate: 32000,
+            sequences: 2..=2,
+        };
+        let mut file = vec![];
+        // two entries, one of which isn't requested, then the values of both
+        for c in [1, 1, BASE + 0x1C, 2, 2, BASE + 0x28, 0] {
+            file.extend(word(c));
+        }
+        for (beats, time, loop_val) in [(1.0f32, 100, 0), (2.0, 200, 0), (4.0, 300, 1)] {
+            file.extend(beats.to_le_bytes());
+            file.extend(word(time));
+            file.extend(word(loop_val));
+        }
+
+        let tempos = extract_tempos(
+            &mut Cursor::new(file),
+            BASE,
+            &table,
+            &[2, 3],
+            ByteOrder::LittleEndian,
+        )
+        .unwrap();
+        assert_eq!(tempos.len(), 1);
+        assert_eq!(tempos[0].id, 2);
+        assert_eq!(tempos[0].sample_rate, 32000);
+        assert_eq!(tempos[0].stream, TempoStream::Sequence);
+        assert_eq!(
+            tempos[0].data,
+            [
+                TempoVal {
+                    beats: 2.0,
+                    time: 200,
+                    loop_val: 0
+                },
+                TempoVal {
+                    beats: 4.0,
+                    time: 300,
+                    loop_val: 1
+                }
+            ]
+        );
+    }
+}
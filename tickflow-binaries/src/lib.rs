@@ -1,2 +1,3 @@
 pub mod data;
 pub mod extract;
+pub mod link;
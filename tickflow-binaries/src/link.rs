@@ -0,0 +1,248 @@
+//! Linker for Tickflow operations
+//!
+//! Takes a list of labels and operations whose pointers may refer to labels, lays out the FLOW
+//! and STRD sections, and resolves every pointer into a PTRO entry. This is the shared backend
+//! for compiling both old Tickflow and Tickscript.
+
+use std::collections::HashMap;
+
+use bytestream::{ByteOrder, StreamWriter};
+use thiserror::Error;
+
+use crate::{
+    data::{
+        btks::{self, Tempo, BTKS},
+        strings::StrdBuilder,
+        Arg, OperationSet, Pointer, TickflowOp,
+    },
+    extract::{self, PointerType},
+};
+
+#[derive(Debug, Clone)]
+pub enum LinkItem {
+    Label(String),
+    Op(TickflowOp),
+}
+
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("undefined label \"{0}\"")]
+    UndefinedLabel(String),
+    #[error("label \"{0}\" is defined more than once")]
+    DuplicatedLabel(String),
+    #[error("operation {0:#x} has {1} arguments (15 at most)")]
+    TooManyArgs(u16, usize),
+    #[error("operation {0:#x} has an arg0 of {1:#x} (must be 18 bits at most)")]
+    OOBArg0(u16, u32),
+    #[error("can't encode string: {0}")]
+    StringEncoding(std::io::Error),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Linker {
+    pub items: Vec<LinkItem>,
+    /// Label the FLOW section starts executing from. Defaults to the start of the section.
+    pub start_label: Option<String>,
+    pub tempos: Option<Vec<Tempo>>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_label(&mut self, name: impl Into<String>) {
+        self.items.push(LinkItem::Label(name.into()));
+    }
+
+    pub fn push_op(&mut self, op: TickflowOp) {
+        self.items.push(LinkItem::Op(op));
+    }
+
+    /// Adds tempos to the TMPO section of the output
+    pub fn add_tempos(&mut self, tempos: impl IntoIterator<Item = Tempo>) {
+        self.tempos.get_or_insert_with(Vec::new).extend(tempos);
+    }
+
+    /// Lays out every item and resolves all labels, reporting every error found
+    pub fn link<T: OperationSet>(&self) -> Result<BTKS, Vec<LinkError>> {
+        let mut errors = vec![];
+
+        // first pass: find where every label is
+        let mut labels = HashMap::new();
+        let mut pos = 0u32;
+        for item in &self.items {
+            match item {
+                LinkItem::Label(name) => {
+                    if labels.insert(name.as_str(), pos).is_some() {
+                        errors.push(LinkError::DuplicatedLabel(name.clone()));
+                    }
+                }
+                LinkItem::Op(op) => pos += 4 * (op.args.len() as u32 + 1),
+            }
+        }
+
+        // second pass: write everything
+        let mut flow = vec![];
+        let mut strd = StrdBuilder::new();
+        let mut pointers = vec![];
+        for item in &self.items {
+            let LinkItem::Op(op) = item else {
+                continue;
+            };
+            let arg0 = u32::from(op.arg0.clone());
+            if op.args.len() > 0xF {
+                errors.push(LinkError::TooManyArgs(op.op, op.args.len()));
+            }
+            if arg0 >= 1 << 18 {
+                errors.push(LinkError::OOBArg0(op.op, arg0));
+            }
+            let op_int =
+                (op.op as u32 & 0x3FF) | ((op.args.len() as u32 & 0xF) << 10) | (arg0 << 14);
+            write_u32(&mut flow, op_int, T::ENDIAN);
+
+            for arg in &op.args {
+                let mut pointer = |points_to, ptype| {
+                    pointers.push(extract::Pointer {
+                        at: flow.len(),
+                        points_to,
+                        ptype,
+                    });
+                    points_to
+                };
+                let value = match arg {
+                    Arg::Signed(c) => *c as u32,
+                    Arg::Unsigned(c) | Arg::Unknown(c) => *c,
+                    Arg::Pointer(Pointer::Raw(c)) => pointer(*c, PointerType::Tickflow),
+                    Arg::Pointer(Pointer::Label(c)) => match labels.get(c.as_str()) {
+                        Some(c) => pointer(*c, PointerType::Tickflow),
+                        None => {
+                            errors.push(LinkError::UndefinedLabel(c.clone()));
+                            0
+                        }
+                    },
+                    Arg::String(c) => match strd.add_string(c, T::ENDIAN) {
+                        Ok(c) => pointer(c, PointerType::Data),
+                        Err(e) => {
+                            errors.push(LinkError::StringEncoding(e));
+                            0
+                        }
+                    },
+                    Arg::Array(c) => pointer(strd.add(c.to_bytes(T::ENDIAN)), PointerType::Data),
+                    Arg::Struct(c) => pointer(strd.add(c.clone()), PointerType::Data),
+                };
+                write_u32(&mut flow, value, T::ENDIAN);
+            }
+        }
+
+        let start_offset = match &self.start_label {
+            Some(c) => match labels.get(c.as_str()) {
+                Some(c) => *c,
+                None => {
+                    errors.push(LinkError::UndefinedLabel(c.clone()));
+                    0
+                }
+            },
+            None => 0,
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(BTKS {
+            btks_type: T::BTKS_TICKFLOW_TYPE,
+            flow: btks::FlowSection {
+                start_offset,
+                data: flow,
+            },
+            ptro: if pointers.is_empty() {
+                None
+            } else {
+                Some(pointers)
+            },
+            tmpo: self.tempos.clone(),
+            strd: strd.into_inner(),
+        })
+    }
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32, endian: ByteOrder) {
+    value
+        .write_to(data, endian)
+        .expect("writing to a Vec can't fail");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{
+        strings::{Encoding, TickflowString},
+        Arg0,
+    };
+
+    fn op(op: u16, args: Vec<Arg>) -> LinkItem {
+        LinkItem::Op(TickflowOp {
+            op,
+            arg0: Arg0::Unsigned(0),
+            args,
+            scene: -1,
+        })
+    }
+
+    fn string() -> Arg {
+        Arg::String(TickflowString::new("hi", Encoding::Ascii))
+    }
+
+    #[test]
+    fn relocates_labels_and_data() {
+        let linker = Linker {
+            items: vec![
+                LinkItem::Label("start".to_string()),
+                op(2, vec![Arg::Pointer(Pointer::Label("sub".to_string()))]),
+                op(0x31, vec![Arg::Unsigned(1), string()]),
+                op(0x31, vec![Arg::Unsigned(2), string()]),
+                LinkItem::Label("sub".to_string()),
+                op(7, vec![]),
+            ],
+            start_label: Some("sub".to_string()),
+            tempos: None,
+        };
+        let btks = linker.link::<TickflowOp>().unwrap();
+
+        assert_eq!(btks.flow.start_offset, 32);
+        assert_eq!(&btks.flow.data[4..8], 32u32.to_le_bytes());
+        assert_eq!(btks.flow.data.len(), 36);
+        // the string is only stored once
+        assert_eq!(btks.strd, b"hi\0\0");
+        let pointers: Vec<_> = btks
+            .ptro
+            .unwrap()
+            .iter()
+            .map(|c| (c.at, c.points_to, c.ptype == PointerType::Tickflow))
+            .collect();
+        assert_eq!(pointers, [(4, 32, true), (16, 0, false), (28, 0, false)]);
+    }
+
+    #[test]
+    fn reports_every_label_error() {
+        let linker = Linker {
+            items: vec![
+                LinkItem::Label("a".to_string()),
+                LinkItem::Label("a".to_string()),
+                op(2, vec![Arg::Pointer(Pointer::Label("b".to_string()))]),
+            ],
+            start_label: Some("c".to_string()),
+            tempos: None,
+        };
+        let errors = linker.link::<TickflowOp>().unwrap_err();
+        assert!(matches!(
+            &errors[..],
+            [
+                LinkError::DuplicatedLabel(a),
+                LinkError::UndefinedLabel(b),
+                LinkError::UndefinedLabel(c),
+            ] if a == "a" && b == "b" && c == "c"
+        ));
+    }
+}
@@ -6,12 +6,65 @@ use thiserror::Error;
 pub enum Error {
     #[error("file IO error: {0}")]
     IoError(std::io::Error),
-    #[error("tickflow error on {fname}:{line} - {error}")]
+    #[error(
+        "tickflow error on {fname}:{line}{} - {error}",
+        span.as_ref().map(|c| format!(":{}", c.start + 1)).unwrap_or_default()
+    )]
     OldTfError {
         fname: String,
         line: usize,
+        /// Part of the line the error refers to, if it's known
+        span: Option<Box<Span>>,
         error: OldTfError,
     },
+    #[error("tempo file error on {fname}:{line} - {error}")]
+    TempoError {
+        fname: String,
+        line: usize,
+        error: TempoError,
+    },
+    /// Error in a file read with `#include` on line `line` of `fname`
+    #[error("{error}\n    included from {fname}:{line}")]
+    Included {
+        fname: String,
+        line: usize,
+        error: Box<Error>,
+    },
+    /// Every error found in a file, in order
+    #[error("{}", join_errors(_0))]
+    Multiple(Vec<Error>),
+}
+
+fn join_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Range of columns in a line, counted in characters from 0, along with the text in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub token: String,
+}
+
+impl Span {
+    /// Span of `token` inside of `line`. Returns `None` if `token` isn't a slice of `line`.
+    pub fn new(line: &str, token: &str) -> Option<Self> {
+        let start = (token.as_ptr() as usize).checked_sub(line.as_ptr() as usize)?;
+        if start + token.len() > line.len() {
+            return None;
+        }
+        let start = line.get(..start)?.chars().count();
+        Some(Self {
+            start,
+            end: start + token.chars().count(),
+            token: token.to_string(),
+        })
+    }
 }
 
 pub fn nom_ok<I, O, E: nom::error::ParseError<I>>(
@@ -22,6 +75,72 @@ pub fn nom_ok<I, O, E: nom::error::ParseError<I>>(
 }
 
 impl Error {
+    /// Merges the errors found while parsing into one, or returns `Ok` if there are none
+    pub fn from_many(mut errors: Vec<Error>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Self::Multiple(errors)),
+        }
+    }
+
+    /// Renders the error like rustc does, with the line it happened in taken from `source` and
+    /// its span (or the whole line, if it doesn't have one) marked with carets. Multiple errors
+    /// are rendered one after another.
+    pub fn render(&self, source: &str) -> String {
+        let (fname, line, span, message) = match self {
+            Self::IoError(_) => return format!("error: {self}\n"),
+            // the source is only known for the main file
+            Self::Included { fname, line, error } => {
+                let mut out = error.render("");
+                out += &format!("  = note: included from {fname}:{line}\n");
+                return out;
+            }
+            Self::Multiple(errors) => {
+                return errors
+                    .iter()
+                    .map(|c| c.render(source))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Self::OldTfError {
+                fname,
+                line,
+                span,
+                error,
+            } => (fname, *line, span.as_ref(), error.to_string()),
+            Self::TempoError { fname, line, error } => (fname, *line, None, error.to_string()),
+        };
+
+        let mut out = format!("error: {message}\n");
+        let Some(text) = line.checked_sub(1).and_then(|c| source.lines().nth(c)) else {
+            let col = span.map(|c| format!(":{}", c.start + 1)).unwrap_or_default();
+            out += &format!(" --> {fname}:{line}{col}\n");
+            return out;
+        };
+        let (start, end) = match span {
+            Some(c) => (c.start, c.end.max(c.start + 1)),
+            None => {
+                let trimmed = text.trim_start();
+                let start = text.chars().count() - trimmed.chars().count();
+                (start, start + trimmed.trim_end().chars().count().max(1))
+            }
+        };
+        let gutter = " ".repeat(line.to_string().len());
+        // tabs are kept so the carets line up with the text
+        let padding: String = text
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out += &format!("{gutter}--> {fname}:{line}:{}\n", start + 1);
+        out += &format!("{gutter} |\n");
+        out += &format!("{line} | {text}\n");
+        out += &format!("{gutter} | {padding}{}\n", "^".repeat(end - start));
+        out
+    }
+
     pub fn wrap_nom<I, O, E: nom::error::ParseError<I>>(
         self,
         remaining: I,
@@ -36,6 +155,8 @@ pub enum OldTfError {
     InvalidIdentifier(String),
     #[error("unknown directive \"#{}\"", **_0)]
     InvalidDirective(crate::old::Identifier),
+    #[error("invalid arguments for \"#{}\"", **_0)]
+    InvalidDirectiveArgs(crate::old::Identifier),
     #[error("invalid string prefix {0}\"\"")]
     InvalidStrPrefix(String),
     #[error("number out of range (0x00000000-0xFFFFFFFF)")]
@@ -46,10 +167,23 @@ pub enum OldTfError {
     MissingRequiredDirective(&'static str),
     #[error("included files cannot have #index, #start, or #assets directives")]
     IncludedDirective,
+    #[error("include cycle: {}", _0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+    #[error("couldn't read included file \"{0}\": {1}")]
+    IncludeFailed(String, std::io::Error),
     #[error("undefined constant \"{0}\"")]
     UndefinedConstant(crate::old::Identifier),
+    #[error(
+        "label \"{0}\" is never defined{}",
+        _1.as_ref().map(|c| format!(" - did you mean \"{c}\"?")).unwrap_or_default()
+    )]
+    UndefinedLabel(String, Option<String>),
     #[error("operations can only be applied to integers")]
     InvalidOpType,
+    #[error("{0} overflows a 32-bit integer")]
+    Overflow(String),
+    #[error("division by zero")]
+    DivisionByZero,
     #[error("arg0 of any command must be an integer")]
     InvalidArg0Type,
     #[error("arg0 value {0:05x} is out of range (must be 18 bits at most)")]
@@ -66,6 +200,42 @@ impl OldTfError {
             error: self,
             fname: fname.to_owned(),
             line: line_num,
+            span: None,
+        }
+    }
+
+    pub fn with_span(self, fname: &str, line_num: usize, span: Option<Span>) -> Error {
+        Error::OldTfError {
+            error: self,
+            fname: fname.to_owned(),
+            line: line_num,
+            span: span.map(Box::new),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TempoError {
+    #[error("missing tempo IDs")]
+    MissingIds,
+    #[error("invalid tempo ID \"{0}\"")]
+    InvalidId(String),
+    #[error("invalid number \"{0}\"")]
+    InvalidNumber(String),
+    #[error("tempo values can't be negative")]
+    NegativeLength,
+    #[error("#tempo block is never closed with #endtempo")]
+    UnclosedBlock,
+    #[error("syntax error")]
+    SyntaxError,
+}
+
+impl TempoError {
+    pub fn with_ctx(self, fname: &str, line_num: usize) -> Error {
+        Error::TempoError {
+            error: self,
+            fname: fname.to_owned(),
+            line: line_num,
         }
     }
 }
@@ -83,3 +253,7 @@ impl From<std::io::Error> for Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Output of parsing that keeps going after errors: everything that could be parsed, along with
+/// every error found
+pub type Recovered<T> = (T, Vec<Error>);
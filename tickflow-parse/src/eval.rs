@@ -0,0 +1,149 @@
+//! Evaluation of constant integer expressions, shared by the Tickflow and Tickscript parsers
+//!
+//! Operators have the same precedence as in C, and every operation is checked, so overflowing or
+//! dividing by zero is an error instead of a panic or a wrapped value.
+
+use crate::error::OldTfError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum Operation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+}
+
+impl Operation {
+    /// How tightly the operation binds, higher being tighter
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Mul | Self::Div => 5,
+            Self::Add | Self::Sub => 4,
+            Self::Shl | Self::Shr => 3,
+            Self::And => 2,
+            Self::Xor => 1,
+            Self::Or => 0,
+        }
+    }
+
+    pub fn apply(self, val1: i32, val2: i32) -> Result<i32, OldTfError> {
+        let overflow = || OldTfError::Overflow(format!("{val1} {self} {val2}"));
+        match self {
+            Self::Add => val1.checked_add(val2).ok_or_else(overflow),
+            Self::Sub => val1.checked_sub(val2).ok_or_else(overflow),
+            Self::Mul => val1.checked_mul(val2).ok_or_else(overflow),
+            Self::Div if val2 == 0 => Err(OldTfError::DivisionByZero),
+            Self::Div => val1.checked_div(val2).ok_or_else(overflow),
+            Self::Shl => {
+                // set bits shifted out of the number count as an overflow, but shifting into the
+                // sign bit doesn't, since values are also used as 32-bit patterns
+                let shift = u32::try_from(val2).map_err(|_| overflow())?;
+                val1.checked_shl(shift)
+                    .filter(|c| (*c as u32) >> shift == val1 as u32)
+                    .ok_or_else(overflow)
+            }
+            Self::Shr => {
+                let shift = u32::try_from(val2).map_err(|_| overflow())?;
+                val1.checked_shr(shift).ok_or_else(overflow)
+            }
+            Self::And => Ok(val1 & val2),
+            Self::Or => Ok(val1 | val2),
+            Self::Xor => Ok(val1 ^ val2),
+        }
+    }
+}
+
+pub fn negate(val: i32) -> Result<i32, OldTfError> {
+    val.checked_neg()
+        .ok_or_else(|| OldTfError::Overflow(format!("-({val})")))
+}
+
+pub fn bitwise_not(val: i32) -> i32 {
+    !val
+}
+
+/// Groups a chain of `values` separated by `ops` by precedence, from left to right within the
+/// same precedence, combining every pair with `combine`. There must be one more value than
+/// operations.
+pub fn fold_by_precedence<V>(
+    mut values: Vec<V>,
+    mut ops: Vec<Operation>,
+    mut combine: impl FnMut(Operation, V, V) -> V,
+) -> V {
+    assert_eq!(values.len(), ops.len() + 1);
+    while let Some(precedence) = ops.iter().map(|c| c.precedence()).max() {
+        let i = ops
+            .iter()
+            .position(|c| c.precedence() == precedence)
+            .unwrap();
+        let op = ops.remove(i);
+        let val2 = values.remove(i + 1);
+        let val1 = values.remove(i);
+        values.insert(i, combine(op, val1, val2));
+    }
+    values.remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(values: Vec<i32>, ops: Vec<Operation>) -> Result<i32, OldTfError> {
+        fold_by_precedence(values.into_iter().map(Ok).collect(), ops, |op, a, b| {
+            op.apply(a?, b?)
+        })
+    }
+
+    #[test]
+    fn precedence() {
+        use Operation::*;
+        assert_eq!(evaluate(vec![1, 2, 3], vec![Add, Mul]).ok(), Some(7));
+        assert_eq!(evaluate(vec![2, 3, 1], vec![Mul, Sub]).ok(), Some(5));
+        assert_eq!(evaluate(vec![10, 4, 3], vec![Sub, Sub]).ok(), Some(3));
+        assert_eq!(evaluate(vec![16, 4, 2], vec![Div, Div]).ok(), Some(2));
+        assert_eq!(evaluate(vec![1, 1, 2], vec![Shl, Add]).ok(), Some(8));
+        // 4 | (2 ^ (7 & 3))
+        assert_eq!(evaluate(vec![4, 2, 7, 3], vec![Or, Xor, And]).ok(), Some(5));
+    }
+
+    #[test]
+    fn overflow() {
+        use Operation::*;
+        assert!(matches!(
+            evaluate(vec![i32::MAX, 1], vec![Add]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(
+            evaluate(vec![i32::MIN, 1], vec![Sub]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(
+            evaluate(vec![0x10000, 0x10000], vec![Mul]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(
+            evaluate(vec![i32::MIN, -1], vec![Div]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(
+            evaluate(vec![1, 0], vec![Div]),
+            Err(OldTfError::DivisionByZero)
+        ));
+        assert!(matches!(
+            evaluate(vec![3, 31], vec![Shl]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(
+            evaluate(vec![1, 32], vec![Shr]),
+            Err(OldTfError::Overflow(_))
+        ));
+        assert!(matches!(negate(i32::MIN), Err(OldTfError::Overflow(_))));
+        assert_eq!(evaluate(vec![1, 31], vec![Shl]).ok(), Some(i32::MIN));
+        assert_eq!(bitwise_not(0), -1);
+    }
+}
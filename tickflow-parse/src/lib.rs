@@ -1,7 +1,9 @@
 pub mod new;
 pub mod old;
+pub mod tempo;
 
 pub mod error;
+pub mod eval;
 pub use error::{Error, Result};
 
 pub(crate) mod better_nom_regex;
@@ -36,7 +38,7 @@ pub fn create_escapes(string: &str) -> String {
 }
 
 pub fn read_anysign_int(src: &str, radix: u32) -> std::result::Result<i32, std::num::IntErrorKind> {
-    let number = i64::from_str_radix(src, radix).map_err(|e| e.kind().clone())?;
+    let number = i64::from_str_radix(src, radix).map_err(|e| *e.kind())?;
     if number >= 0 {
         if number > (u32::MAX as i64) {
             Err(std::num::IntErrorKind::PosOverflow)
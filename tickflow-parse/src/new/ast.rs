@@ -0,0 +1,165 @@
+//! Syntax tree for Tickscript files
+//!
+//! Commands are kept by name, since what they compile to depends on the language implementation.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntType {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+}
+
+impl IntType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    String {
+        value: String,
+        is_unicode: bool,
+    },
+    Array {
+        /// Type of the elements of an integer array, if given
+        int_type: Option<IntType>,
+        values: Vec<Value>,
+    },
+    /// Name of a constant or a sub
+    Identifier(String),
+}
+
+/// Comparison against the condition variable, used by `if` and `while`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Command {
+        name: String,
+        args: Vec<Value>,
+    },
+    RawOp {
+        op: u16,
+        arg0: u32,
+        args: Vec<Value>,
+    },
+    /// `if`, where an `else` block with nothing but another `if` is printed as `else if`
+    If {
+        cmp: Comparison,
+        value: Value,
+        then: Vec<Statement>,
+        otherwise: Option<Vec<Statement>>,
+    },
+    Switch(Vec<Case>),
+    Break,
+    While {
+        cmp: Comparison,
+        value: Value,
+        body: Vec<Statement>,
+    },
+    Loop(Vec<Statement>),
+}
+
+/// Case of a `switch`. Falls through to the next one unless its body ends in a `break`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// Value to compare against, or `None` for `default`
+    pub value: Option<Value>,
+    pub body: Vec<Statement>,
+}
+
+/// Type of an argument in a command definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Any,
+    Int,
+    Sized(IntType),
+    String,
+    Sub,
+}
+
+impl ArgType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::Int => "int",
+            Self::Sized(c) => c.name(),
+            Self::String => "string",
+            Self::Sub => "sub",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Directive {
+        name: String,
+        args: Vec<Value>,
+    },
+    Const {
+        name: String,
+        value: Value,
+    },
+    /// Command definition, where `body` is a command or raw operation that can use the arguments
+    /// by name
+    Command {
+        name: String,
+        args: Vec<(String, ArgType)>,
+        body: Statement,
+    },
+    Sub(Sub),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sub {
+    pub name: String,
+    pub sync: bool,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct File {
+    pub items: Vec<Item>,
+}
+
+impl File {
+    pub fn subs(&self) -> impl Iterator<Item = &Sub> {
+        self.items.iter().filter_map(|c| match c {
+            Item::Sub(c) => Some(c),
+            _ => None,
+        })
+    }
+}
@@ -1 +1,7 @@
 //! TickScript
+
+pub mod ast;
+pub mod tempo;
+
+/// This module doesn't include any new items, just Display definitions for the syntax tree
+mod printing;
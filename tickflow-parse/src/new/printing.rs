@@ -0,0 +1,196 @@
+use std::fmt::{Display, Formatter, Result};
+
+use super::ast::{Case, File, Item, Statement, Sub, Value};
+
+const INDENT: &str = "    ";
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Integer(c) => {
+                if c.abs() >= 0xA {
+                    write!(f, "{}0x{:x}", if *c < 0 { "-" } else { "" }, c.abs())
+                } else {
+                    write!(f, "{c}")
+                }
+            }
+            Self::String { value, is_unicode } => write!(
+                f,
+                "{}\"{}\"",
+                if *is_unicode { "u" } else { "" },
+                crate::create_escapes(value)
+            ),
+            Self::Array { int_type, values } => write!(
+                f,
+                "{}[{}]",
+                int_type.map(|c| c.name()).unwrap_or_default(),
+                join(values)
+            ),
+            Self::Identifier(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.write(f, 0)
+    }
+}
+
+impl Statement {
+    fn write(&self, f: &mut Formatter<'_>, indent: usize) -> Result {
+        let pad = INDENT.repeat(indent);
+        match self {
+            Self::Command { name, args } => {
+                write!(f, "{pad}{name}")?;
+                if !args.is_empty() {
+                    write!(f, " {}", join(args))?;
+                }
+                writeln!(f)
+            }
+            Self::RawOp { op, arg0, args } => {
+                write!(
+                    f,
+                    "{pad}raw_op {}<{}>",
+                    Value::Integer(*op as i64),
+                    Value::Integer(*arg0 as i64)
+                )?;
+                if !args.is_empty() {
+                    write!(f, " {}", join(args))?;
+                }
+                writeln!(f)
+            }
+            Self::If {
+                cmp,
+                value,
+                then,
+                otherwise,
+            } => {
+                write!(f, "{pad}")?;
+                let (mut cmp, mut value, mut then, mut otherwise) = (cmp, value, then, otherwise);
+                loop {
+                    writeln!(f, "if {} {value} {{", cmp.symbol())?;
+                    write_block(f, then, indent + 1)?;
+                    write!(f, "{pad}}}")?;
+                    match otherwise.as_deref() {
+                        Some(
+                            [Self::If {
+                                cmp: c,
+                                value: v,
+                                then: t,
+                                otherwise: o,
+                            }],
+                        ) => {
+                            write!(f, " else ")?;
+                            (cmp, value, then, otherwise) = (c, v, t, o);
+                        }
+                        Some(c) => {
+                            writeln!(f, " else {{")?;
+                            write_block(f, c, indent + 1)?;
+                            return writeln!(f, "{pad}}}");
+                        }
+                        None => return writeln!(f),
+                    }
+                }
+            }
+            Self::Switch(cases) => {
+                writeln!(f, "{pad}switch {{")?;
+                for case in cases {
+                    case.write(f, indent + 1)?;
+                }
+                writeln!(f, "{pad}}}")
+            }
+            Self::Break => writeln!(f, "{pad}break"),
+            Self::While { cmp, value, body } => {
+                writeln!(f, "{pad}while {} {value} {{", cmp.symbol())?;
+                write_block(f, body, indent + 1)?;
+                writeln!(f, "{pad}}}")
+            }
+            Self::Loop(body) => {
+                writeln!(f, "{pad}loop {{")?;
+                write_block(f, body, indent + 1)?;
+                writeln!(f, "{pad}}}")
+            }
+        }
+    }
+}
+
+impl Case {
+    fn write(&self, f: &mut Formatter<'_>, indent: usize) -> Result {
+        let pad = INDENT.repeat(indent);
+        match &self.value {
+            Some(c) => writeln!(f, "{pad}case {c}:")?,
+            None => writeln!(f, "{pad}default:")?,
+        }
+        write_block(f, &self.body, indent + 1)
+    }
+}
+
+impl Display for Sub {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(
+            f,
+            "{}sub {} {{",
+            if self.sync { "sync " } else { "" },
+            self.name
+        )?;
+        write_block(f, &self.body, 1)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Directive { name, args } => {
+                write!(f, "#{name}")?;
+                if !args.is_empty() {
+                    write!(f, " {}", join(args))?;
+                }
+                writeln!(f)
+            }
+            Self::Const { name, value } => writeln!(f, "const {name} = {value}"),
+            Self::Command { name, args, body } => {
+                write!(f, "command {name}")?;
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|(name, arg_type)| format!("{name}: {}", arg_type.name()))
+                    .collect();
+                if !args.is_empty() {
+                    write!(f, " {}", args.join(", "))?;
+                }
+                write!(f, " = {body}")
+            }
+            Self::Sub(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+impl Display for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for (i, item) in self.items.iter().enumerate() {
+            // subs get a blank line around them
+            let is_sub = |c: &Item| matches!(c, Item::Sub(_));
+            if i > 0 && (is_sub(item) || is_sub(&self.items[i - 1])) {
+                writeln!(f)?;
+            }
+            write!(f, "{item}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_block(f: &mut Formatter<'_>, statements: &[Statement], indent: usize) -> Result {
+    for statement in statements {
+        statement.write(f, indent)?;
+    }
+    Ok(())
+}
+
+fn join(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,183 @@
+//! `#tempo` blocks, which embed a tempo into a Tickscript file:
+//!
+//! ```text
+//! #tempo 01000101 32000 sequence
+//!     2 1.0
+//!     4 1.5 1
+//! #endtempo
+//! ```
+//!
+//! The header has the ID of the tempo in hex, then optionally its sample rate (32000 if missing)
+//! and `streamed` or `sequence` to set the kind of audio, which is otherwise guessed from the ID.
+//! Every value is written the same way as in a [`TempoFile`].
+
+use std::io::Write;
+
+use tickflow_binaries::data::btks::TempoStream;
+
+use crate::{
+    error::TempoError,
+    tempo::{read_value, TempoFile, DEFAULT_SAMPLE_RATE},
+    Result,
+};
+
+/// Reads a `#tempo` block at the start of `input`, which is located at line `line_num` of the
+/// file. Returns the tempo and the rest of the input after the block.
+pub fn read_tempo_block<'a>(
+    fname: &str,
+    input: &'a str,
+    line_num: usize,
+) -> Result<(TempoFile, &'a str)> {
+    let mut lines = input.split_inclusive('\n');
+
+    let first = lines.next().unwrap_or_default();
+    let header = strip_comment(first)
+        .strip_prefix("#tempo")
+        .filter(|c| c.is_empty() || c.starts_with(char::is_whitespace))
+        .ok_or_else(|| TempoError::SyntaxError.with_ctx(fname, line_num))?;
+    let (id, sample_rate, stream) =
+        read_block_header(header).map_err(|e| e.with_ctx(fname, line_num))?;
+
+    let mut consumed = first.len();
+    let mut data = vec![];
+    for (i, line) in lines.enumerate() {
+        consumed += line.len();
+        let line = strip_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+        if line == "#endtempo" {
+            return Ok((
+                TempoFile {
+                    ids: vec![id],
+                    data,
+                    sample_rate,
+                    stream,
+                },
+                &input[consumed..],
+            ));
+        }
+        data.push(read_value(line, sample_rate).map_err(|e| e.with_ctx(fname, line_num + i + 1))?);
+    }
+
+    Err(TempoError::UnclosedBlock.with_ctx(fname, line_num))
+}
+
+/// Writes a `#tempo` block for every ID in `tempo`
+pub fn write_tempo_block(f: &mut impl Write, tempo: &TempoFile) -> Result<()> {
+    for id in &tempo.ids {
+        write!(f, "#tempo {id:08X} {}", tempo.sample_rate)?;
+        match tempo.stream {
+            Some(TempoStream::Streamed) => write!(f, " streamed")?,
+            Some(TempoStream::Sequence) => write!(f, " sequence")?,
+            None => {}
+        }
+        writeln!(f)?;
+        for value in &tempo.data {
+            writeln!(f, "    {}", tempo.value_to_text(value))?;
+        }
+        writeln!(f, "#endtempo")?;
+    }
+    Ok(())
+}
+
+/// Reads the ID, sample rate and kind of audio after `#tempo`
+fn read_block_header(
+    header: &str,
+) -> std::result::Result<(u32, u32, Option<TempoStream>), TempoError> {
+    let mut words = header.split_whitespace();
+    let id = words.next().ok_or(TempoError::MissingIds)?;
+    let id = u32::from_str_radix(id.trim_start_matches("0x"), 16)
+        .map_err(|_| TempoError::InvalidId(id.to_string()))?;
+
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut word = words.next();
+    if let Some(c) = word.filter(|c| !matches!(*c, "streamed" | "sequence")) {
+        sample_rate = c
+            .parse()
+            .ok()
+            .filter(|c| *c != 0)
+            .ok_or_else(|| TempoError::InvalidNumber(c.to_string()))?;
+        word = words.next();
+    }
+    let stream = match word {
+        Some("streamed") => Some(TempoStream::Streamed),
+        Some("sequence") => Some(TempoStream::Sequence),
+        Some(_) => Err(TempoError::SyntaxError)?,
+        None => None,
+    };
+    if words.next().is_some() {
+        Err(TempoError::SyntaxError)?
+    }
+    Ok((id, sample_rate, stream))
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split_once("//").map(|c| c.0).unwrap_or(line).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::btks::TempoVal;
+
+    use super::*;
+
+    #[test]
+    fn block_round_trip() {
+        let text =
+            "#tempo 01000101 48000 sequence // comment\n    2 1\n\n    4 1.5 1\n#endtempo\nsub";
+        let (tempo, rest) = read_tempo_block("test", text, 1).unwrap();
+        assert_eq!(rest, "sub");
+        assert_eq!(tempo.ids, [0x01000101]);
+        assert_eq!(tempo.sample_rate, 48000);
+        assert_eq!(tempo.stream, Some(TempoStream::Sequence));
+        assert_eq!(
+            tempo.data[1],
+            TempoVal {
+                beats: 4.0,
+                time: 72000,
+                loop_val: 1,
+            }
+        );
+
+        let mut written = vec![];
+        write_tempo_block(&mut written, &tempo).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(
+            written,
+            "#tempo 01000101 48000 sequence\n    2 1\n    4 1.5 1\n#endtempo\n"
+        );
+        assert_eq!(read_tempo_block("test", &written, 1).unwrap(), (tempo, ""));
+    }
+
+    #[test]
+    fn sample_rate_and_kind_are_optional() {
+        let (tempo, _) = read_tempo_block("test", "#tempo A\n1 0.5\n#endtempo\n", 1).unwrap();
+        assert_eq!(tempo.ids, [0xA]);
+        assert_eq!(tempo.sample_rate, DEFAULT_SAMPLE_RATE);
+        assert_eq!(tempo.stream, None);
+        assert_eq!(tempo.data[0].time, DEFAULT_SAMPLE_RATE / 2);
+
+        let (tempo, _) = read_tempo_block("test", "#tempo A streamed\n#endtempo", 1).unwrap();
+        assert_eq!(tempo.stream, Some(TempoStream::Streamed));
+    }
+
+    #[test]
+    fn block_errors() {
+        for text in [
+            "#tempo\n#endtempo",
+            "#tempo A 0\n#endtempo",
+            "#tempo A 1 2\n#endtempo",
+        ] {
+            assert!(read_tempo_block("test", text, 1).is_err(), "{text}");
+        }
+        assert!(matches!(
+            read_tempo_block("test", "#tempo A\n1 0.5\n", 3),
+            Err(crate::error::Error::TempoError {
+                line: 3,
+                error: TempoError::UnclosedBlock,
+                ..
+            })
+        ));
+    }
+}
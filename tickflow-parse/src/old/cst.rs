@@ -0,0 +1,124 @@
+//! Syntax tree of a Tickflow file that keeps everything the [`Statement`]s don't, like comments,
+//! blank lines and spacing, so a file can be printed back exactly as it was written
+//!
+//! Comments starting with `//{` and `//}` are the folding markers used by Notepad++, and are used
+//! by [the formatter](super::format) to indent blocks.
+
+use std::fmt::Display;
+
+use super::{
+    parsing::{read_statement_at, Location},
+    Statement, StatementSpans,
+};
+use crate::error::Recovered;
+
+#[derive(Debug, Clone, Default)]
+pub struct SyntaxFile {
+    pub lines: Vec<SyntaxLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxLine {
+    /// Whole line, as written
+    pub text: String,
+    /// Code in the line without the whitespace around it, if there is any
+    pub code: Option<String>,
+    /// Statement in [`SyntaxLine::code`], or `None` if there's no code or it couldn't be parsed
+    pub statement: Option<Statement>,
+    /// Where the parts of the statement are in [`SyntaxLine::text`]
+    pub spans: StatementSpans,
+    /// Comment at the end of the line, after the `//`
+    pub comment: Option<String>,
+}
+
+impl SyntaxFile {
+    /// Parses every line of a file, keeping the ones that couldn't be parsed as they are
+    pub fn parse(fname: &str, text: &str) -> Recovered<Self> {
+        let mut lines = vec![];
+        let mut errors = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let (code, comment) = split_comment(line);
+            let code = code.trim();
+            let (statement, spans) = if code.is_empty() {
+                (None, StatementSpans::default())
+            } else {
+                let loc = Location {
+                    fname,
+                    line_num: i + 1,
+                    line,
+                };
+                match read_statement_at(code, loc) {
+                    Ok((statement, spans)) => (Some(statement), spans),
+                    Err(e) => {
+                        errors.push(e);
+                        (None, StatementSpans::default())
+                    }
+                }
+            };
+            lines.push(SyntaxLine {
+                text: line.to_string(),
+                code: (!code.is_empty()).then(|| code.to_string()),
+                statement,
+                spans,
+                comment: comment.map(str::to_string),
+            });
+        }
+        (Self { lines }, errors)
+    }
+
+    /// Every statement in the file, along with its line number and where its parts are
+    pub fn statements(&self) -> Vec<(usize, Statement, StatementSpans)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i + 1, c.statement.clone()?, c.spans.clone())))
+            .collect()
+    }
+
+    /// Whether any line has code that couldn't be parsed
+    pub fn has_errors(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|c| c.code.is_some() && c.statement.is_none())
+    }
+}
+
+impl SyntaxLine {
+    /// Whether the line has a `//{` folding marker
+    pub fn opens_fold(&self) -> bool {
+        self.comment.as_ref().is_some_and(|c| c.starts_with('{'))
+    }
+
+    /// Whether the line has a `//}` folding marker
+    pub fn closes_fold(&self) -> bool {
+        self.comment.as_ref().is_some_and(|c| c.starts_with('}'))
+    }
+}
+
+impl Display for SyntaxFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a line into its code and the comment after it, if any. `//` inside of strings doesn't
+/// start a comment.
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '/' if !in_string && line[i + 1..].starts_with('/') => {
+                return (&line[..i], Some(&line[i + 2..]))
+            }
+            _ => {}
+        }
+    }
+    (line, None)
+}
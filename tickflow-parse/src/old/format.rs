@@ -0,0 +1,237 @@
+//! Formatter for Tickflow files
+//!
+//! Works on the tokens of every line instead of printing the [`Statement`]s again, so integers keep
+//! the radix they were written in and parentheses are kept. Comments are kept as they are, and
+//! lines that couldn't be parsed are only re-indented.
+
+use super::{cst::SyntaxFile, Statement};
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Indentation for every level of `//{` `//}` blocks
+    pub indent: String,
+    /// Whether hexadecimal digits are written in uppercase (the `0x` prefix is always lowercase)
+    pub uppercase_hex: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: "    ".to_string(),
+            uppercase_hex: false,
+        }
+    }
+}
+
+/// Formats a whole file. Blank lines are kept, but runs of them are reduced to one.
+pub fn format(file: &SyntaxFile, options: &FormatOptions) -> String {
+    let mut out = String::new();
+    let mut depth = 0usize;
+    let mut last_blank = true;
+    for line in &file.lines {
+        if line.code.is_none() && line.comment.is_none() {
+            if !last_blank {
+                out.push('\n');
+            }
+            last_blank = true;
+            continue;
+        }
+        last_blank = false;
+
+        if line.closes_fold() {
+            depth = depth.saturating_sub(1);
+        }
+        out += &options.indent.repeat(depth);
+        if let Some(code) = &line.code {
+            match &line.statement {
+                Some(statement) => out += &format_statement(code, statement, options),
+                None => out += code,
+            }
+            if line.comment.is_some() {
+                out.push(' ');
+            }
+        }
+        if let Some(comment) = &line.comment {
+            out += "//";
+            out += comment.trim_end();
+        }
+        out.push('\n');
+        if line.opens_fold() {
+            depth += 1;
+        }
+    }
+    // no blank lines at the end
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+/// Formats the code of a statement, which has to be the code it was parsed from
+pub fn format_statement(code: &str, statement: &Statement, options: &FormatOptions) -> String {
+    let Some(tokens) = tokenize(code) else {
+        return code.to_string();
+    };
+    match statement {
+        Statement::Label(c) => format!("{}:", **c),
+        Statement::Constant { .. } => match tokens.iter().position(|c| *c == "=") {
+            Some(i) => format!(
+                "{} = {}",
+                tokens[..i].join(""),
+                format_expr(&tokens[i + 1..], options)
+            ),
+            None => code.to_string(),
+        },
+        Statement::Directive { name, .. } => {
+            // #include takes the rest of the line as is
+            let rest = code[1 + name.len()..].trim();
+            match name.as_str() {
+                "include" => format!("#include {rest}"),
+                "alias" if tokens.len() > 2 => format!(
+                    "#alias {} {}",
+                    tokens[2],
+                    format_expr(&tokens[3..], options)
+                ),
+                _ => format!("#{} {}", **name, format_expr(&tokens[2..], options)),
+            }
+        }
+        Statement::Command { .. } => {
+            let mut out = format_operand(tokens[0], options);
+            let mut rest = &tokens[1..];
+            if rest.first() == Some(&"<") {
+                if let Some(end) = rest.iter().position(|c| *c == ">") {
+                    out += &format!("<{}>", format_expr(&rest[1..end], options));
+                    rest = &rest[end + 1..];
+                }
+            }
+            if !rest.is_empty() {
+                out += " ";
+                out += &format_expr(rest, options);
+            }
+            out
+        }
+    }
+}
+
+/// Formats a list of expressions separated by commas
+fn format_expr(tokens: &[&str], options: &FormatOptions) -> String {
+    let mut out = String::new();
+    let mut expects_operand = true;
+    for token in tokens {
+        match *token {
+            "," => {
+                out += ", ";
+                expects_operand = true;
+            }
+            "(" => {
+                out += "(";
+                expects_operand = true;
+            }
+            ")" => {
+                out += ")";
+                expects_operand = false;
+            }
+            "-" | "~" if expects_operand => out += token,
+            "+" | "-" | "*" | "/" | "<<" | ">>" | "&" | "|" | "^" => {
+                out += &format!(" {token} ");
+                expects_operand = true;
+            }
+            c => {
+                out += &format_operand(c, options);
+                expects_operand = false;
+            }
+        }
+    }
+    out
+}
+
+fn format_operand(token: &str, options: &FormatOptions) -> String {
+    match token.get(..2) {
+        Some("0x" | "0X") => {
+            let digits = &token[2..];
+            if options.uppercase_hex {
+                format!("0x{}", digits.to_uppercase())
+            } else {
+                format!("0x{}", digits.to_lowercase())
+            }
+        }
+        _ => token.to_string(),
+    }
+}
+
+/// Splits code into tokens, or returns `None` if there's anything that isn't a valid token
+fn tokenize(code: &str) -> Option<Vec<&str>> {
+    let mut tokens = vec![];
+    let mut rest = code.trim_start();
+    while !rest.is_empty() {
+        let first = rest.chars().next()?;
+        let len = if first.is_ascii_alphanumeric() || first == '_' || first == '$' {
+            // identifiers, numbers and prefixes of strings
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            if rest[len..].starts_with('"') {
+                len + string_len(&rest[len..])?
+            } else {
+                len
+            }
+        } else if first == '"' {
+            string_len(rest)?
+        } else if rest.starts_with("<<") || rest.starts_with(">>") {
+            2
+        } else if "+-*/&|^~()<>,=:#".contains(first) {
+            1
+        } else {
+            return None;
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Some(tokens)
+}
+
+/// Length of the string literal at the start of `input`, quotes included
+fn string_len(input: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in input.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = "  #index   0x5a\nSPEED=  0x10+2 //speed\n\n\n\nstart:   //{\n0x1 <0x2>  SPEED,(3 +4)\n\t  rest 0x30\n   this isn't tickflow\n  //}   \n  // end   \n\n";
+
+    const FORMATTED: &str = "#index 0x5a\nSPEED = 0x10 + 2 //speed\n\nstart: //{\n    0x1<0x2> SPEED, (3 + 4)\n    rest 0x30\n    this isn't tickflow\n//}\n// end\n";
+
+    fn format_text(text: &str, options: &FormatOptions) -> String {
+        format(&SyntaxFile::parse("test", text).0, options)
+    }
+
+    #[test]
+    fn formats_messy_files() {
+        assert_eq!(format_text(MESSY, &FormatOptions::default()), FORMATTED);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for options in [
+            FormatOptions::default(),
+            FormatOptions {
+                indent: "\t".to_string(),
+                uppercase_hex: true,
+            },
+        ] {
+            let formatted = format_text(MESSY, &options);
+            assert_eq!(format_text(&formatted, &options), formatted);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Bookkeeping for `#include`d files
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::Error;
+
+/// Every file read while preprocessing, the first one being the main file
+pub(super) struct SourceFiles {
+    files: Vec<SourceFile>,
+}
+
+struct SourceFile {
+    name: String,
+    /// File and line of the `#include` that read this file
+    included_from: Option<(usize, usize)>,
+}
+
+pub(super) enum Include {
+    /// File that hasn't been read yet, with its index and resolved path
+    New(usize, String),
+    /// File that was already included somewhere else, so it's skipped
+    Repeated,
+    /// File that's already being read, with the chain of files from the main one up to it again
+    Cycle(Vec<String>),
+}
+
+impl SourceFiles {
+    pub fn new(main: &str) -> Self {
+        Self {
+            files: vec![SourceFile {
+                name: main.to_string(),
+                included_from: None,
+            }],
+        }
+    }
+
+    pub fn name(&self, file: usize) -> &str {
+        &self.files[file].name
+    }
+
+    /// Files that led to `file` being read, starting at the main one
+    fn chain(&self, mut file: usize) -> Vec<usize> {
+        let mut chain = vec![file];
+        while let Some((parent, _)) = self.files[file].included_from {
+            chain.push(parent);
+            file = parent;
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Registers a file included from `parent` at `line`, with `path` relative to `parent`
+    pub fn include(&mut self, parent: usize, line: usize, path: &str) -> Include {
+        let name = resolve(self.name(parent), path);
+        let chain = self.chain(parent);
+        if chain.iter().any(|c| self.files[*c].name == name) {
+            let mut names: Vec<_> = chain
+                .into_iter()
+                .map(|c| self.files[c].name.clone())
+                .collect();
+            names.push(name);
+            Include::Cycle(names)
+        } else if self.files.iter().any(|c| c.name == name) {
+            Include::Repeated
+        } else {
+            self.files.push(SourceFile {
+                name: name.clone(),
+                included_from: Some((parent, line)),
+            });
+            Include::New(self.files.len() - 1, name)
+        }
+    }
+
+    /// Adds every `#include` that led to `file` being read to an error in it
+    pub fn wrap(&self, mut file: usize, mut error: Error) -> Error {
+        while let Some((parent, line)) = self.files[file].included_from {
+            error = Error::Included {
+                fname: self.name(parent).to_string(),
+                line,
+                error: Box::new(error),
+            };
+            file = parent;
+        }
+        error
+    }
+}
+
+/// Path of `path` when included from `including`, without any `.` or `..` that can be removed
+fn resolve(including: &str, path: &str) -> String {
+    let joined = Path::new(including)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(path);
+    let mut out = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            c => out.push(c),
+        }
+    }
+    out.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, io};
+
+    use tickflow_binaries::data::TickflowOp;
+
+    use super::*;
+    use crate::{
+        error::OldTfError,
+        old::{parse_from_text, Context},
+    };
+
+    /// Parses `main.tickflow` from `files`, and returns the result along with every file that
+    /// was read, in order
+    fn parse(files: &[(&str, &'static str)]) -> (crate::Result<Context>, Vec<String>) {
+        let files: HashMap<_, _> = files.iter().copied().collect();
+        let read = RefCell::new(vec![]);
+        let statements =
+            parse_from_text("main.tickflow", &mut files["main.tickflow"].as_bytes()).unwrap();
+        let context = Context::parse_file::<TickflowOp, &[u8]>(
+            statements,
+            |fname| {
+                read.borrow_mut().push(fname.clone());
+                files
+                    .get(fname.as_str())
+                    .map(|c| c.as_bytes())
+                    .ok_or(io::ErrorKind::NotFound.into())
+            },
+            "main.tickflow",
+        );
+        (context, read.into_inner())
+    }
+
+    #[test]
+    fn resolves_relative_paths() {
+        assert_eq!(resolve("main.tickflow", "a.tickflow"), "a.tickflow");
+        assert_eq!(resolve("lib/a.tickflow", "b.tickflow"), "lib/b.tickflow");
+        assert_eq!(resolve("lib/a.tickflow", "../b.tickflow"), "b.tickflow");
+        assert_eq!(
+            resolve("lib/a.tickflow", "./x/../b.tickflow"),
+            "lib/b.tickflow"
+        );
+        assert_eq!(resolve("main.tickflow", "../b.tickflow"), "../b.tickflow");
+        assert_eq!(resolve("/main.tickflow", "../b.tickflow"), "/b.tickflow");
+    }
+
+    #[test]
+    fn includes_nested_files_once() {
+        let (context, read) = parse(&[
+            (
+                "main.tickflow",
+                "#index 0x1\n#include lib/a.tickflow\n#include lib/b.tickflow\nstart:\n0x1 A, B\n",
+            ),
+            ("lib/a.tickflow", "#include ../common.tickflow\nA = C\n"),
+            (
+                "lib/b.tickflow",
+                "#include ./../common.tickflow\nB = C + 1\n",
+            ),
+            ("common.tickflow", "C = 2\n"),
+        ]);
+        assert_eq!(
+            read,
+            ["lib/a.tickflow", "common.tickflow", "lib/b.tickflow"]
+        );
+        let context = context.unwrap();
+        assert!(matches!(
+            &context.parsed_cmds[1],
+            crate::old::ParsedStatement::Command { args, .. } if matches!(
+                &args[..],
+                [crate::old::ParsedValue::Integer(2), crate::old::ParsedValue::Integer(3)]
+            )
+        ));
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let (context, read) = parse(&[
+            ("main.tickflow", "#index 0x1\n#include a.tickflow\nstart:\n"),
+            ("a.tickflow", "\n#include b.tickflow\n"),
+            ("b.tickflow", "#include a.tickflow\n"),
+        ]);
+        assert_eq!(read, ["a.tickflow", "b.tickflow"]);
+        let Err(Error::Included { fname, line, error }) = context else {
+            panic!("expected the cycle to be reported from main.tickflow");
+        };
+        assert_eq!((fname.as_str(), line), ("main.tickflow", 2));
+        let Error::Included { fname, line, error } = *error else {
+            panic!("expected the cycle to be reported from a.tickflow");
+        };
+        assert_eq!((fname.as_str(), line), ("a.tickflow", 2));
+        let Error::OldTfError {
+            error: OldTfError::IncludeCycle(chain),
+            fname,
+            line: 1,
+            ..
+        } = *error
+        else {
+            panic!("expected an include cycle in b.tickflow");
+        };
+        assert_eq!(fname, "b.tickflow");
+        assert_eq!(
+            chain,
+            ["main.tickflow", "a.tickflow", "b.tickflow", "a.tickflow"]
+        );
+    }
+
+    #[test]
+    fn wraps_errors_in_included_files() {
+        let (context, _) = parse(&[
+            (
+                "main.tickflow",
+                "#index 0x1\nstart:\n#include lib/a.tickflow\n#include c.tickflow\n",
+            ),
+            ("lib/a.tickflow", "#include b.tickflow\n"),
+            ("lib/b.tickflow", "A = 1\n\nA = 2\n"),
+        ]);
+        let Err(Error::Multiple(errors)) = context else {
+            panic!("expected every error to be reported");
+        };
+        let [missing, redefined] = &errors[..] else {
+            panic!("{errors:?}");
+        };
+        assert_eq!(
+            redefined.to_string(),
+            "tickflow error on lib/b.tickflow:3:1 - constant \"A\" was previously defined to be 1 in line 1\n    \
+             included from lib/a.tickflow:1\n    \
+             included from main.tickflow:3"
+        );
+        assert!(matches!(
+            missing,
+            Error::OldTfError {
+                error: OldTfError::IncludeFailed(fname, _),
+                line: 4,
+                ..
+            } if fname == "c.tickflow"
+        ));
+    }
+}
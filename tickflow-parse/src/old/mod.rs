@@ -2,8 +2,11 @@
 //!
 //! How to use:
 //! 1. Run [`parse_from_text`] on your text file/string value
-//! 2. Run the output `Vec<(usize, Statement)>`, which is a Rust representation of the raw contents of the file, through [Context::parse_file]
+//! 2. Run the output `Vec<(usize, Statement, StatementSpans)>`, which is a Rust representation of the raw contents of the file, through [Context::parse_file]
 //! 3. You can use the outputted `Vec<ParsedStatement>` with the main `tickflow` library
+//!
+//! Both steps have a `_recover` version that keeps going after errors, to report all of them at
+//! once along with whatever could be parsed.
 
 //TODO: split the entire module in more files (?)
 
@@ -12,12 +15,23 @@ use std::{collections::HashMap, io::Read, ops::Deref};
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::{error::OldTfError, Result};
+use tickflow_binaries::data::{OperationSet, RawTickflowOp};
+
+use crate::{
+    error::{OldTfError, Recovered, Span},
+    eval, Error, Result,
+};
 
+pub mod cst;
+pub mod format;
+mod include;
 /// [nom] parsers for Tickflow syntax
 pub mod parsing;
 
-pub use parsing::parse_from_text;
+use include::{Include, SourceFiles};
+
+pub use crate::eval::Operation;
+pub use parsing::{parse_from_text, parse_from_text_recover};
 
 /// This module doesn't include any new items, just Display definitions for Value, Statement, etc.
 mod printing;
@@ -67,6 +81,7 @@ pub enum Value {
         values: [Box<Value>; 2],
     },
     Negated(Box<Value>),
+    BitwiseNot(Box<Value>),
     Constant(Identifier),
     Integer(i32),
     String {
@@ -105,35 +120,6 @@ pub enum ParsedValue {
     Label(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
-pub enum Operation {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Shl,
-    Shr,
-    And,
-    Or,
-    Xor,
-}
-
-impl Operation {
-    pub fn apply(self, val1: i32, val2: i32) -> i32 {
-        match self {
-            Operation::Add => val1 + val2,
-            Operation::Sub => val1 - val2,
-            Operation::Mul => val1 * val2,
-            Operation::Div => val1 / val2,
-            Operation::Shl => val1 << val2,
-            Operation::Shr => val1 >> val2,
-            Operation::And => val1 & val2,
-            Operation::Or => val1 | val2,
-            Operation::Xor => val1 ^ val2,
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub enum Statement {
     Directive {
@@ -152,6 +138,16 @@ pub enum Statement {
     },
 }
 
+/// Where the parts of a [`Statement`] are in its line, to point at them in errors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatementSpans {
+    /// Name of the label, constant, directive or command
+    pub name: Option<Span>,
+    pub arg0: Option<Span>,
+    /// Every argument, or the value of a constant
+    pub args: Vec<Span>,
+}
+
 #[derive(Debug, Clone)]
 pub enum ParsedStatement {
     Label(String, usize),
@@ -164,17 +160,66 @@ pub enum ParsedStatement {
 
 #[derive(Debug, Clone)]
 pub enum CommandName {
+    /// Operation number, which can have a preset arg0 from bit 14 onwards (as in Tickompiler)
     Raw(i32),
     Named(Identifier),
 }
 
+impl Statement {
+    /// Command for an operation, named after its built-in alias in `S` if it has one
+    pub fn command<S: OperationSet>(op: u16, arg0: u32, scene: i32, args: Vec<Value>) -> Self {
+        let raw = RawTickflowOp {
+            op,
+            arg0,
+            args: vec![],
+            scene,
+        };
+        let (cmd, arg0) = match S::alias_for_op(&raw, scene) {
+            Some((name, def)) => (
+                CommandName::Named(Identifier::new_unchecked(name)),
+                if def.arg0.is_some() { 0 } else { arg0 },
+            ),
+            None => (CommandName::Raw(op as i32), arg0),
+        };
+        Self::Command {
+            cmd,
+            arg0: (arg0 != 0).then_some(Value::Integer(arg0 as i32)),
+            args,
+        }
+    }
+}
+
 impl Context {
-    pub fn parse_file<T: Read>(
-        statements: Vec<(usize, Statement)>,
+    /// Parses every statement of a file. Commands are named with `#alias` directives or with the
+    /// built-in aliases of `S`, in that order.
+    ///
+    /// Every error in the file is reported, see [`Context::parse_file_recover`] to get the partial
+    /// result as well.
+    ///
+    /// `#include` paths are relative to the file they're in, and `include_fn` gets them joined to
+    /// the path of that file, starting from `fname`. Files that were already included are
+    /// skipped, and including a file from itself (even indirectly) is an error.
+    pub fn parse_file<S: OperationSet, T: Read>(
+        statements: Vec<(usize, Statement, StatementSpans)>,
         include_fn: impl Fn(String) -> std::io::Result<T>,
         fname: &str,
     ) -> Result<Self> {
+        let (context, errors) = Self::parse_file_recover::<S, T>(statements, include_fn, fname);
+        Error::from_many(errors)?;
+        Ok(context)
+    }
+
+    /// Like [`Context::parse_file`], but keeps going after errors, and returns everything that
+    /// could be parsed along with every error, in order. Statements with errors are left out,
+    /// and constants that couldn't be evaluated are set to 0 so they don't cause more errors.
+    pub fn parse_file_recover<S: OperationSet, T: Read>(
+        statements: Vec<(usize, Statement, StatementSpans)>,
+        include_fn: impl Fn(String) -> std::io::Result<T>,
+        fname: &str,
+    ) -> Recovered<Self> {
         let mut constants = HashMap::new();
+        let mut errors = vec![];
+        let mut files = SourceFiles::new(fname);
 
         let DirectiveResult {
             statements,
@@ -182,24 +227,35 @@ impl Context {
             start,
             assets,
             aliases,
-        } = Self::preprocess_directives(statements, &include_fn, false, fname)?;
+        } = Self::preprocess_directives(statements, &include_fn, 0, &mut files, &mut errors);
 
-        let Some(index) = index else {
-            Err(OldTfError::MissingRequiredDirective("index").with_ctx(fname, 1))?
-        };
+        let index = index.unwrap_or_else(|| {
+            errors.push(OldTfError::MissingRequiredDirective("index").with_ctx(fname, 1));
+            0
+        });
 
         let mut parsed_cmds = vec![];
+        // labels used by commands, with the lines they're used in
+        let mut label_uses = vec![];
 
         // read file
-        for (l, st) in statements {
+        for (file, l, st, spans) in statements {
+            let fname = files.name(file);
+            let error_count = errors.len();
             match st {
                 Statement::Constant { name, value } => {
-                    let value = Self::parse_value(&constants, value, fname, l)?;
+                    let value = Self::parse_value(&constants, value, fname, l, spans.args.first())
+                        .unwrap_or_else(|e| {
+                            errors.push(e);
+                            ParsedValue::Integer(0)
+                        });
 
                     // insert new constant into map, error on duplicated constant
                     if let Some((old_value, old_l)) = constants.insert(name.clone(), (value, l)) {
-                        Err(OldTfError::RedefinedConstant(name, old_value, old_l)
-                            .with_ctx(fname, l))?;
+                        errors.push(
+                            OldTfError::RedefinedConstant(name, old_value, old_l)
+                                .with_span(fname, l, spans.name),
+                        );
                     }
                 }
                 Statement::Label(c) => {
@@ -212,10 +268,13 @@ impl Context {
                             &*c == existing_label
                         })
                     {
-                        Err(OldTfError::RedefinedLabel(old_name.clone(), *old_l).with_ctx(fname, l))?
+                        errors.push(
+                            OldTfError::RedefinedLabel(old_name.clone(), *old_l)
+                                .with_span(fname, l, spans.name),
+                        );
+                    } else {
+                        parsed_cmds.push(ParsedStatement::Label(c.0, l))
                     }
-
-                    parsed_cmds.push(ParsedStatement::Label(c.0, l))
                 }
                 Statement::Command { cmd, arg0, args } => {
                     let cmd = match &cmd {
@@ -223,30 +282,84 @@ impl Context {
                         CommandName::Named(c) => {
                             if let Some(c) = aliases.get(c) {
                                 CommandName::Raw(*c)
+                            } else if let Some(def) = S::alias_by_name(c) {
+                                CommandName::Raw(
+                                    (def.op as u32 | def.arg0.unwrap_or(0) << 14) as i32,
+                                )
                             } else {
                                 cmd
                             }
                         }
                     };
-                    let arg0 = if let Some(c) = arg0 {
-                        match Self::parse_value(&constants, c, fname, l)? {
-                            ParsedValue::Integer(c) if c == c & ((1 << 18) - 1) => Some(c as u32),
-                            ParsedValue::Integer(c) => {
-                                Err(OldTfError::OOBArg0(c).with_ctx(fname, l))?
-                            }
-                            _ => Err(OldTfError::InvalidArg0Type.with_ctx(fname, l))?,
-                        }
-                    } else {
-                        None
-                    };
+                    let arg0_span = spans.arg0.as_ref();
+                    let arg0 = arg0
+                        .map(
+                            |c| match Self::parse_value(&constants, c, fname, l, arg0_span)? {
+                                ParsedValue::Integer(c) if c == c & ((1 << 18) - 1) => Ok(c as u32),
+                                ParsedValue::Integer(c) => Err(OldTfError::OOBArg0(c).with_span(
+                                    fname,
+                                    l,
+                                    arg0_span.cloned(),
+                                )),
+                                _ => Err(OldTfError::InvalidArg0Type.with_span(
+                                    fname,
+                                    l,
+                                    arg0_span.cloned(),
+                                )),
+                            },
+                        )
+                        .transpose()
+                        .unwrap_or_else(|e| {
+                            errors.push(e);
+                            None
+                        });
+                    // every argument is checked, even after one of them fails
                     let args = args
                         .into_iter()
-                        .map(|c| Self::parse_value(&constants, c, fname, l))
-                        .collect::<Result<_>>()?;
-                    parsed_cmds.push(ParsedStatement::Command { cmd, arg0, args })
+                        .enumerate()
+                        .filter_map(|(i, c)| {
+                            let span = spans.args.get(i);
+                            Self::parse_value(&constants, c, fname, l, span)
+                                .map(|c| (c, span))
+                                .map_err(|e| errors.push(e))
+                                .ok()
+                        })
+                        .collect::<Vec<_>>();
+                    if errors.len() == error_count {
+                        for (arg, span) in &args {
+                            if let ParsedValue::Label(c) = arg {
+                                label_uses.push((c.clone(), file, l, span.cloned()));
+                            }
+                        }
+                        let args = args.into_iter().map(|c| c.0).collect();
+                        parsed_cmds.push(ParsedStatement::Command { cmd, arg0, args })
+                    }
                 }
                 Statement::Directive { .. } => unreachable!(),
             }
+            let new_errors: Vec<_> = errors.drain(error_count..).collect();
+            errors.extend(new_errors.into_iter().map(|c| files.wrap(file, c)));
+        }
+
+        // unknown identifiers are taken as labels, so a misspelled constant would end up as a
+        // label that doesn't exist
+        let labels: Vec<&str> = parsed_cmds
+            .iter()
+            .filter_map(|c| match c {
+                ParsedStatement::Label(name, _) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        for (name, file, l, span) in label_uses {
+            if !labels.contains(&name.as_str()) {
+                let candidates = labels
+                    .iter()
+                    .copied()
+                    .chain(constants.keys().map(|c| c.as_str()));
+                let suggestion = closest_name(&name, candidates).map(str::to_owned);
+                let error = OldTfError::UndefinedLabel(name, suggestion);
+                errors.push(files.wrap(file, error.with_span(files.name(file), l, span)));
+            }
         }
 
         let find_label = |lname| {
@@ -272,7 +385,8 @@ impl Context {
         {
             None
         } else {
-            Err(OldTfError::MissingRequiredDirective("start").with_ctx(fname, 1))?
+            errors.push(OldTfError::MissingRequiredDirective("start").with_ctx(fname, 1));
+            None
         };
 
         let assets = if let Some(c) = assets {
@@ -288,11 +402,14 @@ impl Context {
             //Err(OldTfError::MissingRequiredDirective("assets").with_ctx(fname, 1))?
         };
 
-        Ok(Self {
-            index,
-            start: [start, assets],
-            parsed_cmds,
-        })
+        (
+            Self {
+                index,
+                start: [start, assets],
+                parsed_cmds,
+            },
+            errors,
+        )
     }
 
     fn parse_value(
@@ -300,25 +417,35 @@ impl Context {
         value: Value,
         fname: &str,
         line_num: usize,
+        span: Option<&Span>,
     ) -> Result<ParsedValue> {
+        let error = |e: OldTfError| e.with_span(fname, line_num, span.cloned());
         // difference from what Tickompiler does - labels, instead of being treated as integers with
         // special metadata, are their own type, so operations don't apply to them
         match value {
             Value::Operation { op, values } => {
                 let [val1, val2] =
-                    values.map(|c| Self::parse_value(constants, *c, fname, line_num));
+                    values.map(|c| Self::parse_value(constants, *c, fname, line_num, span));
 
                 if let (ParsedValue::Integer(val1), ParsedValue::Integer(val2)) = (val1?, val2?) {
-                    Ok(ParsedValue::Integer(op.apply(val1, val2)))
+                    Ok(ParsedValue::Integer(op.apply(val1, val2).map_err(error)?))
                 } else {
-                    Err(OldTfError::InvalidOpType.with_ctx(fname, line_num))?
+                    Err(error(OldTfError::InvalidOpType))?
                 }
             }
-            Value::Negated(c) => match Self::parse_value(constants, *c, fname, line_num)? {
-                ParsedValue::Integer(c) => Ok(ParsedValue::Integer(-c)),
-                _ => Err(OldTfError::InvalidOpType.with_ctx(fname, line_num))?,
+            Value::Negated(c) => match Self::parse_value(constants, *c, fname, line_num, span)? {
+                ParsedValue::Integer(c) => {
+                    Ok(ParsedValue::Integer(eval::negate(c).map_err(error)?))
+                }
+                _ => Err(error(OldTfError::InvalidOpType))?,
             },
-            //TODO: ensure all labels used exist
+            Value::BitwiseNot(c) => {
+                match Self::parse_value(constants, *c, fname, line_num, span)? {
+                    ParsedValue::Integer(c) => Ok(ParsedValue::Integer(eval::bitwise_not(c))),
+                    _ => Err(error(OldTfError::InvalidOpType))?,
+                }
+            }
+            // labels that are used are checked to exist once the whole file is read
             Value::Constant(c) => Ok(constants
                 .get(&c)
                 .cloned()
@@ -330,8 +457,38 @@ impl Context {
     }
 }
 
+/// Finds the name most similar to `name`, if there's any that's close enough to be a typo
+fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between two strings, ignoring case since that's a common typo
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + (ca != cb) as usize);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
 struct DirectiveResult {
-    statements: Vec<(usize, Statement)>,
+    /// Statements with the file (in [`SourceFiles`]) and line they come from
+    statements: Vec<(usize, usize, Statement, StatementSpans)>,
     index: Option<i32>,
     start: Option<i32>,
     assets: Option<i32>,
@@ -339,62 +496,100 @@ struct DirectiveResult {
 }
 
 impl Context {
+    /// Reads the directives of `file`, and the statements of every file it includes in its place
     fn preprocess_directives<T: Read>(
-        statements: Vec<(usize, Statement)>,
+        statements: Vec<(usize, Statement, StatementSpans)>,
         include_fn: &impl Fn(String) -> std::io::Result<T>,
-        is_included_file: bool,
-        fname: &str,
-    ) -> Result<DirectiveResult> {
+        file: usize,
+        files: &mut SourceFiles,
+        errors: &mut Vec<Error>,
+    ) -> DirectiveResult {
         let (mut index, mut start, mut assets) = (None, None, None);
         let mut aliases = HashMap::new();
 
         let mut out_statements = vec![];
 
-        for (l, st) in statements {
+        for (l, st, spans) in statements {
             if let Statement::Directive { name, args } = st {
+                let name_error = |files: &SourceFiles, c: OldTfError| {
+                    files.wrap(file, c.with_span(files.name(file), l, spans.name.clone()))
+                };
                 // slight difference from what tickompiler does for #start/#assets vs start:/assets: but it's such an
                 // edge case no reasonable person should've ever encountered it (and if they have it's a very easy fix)
-                match name.as_ref() {
-                    "index" => index = Some(*args[0].unwrap_int()),
-                    "start" => start = Some(*args[0].unwrap_int()),
-                    "assets" => assets = Some(*args[0].unwrap_int()),
-                    "alias" => {
-                        aliases.insert(args[0].unwrap_constant().clone(), *args[1].unwrap_int());
+                match (name.as_ref(), &args[..]) {
+                    ("index" | "start" | "assets", _) if file != 0 => {
+                        errors.push(name_error(files, OldTfError::IncludedDirective))
+                    }
+                    ("index", [Value::Integer(c)]) => index = Some(*c),
+                    ("start", [Value::Integer(c)]) => start = Some(*c),
+                    ("assets", [Value::Integer(c)]) => assets = Some(*c),
+                    ("alias", [Value::Constant(alias), Value::Integer(c)]) => {
+                        aliases.insert(alias.clone(), *c);
                     }
-                    "include" if !is_included_file => {
-                        let fname = args[0].unwrap_string().0;
-                        let mut included_file = include_fn(fname.clone())?;
-                        let included_file = parse_from_text(fname, &mut included_file)?;
+                    ("include", [Value::String { value: path, .. }]) => {
+                        let span = spans.args.first().cloned();
+                        let error = |files: &SourceFiles, c: OldTfError| {
+                            files.wrap(file, c.with_span(files.name(file), l, span.clone()))
+                        };
+                        let (included, fname) = match files.include(file, l, path) {
+                            Include::New(included, fname) => (included, fname),
+                            Include::Repeated => continue,
+                            Include::Cycle(chain) => {
+                                errors.push(error(files, OldTfError::IncludeCycle(chain)));
+                                continue;
+                            }
+                        };
+                        let included_file = match include_fn(fname.clone()) {
+                            Ok(mut c) => match parse_from_text_recover(&fname, &mut c) {
+                                Ok((statements, included_errors)) => {
+                                    errors.extend(
+                                        included_errors
+                                            .into_iter()
+                                            .map(|c| files.wrap(included, c)),
+                                    );
+                                    statements
+                                }
+                                Err(e) => {
+                                    errors.push(files.wrap(included, e));
+                                    continue;
+                                }
+                            },
+                            Err(e) => {
+                                errors.push(error(files, OldTfError::IncludeFailed(fname, e)));
+                                continue;
+                            }
+                        };
                         let DirectiveResult {
                             statements: included_file,
-                            index,
-                            start,
-                            assets,
                             aliases: included_aliases,
-                        } = Self::preprocess_directives(included_file, include_fn, true, fname)?;
+                            ..
+                        } = Self::preprocess_directives(
+                            included_file,
+                            include_fn,
+                            included,
+                            files,
+                            errors,
+                        );
 
-                        let (None, None, None) = (index, start, assets) else {
-                            Err(OldTfError::IncludedDirective.with_ctx(fname, 1))?
-                        };
                         out_statements.extend(included_file);
                         aliases.extend(included_aliases);
                     }
-                    "include" if is_included_file => {
-                        Err(OldTfError::IncludedDirective.with_ctx(fname, 1))?
+                    ("index" | "start" | "assets" | "alias" | "include", _) => {
+                        errors.push(name_error(files, OldTfError::InvalidDirectiveArgs(name)))
                     }
-                    _ => unreachable!(),
+                    _ => errors.push(name_error(files, OldTfError::InvalidDirective(name))),
                 }
             } else {
-                out_statements.push((l, st));
+                out_statements.push((file, l, st, spans));
             }
         }
-        Ok(DirectiveResult {
+        DirectiveResult {
             statements: out_statements,
             index,
             start,
             assets,
             aliases,
-        })
+        }
     }
 }
 
@@ -428,3 +623,180 @@ impl From<&ParsedValue> for Value {
         value.clone().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tickflow_binaries::data::TickflowOp;
+
+    use super::*;
+
+    fn parse(text: &str) -> Result<Context> {
+        let statements = parse_from_text("test.tickflow", &mut text.as_bytes())?;
+        Context::parse_file::<TickflowOp, &[u8]>(statements, |_| unreachable!(), "test.tickflow")
+    }
+
+    #[test]
+    fn renders_semantic_errors_with_spans() {
+        let text = "#index 0x1\nstart:\nA = 1\nA = 2\n0x1<0x40000> 0\n0x2 1, strat\n";
+        let expected = "\
+error: constant \"A\" was previously defined to be 1 in line 3
+ --> test.tickflow:4:1
+  |
+4 | A = 2
+  | ^
+
+error: arg0 value 40000 is out of range (must be 18 bits at most)
+ --> test.tickflow:5:5
+  |
+5 | 0x1<0x40000> 0
+  |     ^^^^^^^
+
+error: label \"strat\" is never defined
+ --> test.tickflow:6:8
+  |
+6 | 0x2 1, strat
+  |        ^^^^^
+";
+        assert_eq!(parse(text).unwrap_err().render(text), expected);
+    }
+
+    #[test]
+    fn recovers_from_syntax_errors() {
+        let text = "#index 0x1\nstart:\n0x1 (2\n0x2 3\n#bad 1\n0x3 4 5\n";
+        let (statements, errors) =
+            parse_from_text_recover("test.tickflow", &mut text.as_bytes()).unwrap();
+        let lines: Vec<_> = errors
+            .iter()
+            .map(|c| match c {
+                Error::OldTfError { line, .. } => *line,
+                _ => panic!("{c:?}"),
+            })
+            .collect();
+        assert_eq!(lines, [3, 5, 6]);
+        let lines: Vec<_> = statements.iter().map(|c| c.0).collect();
+        assert_eq!(lines, [1, 2, 4]);
+    }
+
+    #[test]
+    fn recovers_from_semantic_errors() {
+        let text = "#index 0x1\nstart:\nA = 1\nA = 2\n0x1<0x40000>\n0x2 A\n0x3 1 + \"a\"\n0x4\n";
+        let statements = parse_from_text("test.tickflow", &mut text.as_bytes()).unwrap();
+        let (context, errors) = Context::parse_file_recover::<TickflowOp, &[u8]>(
+            statements,
+            |_| unreachable!(),
+            "test.tickflow",
+        );
+        assert!(matches!(
+            &errors[..],
+            [
+                Error::OldTfError {
+                    error: OldTfError::RedefinedConstant(..),
+                    line: 4,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::OOBArg0(0x40000),
+                    line: 5,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::InvalidOpType,
+                    line: 7,
+                    ..
+                },
+            ]
+        ));
+        let ops: Vec<_> = context
+            .parsed_cmds
+            .iter()
+            .filter_map(|c| match c {
+                ParsedStatement::Command {
+                    cmd: CommandName::Raw(op),
+                    args,
+                    ..
+                } => Some((*op, args.len())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ops, [(2, 1), (4, 0)]);
+    }
+
+    #[test]
+    fn suggests_misspelled_constants() {
+        let text = "#index 0x1\nSPEED = 2\nstart:\n0x1 SPED\n0x2 elsewhere\n";
+        let Err(Error::Multiple(errors)) = parse(text) else {
+            panic!("expected every undefined label to be reported");
+        };
+        assert!(matches!(
+            &errors[..],
+            [
+                Error::OldTfError {
+                    error: OldTfError::UndefinedLabel(name, Some(suggestion)),
+                    line: 4,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::UndefinedLabel(_, None),
+                    line: 5,
+                    ..
+                },
+            ] if name == "SPED" && suggestion == "SPEED"
+        ));
+    }
+
+    #[test]
+    fn malformed_directives() {
+        let directive = |l, name: &str, args| {
+            let name = Identifier::new_unchecked(name);
+            (
+                l,
+                Statement::Directive { name, args },
+                StatementSpans::default(),
+            )
+        };
+        let constant = Value::Constant(Identifier::new_unchecked("CONST"));
+        let statements = vec![
+            directive(1, "index", vec![Value::Integer(1)]),
+            directive(2, "alias", vec![constant.clone(), constant]),
+            directive(3, "include", vec![Value::Integer(3)]),
+            directive(4, "start", vec![]),
+            directive(5, "bad", vec![]),
+            (
+                6,
+                Statement::Label(Identifier::new_unchecked("start")),
+                StatementSpans::default(),
+            ),
+        ];
+        let (context, errors) = Context::parse_file_recover::<TickflowOp, &[u8]>(
+            statements,
+            |_| unreachable!(),
+            "test.tickflow",
+        );
+        assert_eq!(context.index, 1);
+        assert!(matches!(
+            &errors[..],
+            [
+                Error::OldTfError {
+                    error: OldTfError::InvalidDirectiveArgs(_),
+                    line: 2,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::InvalidDirectiveArgs(_),
+                    line: 3,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::InvalidDirectiveArgs(_),
+                    line: 4,
+                    ..
+                },
+                Error::OldTfError {
+                    error: OldTfError::InvalidDirective(_),
+                    line: 5,
+                    ..
+                },
+            ]
+        ));
+    }
+}
@@ -3,8 +3,8 @@ use std::io::Read;
 use lazy_static::lazy_static;
 use nom::{
     bytes::complete::tag,
-    character::complete::{digit1, hex_digit1, space0, space1},
-    combinator::{eof, opt},
+    character::complete::{digit1, hex_digit1, oct_digit1, space0, space1},
+    combinator::{consumed, eof, opt},
     error::ErrorKind as NomErrorKind,
     error::ParseError,
     multi::{many1, separated_list0},
@@ -16,106 +16,176 @@ use regex::Regex;
 use crate::better_nom_regex::{re_capture, re_find};
 use crate::{
     bin_digit1,
-    error::{nom_ok, OldTfError},
-    Result,
+    error::{nom_ok, OldTfError, Recovered, Span},
+    eval, Error, Result,
 };
 
-use super::{CommandName, Identifier, Operation, Statement, Value, IDENTIFIER_REGEX};
+use super::{
+    cst::SyntaxFile, CommandName, Identifier, Operation, Statement, StatementSpans, Value,
+    IDENTIFIER_REGEX,
+};
 
-pub fn parse_from_text(fname: &str, f: &mut impl Read) -> Result<Vec<(usize, Statement)>> {
-    let mut text = String::new();
-    f.read_to_string(&mut text)?;
+/// Where the text being parsed comes from, for error reporting
+#[derive(Debug, Clone, Copy)]
+pub struct Location<'a> {
+    pub fname: &'a str,
+    pub line_num: usize,
+    /// Whole line, which every parsed token is a slice of
+    pub line: &'a str,
+}
 
-    let mut statements = vec![];
-    for (i, line) in text.lines().enumerate() {
-        if line.trim_start().starts_with("//") || line.trim_start().is_empty() {
-            continue;
-        }
-        statements.push((
-            i + 1,
-            read_statement(
-                line.split_once("//").map(|c| c.0).unwrap_or(line).trim(),
-                fname,
-                i + 1,
-            )?,
-        ));
+impl Location<'_> {
+    /// Span of `token`, which must be a slice of the line
+    fn span(&self, token: &str) -> Option<Span> {
+        Span::new(self.line, token)
     }
+
+    /// Error about `token`, which must be a slice of the line to get a column
+    pub fn error(&self, error: OldTfError, token: &str) -> Error {
+        error.with_span(self.fname, self.line_num, Span::new(self.line, token))
+    }
+
+    /// Syntax error at the start of `remaining`, which covers the token found there
+    fn syntax_error(&self, remaining: &str) -> Error {
+        let end = remaining
+            .find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(remaining.len());
+        self.error(
+            OldTfError::SyntaxError,
+            &remaining[..end.max(1).min(remaining.len())],
+        )
+    }
+}
+
+/// Parses every line of a file, stopping at the first error.
+/// See [`parse_from_text_recover`] to get every error instead.
+pub fn parse_from_text(
+    fname: &str,
+    f: &mut impl Read,
+) -> Result<Vec<(usize, Statement, StatementSpans)>> {
+    let (statements, errors) = parse_from_text_recover(fname, f)?;
+    Error::from_many(errors)?;
     Ok(statements)
 }
 
+/// Parses every line of a file, skipping the ones with errors. Returns the statements that could
+/// be parsed along with every error, in order. Only IO errors stop parsing.
+pub fn parse_from_text_recover(
+    fname: &str,
+    f: &mut impl Read,
+) -> Result<Recovered<Vec<(usize, Statement, StatementSpans)>>> {
+    let mut text = String::new();
+    f.read_to_string(&mut text)?;
+
+    let (file, errors) = SyntaxFile::parse(fname, &text);
+    Ok((file.statements(), errors))
+}
+
 pub fn read_statement(input: &str, fname: &str, line_num: usize) -> Result<Statement> {
+    let loc = Location {
+        fname,
+        line_num,
+        line: input,
+    };
+    Ok(read_statement_at(input, loc)?.0)
+}
+
+/// Reads a statement, which is a slice of the line in `loc`, along with where its parts are
+pub fn read_statement_at(input: &str, loc: Location) -> Result<(Statement, StatementSpans)> {
     if let Ok((remaining, (_, name, _))) =
         tuple::<_, _, (), _>((tag::<_, _, ()>("#"), ident, space1))(input)
     {
-        match name.as_str() {
+        let name_token = &input[1..1 + name.len()];
+        let (args, arg_tokens) = match name.as_str() {
             "index" | "start" | "assets" => {
-                if let Ok((_, (val, _))) =
-                    tuple::<_, _, (), _>((integer(fname, line_num), eof))(remaining)
+                if let Ok((_, ((token, val), _))) =
+                    tuple::<_, _, (), _>((consumed(integer(loc)), eof))(remaining)
                 {
-                    Ok(Statement::Directive {
-                        name,
-                        args: vec![Value::Integer(val?)],
-                    })
+                    (vec![Value::Integer(val?)], vec![token])
                 } else {
-                    Err(OldTfError::SyntaxError.with_ctx(fname, line_num))
+                    Err(loc.error(OldTfError::SyntaxError, remaining))?
                 }
             }
             "alias" => {
-                match tuple::<_, _, (), _>((ident, space0, integer(fname, line_num), eof))(
+                match tuple::<_, _, (), _>((consumed(ident), space0, consumed(integer(loc)), eof))(
                     remaining,
                 ) {
-                    Ok((_, (aname, _, val, _))) => Ok(Statement::Directive {
-                        name,
-                        args: vec![Value::Constant(aname), Value::Integer(val?)],
-                    }),
-                    Err(_) => Err(OldTfError::SyntaxError.with_ctx(fname, line_num)),
+                    Ok((_, ((name_token, aname), _, (token, val), _))) => (
+                        vec![Value::Constant(aname), Value::Integer(val?)],
+                        vec![name_token, token],
+                    ),
+                    Err(_) => Err(loc.error(OldTfError::SyntaxError, remaining))?,
                 }
             }
-            "include" => Ok(Statement::Directive {
-                name,
-                args: vec![Value::String {
+            "include" => (
+                vec![Value::String {
                     value: remaining.trim().to_string(),
                     is_unicode: false,
                 }],
-            }),
-            _ => Err(OldTfError::InvalidDirective(name).with_ctx(fname, line_num))?,
-        }
+                vec![remaining.trim()],
+            ),
+            _ => Err(loc.error(OldTfError::InvalidDirective(name.clone()), name_token))?,
+        };
+        let spans = StatementSpans {
+            name: loc.span(name_token),
+            arg0: None,
+            args: arg_tokens.into_iter().filter_map(|c| loc.span(c)).collect(),
+        };
+        Ok((Statement::Directive { name, args }, spans))
     } else if let Ok((_, (name, _, _))) = tuple::<_, _, (), _>((ident, tag(":"), eof))(input) {
-        Ok(Statement::Label(name))
-    } else if let Ok((_, (name, _, _, _, value, _))) =
-        tuple::<_, _, (), _>((ident, space0, tag("="), space0, value(fname, line_num), eof))(input)
+        let spans = StatementSpans {
+            name: loc.span(&input[..name.len()]),
+            ..Default::default()
+        };
+        Ok((Statement::Label(name), spans))
+    } else if let Ok((_, (name, _, _, _, (token, value), _))) =
+        tuple::<_, _, (), _>((ident, space0, tag("="), space0, consumed(value(loc)), eof))(input)
     {
-        Ok(Statement::Constant {
-            name,
-            value: value?,
-        })
+        let spans = StatementSpans {
+            name: loc.span(&input[..name.len()]),
+            arg0: None,
+            args: loc.span(token).into_iter().collect(),
+        };
+        Ok((
+            Statement::Constant {
+                name,
+                value: value?,
+            },
+            spans,
+        ))
     } else {
-        let (_, (cmd, arg0, args, _)) = tuple::<_, _, nom::error::Error<_>, _>((
-            cmd_name(fname, line_num),
+        let (_, ((cmd_token, cmd), arg0, args, _)) = tuple::<_, _, nom::error::Error<_>, _>((
+            consumed(cmd_name(loc)),
             opt(pair(
                 space0,
-                with_matching_brackets('<', '>', value(fname, line_num)),
+                with_matching_brackets('<', '>', consumed(value(loc))),
             )),
             opt(pair(
                 space1,
-                separated_list0(tuple((space0, tag(","), space0)), value(fname, line_num)),
+                separated_list0(tuple((space0, tag(","), space0)), consumed(value(loc))),
             )),
             eof,
         ))(input)
-        .map_err(|c| {
-            println!("{c}"); // TODO: ?
-            OldTfError::SyntaxError.with_ctx(fname, line_num)
+        .map_err(|c| match c {
+            nom::Err::Error(c) | nom::Err::Failure(c) => loc.syntax_error(c.input),
+            nom::Err::Incomplete(_) => loc.syntax_error(input),
         })?;
         let args = args.map(|c| c.1).unwrap_or(vec![]);
-        Ok(Statement::Command {
+        let spans = StatementSpans {
+            name: loc.span(cmd_token),
+            arg0: arg0.as_ref().and_then(|(_, (c, _))| loc.span(c)),
+            args: args.iter().filter_map(|(c, _)| loc.span(c)).collect(),
+        };
+        let statement = Statement::Command {
             cmd: cmd?,
             arg0: match arg0 {
-                Some((_, Err(e))) => Err(e)?,
-                Some((_, Ok(c))) => Some(c),
+                Some((_, (_, Err(e)))) => Err(e)?,
+                Some((_, (_, Ok(c)))) => Some(c),
                 None => None,
             },
-            args: args.into_iter().collect::<Result<Vec<_>>>()?,
-        })
+            args: args.into_iter().map(|c| c.1).collect::<Result<Vec<_>>>()?,
+        };
+        Ok((statement, spans))
     }
 }
 
@@ -132,52 +202,47 @@ lazy_static! {
     static ref OP_REGEX: Regex = Regex::new(r"^[+\-*/&|^]|>>|<<").unwrap();
 }
 
-const OP_PRIORITY: &[&[Operation]] = &[
-    &[Operation::Mul, Operation::Div],
-    &[Operation::Add, Operation::Sub],
-    &[Operation::Shl, Operation::Shr],
-    &[Operation::And, Operation::Or, Operation::Xor],
-];
-
+/// Reads the digits of an integer literal, which starts at `input` and ends at `remaining`
 pub fn int_ok<'a, E: ParseError<&'a str>>(
     val: &str,
     radix: u32,
+    input: &str,
     remaining: &'a str,
-    fname: &str,
-    line_num: usize,
+    loc: Location,
 ) -> (&'a str, Result<i32>) {
     (
         remaining,
         crate::read_anysign_int(val, radix)
-            .map_err(|c| <_ as Into<OldTfError>>::into(c).with_ctx(fname, line_num)),
+            .map_err(|c| loc.error(c.into(), &input[..input.len() - remaining.len()])),
     )
 }
 
-pub fn integer<'a, E: nom::error::ParseError<&'a str>>(
-    fname: &str,
-    line_num: usize,
-) -> impl Fn(&'a str) -> IResult<&str, Result<i32>, E> + '_ {
+pub fn integer<'a, 'l, E: nom::error::ParseError<&'a str>>(
+    loc: Location<'l>,
+) -> impl Fn(&'a str) -> IResult<&'a str, Result<i32>, E> + 'l {
     move |input| {
         let (remaining, val) = digit1::<_, E>(input)?;
         Ok(
             if let Ok((remaining, (_, val))) = tuple((tag("0x"), hex_digit1::<_, E>))(input) {
-                int_ok::<E>(val, 16, remaining, fname, line_num)
+                int_ok::<E>(val, 16, input, remaining, loc)
             } else if let Ok((remaining, (_, val))) = tuple((tag("0b"), bin_digit1::<_, E>))(input)
             {
-                int_ok::<E>(val, 2, remaining, fname, line_num)
+                int_ok::<E>(val, 2, input, remaining, loc)
+            } else if let Ok((remaining, (_, val))) = tuple((tag("0o"), oct_digit1::<_, E>))(input)
+            {
+                int_ok::<E>(val, 8, input, remaining, loc)
             } else {
-                int_ok::<E>(val, 10, remaining, fname, line_num)
+                int_ok::<E>(val, 10, input, remaining, loc)
             },
         )
     }
 }
 
-pub fn cmd_name<'a, E: nom::error::ParseError<&'a str>>(
-    fname: &str,
-    line_num: usize,
-) -> impl Fn(&'a str) -> IResult<&str, Result<CommandName>, E> + '_ {
+pub fn cmd_name<'a, 'l, E: nom::error::ParseError<&'a str>>(
+    loc: Location<'l>,
+) -> impl Fn(&'a str) -> IResult<&'a str, Result<CommandName>, E> + 'l {
     move |input| {
-        if let Ok((remaining, val)) = integer::<E>(fname, line_num)(input) {
+        if let Ok((remaining, val)) = integer::<E>(loc)(input) {
             Ok((remaining, val.map(CommandName::Raw)))
         } else {
             let (remaining, val) = ident(input)?;
@@ -186,18 +251,17 @@ pub fn cmd_name<'a, E: nom::error::ParseError<&'a str>>(
     }
 }
 
-pub fn value<'a, E: nom::error::ParseError<&'a str>>(
-    fname: &str,
-    line_num: usize,
-) -> impl Fn(&'a str) -> IResult<&str, Result<Value>, E> + '_ {
+pub fn value<'a, 'l, E: nom::error::ParseError<&'a str>>(
+    loc: Location<'l>,
+) -> impl Fn(&'a str) -> IResult<&'a str, Result<Value>, E> + 'l {
     move |input| {
         if let Ok((remaining, (val1, pairs))) = tuple::<_, _, E, _>((
-            value_no_ops(fname, line_num),
+            value_no_ops(loc),
             many1(tuple((
                 space0,
                 re_find(OP_REGEX.clone()),
                 space0,
-                value_no_ops(fname, line_num),
+                value_no_ops(loc),
             ))),
         ))(input)
         {
@@ -227,30 +291,20 @@ pub fn value<'a, E: nom::error::ParseError<&'a str>>(
                 values.push(val);
                 ops.push(op);
             }
-            for op_type in OP_PRIORITY {
-                while let Some((i, op)) = ops.iter().enumerate().find(|(_, c)| op_type.contains(c))
-                {
-                    let val2 = values.remove(i + 1);
-                    let val1 = &mut values[i];
-                    *val1 = Value::Operation {
-                        op: *op,
-                        values: [Box::new(val1.clone()), Box::new(val2)],
-                    };
-                    ops.remove(i);
-                }
-            }
-            assert!(values.len() == 1 && ops.is_empty());
-            Ok((remaining, Ok(values.remove(0))))
+            let value = eval::fold_by_precedence(values, ops, |op, val1, val2| Value::Operation {
+                op,
+                values: [Box::new(val1), Box::new(val2)],
+            });
+            Ok((remaining, Ok(value)))
         } else {
-            value_no_ops(fname, line_num)(input)
+            value_no_ops(loc)(input)
         }
     }
 }
 
-pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
-    fname: &str,
-    line_num: usize,
-) -> impl Fn(&'a str) -> IResult<&str, Result<Value>, E> + '_ {
+pub fn value_no_ops<'a, 'l, E: nom::error::ParseError<&'a str>>(
+    loc: Location<'l>,
+) -> impl Fn(&'a str) -> IResult<&'a str, Result<Value>, E> + 'l {
     move |input| {
         let out: Value;
         let rem: &str;
@@ -258,7 +312,7 @@ pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
         if let Ok((remaining, (_, val, _, _))) = with_matching_brackets(
             '(',
             ')',
-            tuple::<_, _, E, _>((space0, value(fname, line_num), space0, eof)),
+            tuple::<_, _, E, _>((space0, value(loc), space0, eof)),
         )(input)
         {
             let val = match val {
@@ -268,7 +322,7 @@ pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
             rem = remaining;
             out = val;
         } else if let Ok((remaining, (_, _, val))) =
-            tuple::<_, _, E, _>((tag("-"), space0, value(fname, line_num)))(input)
+            tuple::<_, _, E, _>((tag("-"), space0, value_no_ops(loc)))(input)
         {
             let val = match val {
                 Ok(c) => c,
@@ -276,6 +330,15 @@ pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
             };
             rem = remaining;
             out = Value::Negated(Box::new(val));
+        } else if let Ok((remaining, (_, _, val))) =
+            tuple::<_, _, E, _>((tag("~"), space0, value_no_ops(loc)))(input)
+        {
+            let val = match val {
+                Ok(c) => c,
+                Err(e) => return Ok((input, Err(e))),
+            };
+            rem = remaining;
+            out = Value::BitwiseNot(Box::new(val));
         } else if let Ok((remaining, value)) = re_capture::<E>(STRING_REGEX.clone())(input) {
             let mut is_unicode = false;
 
@@ -283,8 +346,8 @@ pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
                 "u" => is_unicode = true,
                 "" => {}
                 c => {
-                    return OldTfError::InvalidStrPrefix(c.to_string())
-                        .with_ctx(fname, line_num)
+                    return loc
+                        .error(OldTfError::InvalidStrPrefix(c.to_string()), c)
                         .wrap_nom(remaining);
                 }
             }
@@ -293,7 +356,7 @@ pub fn value_no_ops<'a, E: nom::error::ParseError<&'a str>>(
                 is_unicode,
             };
             rem = remaining;
-        } else if let Ok((remaining, val)) = integer::<E>(fname, line_num)(input) {
+        } else if let Ok((remaining, val)) = integer::<E>(loc)(input) {
             if let Ok(c) = val {
                 rem = remaining;
                 out = Value::Integer(c);
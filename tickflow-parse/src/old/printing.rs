@@ -54,7 +54,7 @@ impl Display for super::CommandName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Raw(c) => {
-                if c.abs() > 9 {
+                if c.unsigned_abs() > 9 {
                     write!(f, "0x{c:x}")
                 } else {
                     write!(f, "{c}")
@@ -68,17 +68,32 @@ impl Display for super::CommandName {
 impl Display for super::Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Operation { op, values } => write!(f, "{} {op} {}", values[0], values[1]),
+            Self::Operation { op, values } => {
+                // operations are left to right, so the right side needs parentheses even if it
+                // has the same precedence
+                let left = values[0].precedence().is_some_and(|c| c < op.precedence());
+                let right = values[1].precedence().is_some_and(|c| c <= op.precedence());
+                write_operand(f, &values[0], left)?;
+                write!(f, " {op} ")?;
+                write_operand(f, &values[1], right)
+            }
             Self::String { value, is_unicode } => write!(
                 f,
                 "{}\"{}\"",
                 if *is_unicode { "u" } else { "" },
                 crate::create_escapes(value)
             ),
-            Self::Negated(c) => write!(f, "-{}", c),
+            Self::Negated(c) => {
+                write!(f, "-")?;
+                write_operand(f, c, c.precedence().is_some())
+            }
+            Self::BitwiseNot(c) => {
+                write!(f, "~")?;
+                write_operand(f, c, c.precedence().is_some())
+            }
             //TODO: what should be the threshold?
             Self::Integer(c) => {
-                if c.abs() >= 0xA {
+                if c.unsigned_abs() >= 0xA {
                     write!(f, "0x{c:x}")
                 } else {
                     write!(f, "{c}")
@@ -89,6 +104,28 @@ impl Display for super::Value {
     }
 }
 
+impl super::Value {
+    /// Precedence of the operation at the top of the value, if it is one
+    fn precedence(&self) -> Option<u8> {
+        match self {
+            Self::Operation { op, .. } => Some(op.precedence()),
+            _ => None,
+        }
+    }
+}
+
+fn write_operand(
+    f: &mut std::fmt::Formatter<'_>,
+    value: &super::Value,
+    parenthesize: bool,
+) -> std::fmt::Result {
+    if parenthesize {
+        write!(f, "({value})")
+    } else {
+        write!(f, "{value}")
+    }
+}
+
 impl Display for super::Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -114,3 +151,62 @@ impl Display for super::Identifier {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parsing::read_statement, Statement, Value};
+    use crate::eval;
+
+    fn parse(expr: &str) -> Value {
+        match read_statement(&format!("c = {expr}"), "test.tickflow", 1).unwrap() {
+            Statement::Constant { value, .. } => value,
+            c => panic!("not a constant: {c:?}"),
+        }
+    }
+
+    fn evaluate(value: &Value) -> i32 {
+        match value {
+            Value::Integer(c) => *c,
+            Value::Operation { op, values } => op
+                .apply(evaluate(&values[0]), evaluate(&values[1]))
+                .unwrap(),
+            Value::Negated(c) => eval::negate(evaluate(c)).unwrap(),
+            Value::BitwiseNot(c) => eval::bitwise_not(evaluate(c)),
+            c => panic!("not an integer expression: {c:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_values() {
+        for expr in [
+            "-(1 + 2)",
+            "(1 + 2) * 3",
+            "~(1 | 2)",
+            "10 - (4 - 3)",
+            "10 - 4 - 3",
+            "64 / (4 / 2)",
+            "(1 << 2) << 3",
+            "1 << (2 + 3)",
+            "(1 | 2) & 3",
+            "1 | 2 & 3",
+            "-(-5)",
+            "~-(3 * 4) + 1",
+            "0x80000000",
+            "-0x7FFFFFFF - 1",
+        ] {
+            let value = parse(expr);
+            let printed = value.to_string();
+            assert_eq!(
+                evaluate(&parse(&printed)),
+                evaluate(&value),
+                "{expr} was printed as {printed}"
+            );
+        }
+    }
+
+    #[test]
+    fn minimum_integer() {
+        assert_eq!(Value::Integer(i32::MIN).to_string(), "0x80000000");
+        assert_eq!(Value::Integer(-3).to_string(), "-3");
+    }
+}
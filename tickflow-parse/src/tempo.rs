@@ -0,0 +1,256 @@
+//! Tickompiler's `.tempo` file format
+//!
+//! The first line has the IDs (in hex) of every tempo that uses the file, separated by spaces,
+//! optionally followed by `streamed` or `sequence` to set the kind of audio they're used for.
+//! Every other line is a tempo value: its length in beats, its length in seconds, and optionally
+//! a loop value (in hex, defaults to 0).
+
+use std::io::{Read, Write};
+
+use tickflow_binaries::data::btks::{Tempo, TempoStream, TempoVal};
+
+use crate::{error::TempoError, Result};
+
+pub use tickflow_binaries::data::btks::DEFAULT_SAMPLE_RATE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoFile {
+    pub ids: Vec<u32>,
+    pub data: Vec<TempoVal>,
+    pub sample_rate: u32,
+    /// Kind of audio the tempos are used for. If `None`, it's guessed from each ID.
+    pub stream: Option<TempoStream>,
+}
+
+impl TempoFile {
+    pub fn from_text(fname: &str, f: &mut impl Read, sample_rate: u32) -> Result<Self> {
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+
+        let mut header = None;
+        let mut data = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split_once("//").map(|c| c.0).unwrap_or(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if header.is_none() {
+                header = Some(read_header(line).map_err(|e| e.with_ctx(fname, i + 1))?);
+                continue;
+            }
+
+            data.push(read_value(line, sample_rate).map_err(|e| e.with_ctx(fname, i + 1))?);
+        }
+
+        let Some((ids, stream)) = header else {
+            Err(TempoError::MissingIds.with_ctx(fname, 1))?
+        };
+        Ok(Self {
+            ids,
+            data,
+            sample_rate,
+            stream,
+        })
+    }
+
+    pub fn to_text(&self, f: &mut impl Write) -> Result<()> {
+        writeln!(f, "{}", self.header())?;
+        for value in &self.data {
+            writeln!(f, "{}", self.value_to_text(value))?;
+        }
+        Ok(())
+    }
+
+    /// First line of the file, with the IDs and the kind of audio
+    pub(crate) fn header(&self) -> String {
+        let mut header = self
+            .ids
+            .iter()
+            .map(|c| format!("{c:08X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        match self.stream {
+            Some(TempoStream::Streamed) => header += " streamed",
+            Some(TempoStream::Sequence) => header += " sequence",
+            None => {}
+        }
+        header
+    }
+
+    pub(crate) fn value_to_text(&self, value: &TempoVal) -> String {
+        let seconds = value.time as f64 / self.sample_rate as f64;
+        if value.loop_val == 0 {
+            format!("{} {seconds}", value.beats)
+        } else {
+            format!("{} {seconds} {:X}", value.beats, value.loop_val)
+        }
+    }
+
+    /// Creates one [`Tempo`] for each ID in the file
+    pub fn to_tempos(&self) -> Vec<Tempo> {
+        self.ids
+            .iter()
+            .map(|c| Tempo {
+                id: *c,
+                data: self.data.clone(),
+                sample_rate: self.sample_rate,
+                stream: self.stream.unwrap_or_else(|| TempoStream::guess(*c)),
+            })
+            .collect()
+    }
+
+    /// Groups tempos with the same data into a single file each
+    pub fn from_tempos(tempos: &[Tempo]) -> Vec<Self> {
+        let mut files: Vec<(Self, TempoStream)> = vec![];
+        for tempo in tempos {
+            match files.iter_mut().find(|(c, stream)| {
+                c.data == tempo.data
+                    && c.sample_rate == tempo.sample_rate
+                    && *stream == tempo.stream
+            }) {
+                Some((c, _)) => c.ids.push(tempo.id),
+                None => files.push((
+                    Self {
+                        ids: vec![tempo.id],
+                        data: tempo.data.clone(),
+                        sample_rate: tempo.sample_rate,
+                        stream: None,
+                    },
+                    tempo.stream,
+                )),
+            }
+        }
+        files
+            .into_iter()
+            .map(|(mut file, stream)| {
+                // only specify the kind of audio if it can't be guessed
+                if file.ids.iter().any(|c| TempoStream::guess(*c) != stream) {
+                    file.stream = Some(stream);
+                }
+                file
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn read_header(
+    line: &str,
+) -> std::result::Result<(Vec<u32>, Option<TempoStream>), TempoError> {
+    let mut ids = vec![];
+    let mut stream = None;
+    for word in line.split_whitespace() {
+        if stream.is_some() {
+            Err(TempoError::SyntaxError)?
+        }
+        match word {
+            "streamed" => stream = Some(TempoStream::Streamed),
+            "sequence" => stream = Some(TempoStream::Sequence),
+            _ => ids.push(
+                u32::from_str_radix(word.trim_start_matches("0x"), 16)
+                    .map_err(|_| TempoError::InvalidId(word.to_string()))?,
+            ),
+        }
+    }
+    if ids.is_empty() {
+        Err(TempoError::MissingIds)?
+    }
+    Ok((ids, stream))
+}
+
+pub(crate) fn read_value(
+    line: &str,
+    sample_rate: u32,
+) -> std::result::Result<TempoVal, TempoError> {
+    let values: Vec<_> = line.split_whitespace().collect();
+    let (beats, seconds, loop_val) = match values[..] {
+        [beats, seconds] => (beats, seconds, None),
+        [beats, seconds, loop_val] => (beats, seconds, Some(loop_val)),
+        _ => Err(TempoError::SyntaxError)?,
+    };
+
+    let beats: f32 = beats
+        .parse()
+        .map_err(|_| TempoError::InvalidNumber(beats.to_string()))?;
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|_| TempoError::InvalidNumber(seconds.to_string()))?;
+    if !beats.is_finite() || beats < 0.0 || !seconds.is_finite() || seconds < 0.0 {
+        Err(TempoError::NegativeLength)?
+    }
+    let loop_val = match loop_val {
+        Some(c) => u32::from_str_radix(c.trim_start_matches("0x"), 16)
+            .map_err(|_| TempoError::InvalidNumber(c.to_string()))?,
+        None => 0,
+    };
+
+    Ok(TempoVal {
+        beats,
+        time: (seconds * sample_rate as f64).round() as u32,
+        loop_val,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let text = "0000000A 0000001B\n1 0.5\n2.5 1.25 // comment\n\n4 2 1\n";
+        let file = TempoFile::from_text("test", &mut text.as_bytes(), DEFAULT_SAMPLE_RATE).unwrap();
+        assert_eq!(file.ids, [0xA, 0x1B]);
+        assert_eq!(file.stream, None);
+        assert_eq!(
+            file.data[1],
+            TempoVal {
+                beats: 2.5,
+                time: DEFAULT_SAMPLE_RATE * 5 / 4,
+                loop_val: 0,
+            }
+        );
+        assert_eq!(file.data[2].loop_val, 1);
+
+        let mut written = vec![];
+        file.to_text(&mut written).unwrap();
+        assert_eq!(
+            String::from_utf8(written.clone()).unwrap(),
+            "0000000A 0000001B\n1 0.5\n2.5 1.25\n4 2 1\n"
+        );
+        let read =
+            TempoFile::from_text("test", &mut written.as_slice(), DEFAULT_SAMPLE_RATE).unwrap();
+        assert_eq!(read, file);
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let data = vec![TempoVal {
+            beats: 1.0,
+            time: 16000,
+            loop_val: 0,
+        }];
+        let tempo = |id, stream| Tempo {
+            id,
+            data: data.clone(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            stream,
+        };
+        // low IDs are guessed to be streamed
+        let tempos = [
+            tempo(1, TempoStream::Streamed),
+            tempo(2, TempoStream::Sequence),
+            tempo(3, TempoStream::Sequence),
+        ];
+
+        let files = TempoFile::from_tempos(&tempos);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].stream, None);
+        assert_eq!(files[1].stream, Some(TempoStream::Sequence));
+
+        let mut text = vec![];
+        files[1].to_text(&mut text).unwrap();
+        let read = TempoFile::from_text("test", &mut text.as_slice(), DEFAULT_SAMPLE_RATE).unwrap();
+        assert_eq!(read.to_tempos(), tempos[1..]);
+        assert_eq!(files[0].to_tempos(), tempos[..1]);
+    }
+}